@@ -1,7 +1,29 @@
-use std::{error::Error, fs::read_dir};
+use std::{env, error::Error, fs::read_dir, path::Path, process::Command};
 
 use poem_grpc_build::Config;
 
+/// Compiles every `.proto` file in `./proto` into Rust code in `OUT_DIR`.
+///
+/// Besides the generated services and messages, a descriptor set of all
+/// compiled files is written to `OUT_DIR/file_descriptor_set.bin`. Include it
+/// with `gear_microkit::file_descriptor_set!()` and pass it to
+/// `GrpcServer::with_reflection` to serve server reflection for them:
+///
+/// ```rust,ignore
+/// let server = gear_microkit::GrpcServer::new()
+///     .with_reflection(gear_microkit::file_descriptor_set!())
+///     .add_service(services::hello::new());
+/// ```
+///
+/// The same descriptor set passed to `GrpcServer::with_http_transcoding`
+/// serves RPCs annotated with `google.api.http` as REST endpoints. Only the
+/// top level of `./proto` is compiled, so `google/api/annotations.proto` and
+/// `google/api/http.proto` can be vendored in `./proto/google/api` to be
+/// imported.
+///
+/// The git commit and compiler version are exposed to the crate being built
+/// as `GEAR_GIT_SHA` and `GEAR_RUSTC_VERSION`, which
+/// `gear_microkit::build_info!()` picks up.
 pub fn build() -> Result<(), Box<dyn Error>> {
     let mut protos = Vec::new();
 
@@ -24,7 +46,42 @@ pub fn build() -> Result<(), Box<dyn Error>> {
         .message_attribute(".", "#[serde(default)]")
         .client_middleware("gear_microkit::middlewares::AddClientHeaders")
         .client_middleware("gear_microkit::middlewares::ClientTracing")
+        .client_middleware("gear_microkit::middlewares::PropagateDeadline")
         .protoc_arg("--experimental_allow_proto3_optional")
         .compile(&protos, &["./proto"])?;
+    emit_build_env();
     Ok(())
 }
+
+/// Sets the environment variables read by `gear_microkit::build_info!()`.
+fn emit_build_env() {
+    if let Some(sha) = command_output("git", &["rev-parse", "HEAD"]) {
+        println!("cargo:rustc-env=GEAR_GIT_SHA={sha}");
+        // Rerun when the checked out commit changes. Declaring any file opts
+        // out of rerunning on every change in the package, so the protos are
+        // declared as well.
+        println!("cargo:rerun-if-changed=proto");
+        if let Some(git_dir) = command_output("git", &["rev-parse", "--git-dir"]) {
+            println!("cargo:rerun-if-changed={git_dir}/HEAD");
+            if let Some(head_ref) = command_output("git", &["symbolic-ref", "-q", "HEAD"]) {
+                let ref_path = format!("{git_dir}/{head_ref}");
+                if Path::new(&ref_path).exists() {
+                    println!("cargo:rerun-if-changed={ref_path}");
+                }
+            }
+        }
+    }
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    if let Some(version) = command_output(&rustc, &["--version"]) {
+        println!("cargo:rustc-env=GEAR_RUSTC_VERSION={version}");
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_string()).filter(|output| !output.is_empty())
+}
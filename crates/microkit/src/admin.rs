@@ -0,0 +1,218 @@
+use std::{io, sync::Arc, time::Duration};
+
+use poem::{
+    get, handler,
+    http::StatusCode,
+    listener::TcpAcceptor,
+    web::{Data, Json, WithContentType},
+    EndpointExt, IntoResponse, Response, Route, Server,
+};
+use prometheus::{core::Collector, Encoder, IntGaugeVec, Opts, TextEncoder};
+use serde_json::Value;
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{debug::DebugInfo, health::Health, BuildInfo};
+
+/// The HTTP listener for operational endpoints, bound to a separate port so it
+/// is never exposed through the same load balancer as the gRPC traffic.
+///
+/// | Path | Purpose |
+/// |---|---|
+/// | `/metrics` | Prometheus text exposition of the default registry |
+/// | `/livez` | Liveness probe, `200 OK` while the process is running |
+/// | `/readyz` | Readiness probe, `503 Service Unavailable` while a readiness check fails or the server is shutting down |
+/// | `/debug/services` | The registered services and, where a descriptor set describes them, their methods |
+/// | `/debug/config` | The effective configuration, with credentials redacted |
+/// | `/debug/build` | Application name and version, git commit, compiler and gear versions |
+/// | `/debug/middleware` | The effective middleware stack, outermost first |
+pub(crate) struct AdminServer {
+    acceptor: TcpAcceptor,
+}
+
+/// A running [`AdminServer`].
+pub(crate) struct AdminHandle {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<io::Result<()>>,
+}
+
+impl AdminServer {
+    /// Binds the admin listener.
+    ///
+    /// Binding happens eagerly so that an unavailable port fails startup
+    /// instead of being reported from a background task.
+    pub(crate) async fn bind(address: &str) -> io::Result<Self> {
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        Ok(Self {
+            acceptor: TcpAcceptor::from_tokio(listener)?,
+        })
+    }
+
+    /// Registers the process-level collectors and starts serving in the
+    /// background.
+    pub(crate) fn spawn(
+        self,
+        build_info: BuildInfo,
+        health: Arc<Health>,
+        debug: Arc<DebugInfo>,
+    ) -> AdminHandle {
+        register_default_collectors(build_info);
+
+        let app = Route::new()
+            .at("/metrics", get(metrics))
+            .at("/livez", get(livez))
+            .at("/readyz", get(readyz))
+            .at("/debug/services", get(debug_services))
+            .at("/debug/config", get(debug_config))
+            .at("/debug/build", get(debug_build))
+            .at("/debug/middleware", get(debug_middleware))
+            .data(health)
+            .data(debug);
+        let (stop, stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(
+            Server::new_with_acceptor(self.acceptor)
+                .name("admin")
+                .run_with_graceful_shutdown(
+                    app,
+                    async move {
+                        let _ = stopped.await;
+                    },
+                    Some(Duration::from_secs(1)),
+                ),
+        );
+        AdminHandle { stop, task }
+    }
+}
+
+impl AdminHandle {
+    /// Stops the admin listener and waits for it to exit.
+    ///
+    /// Called after the gRPC server has finished draining, so that metrics
+    /// remain scrapeable for the whole shutdown sequence.
+    pub(crate) async fn shutdown(self) -> io::Result<()> {
+        let _ = self.stop.send(());
+        self.task.await.map_err(io::Error::other)?
+    }
+}
+
+#[handler]
+fn metrics() -> poem::Result<WithContentType<Vec<u8>>> {
+    let encoder = TextEncoder::new();
+    let mut buf = Vec::new();
+    encoder
+        .encode(&prometheus::gather(), &mut buf)
+        .map_err(|err| poem::Error::new(err, StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(buf.with_content_type(encoder.format_type().to_string()))
+}
+
+#[handler]
+fn livez() -> &'static str {
+    "ok"
+}
+
+#[handler]
+async fn readyz(health: Data<&Arc<Health>>) -> Response {
+    let report = health.readiness().await;
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Json(report).with_status(status).into_response()
+}
+
+#[handler]
+fn debug_services(debug: Data<&Arc<DebugInfo>>) -> Json<Value> {
+    Json(debug.services.clone())
+}
+
+#[handler]
+fn debug_config(debug: Data<&Arc<DebugInfo>>) -> Json<Value> {
+    Json(debug.config.clone())
+}
+
+#[handler]
+fn debug_build(debug: Data<&Arc<DebugInfo>>) -> Json<Value> {
+    Json(debug.build.clone())
+}
+
+#[handler]
+fn debug_middleware(debug: Data<&Arc<DebugInfo>>) -> Json<Value> {
+    Json(debug.middleware.clone())
+}
+
+fn register_default_collectors(build_info: BuildInfo) {
+    let build_info_gauge = IntGaugeVec::new(
+        Opts::new(
+            "micro_build_info",
+            "build information of the running service, always 1",
+        ),
+        &["name", "version", "gear_version"],
+    )
+    .expect("valid build info metric");
+    build_info_gauge
+        .with_label_values(&[
+            build_info.name,
+            build_info.version,
+            env!("CARGO_PKG_VERSION"),
+        ])
+        .set(1);
+    register_once(build_info_gauge);
+
+    #[cfg(target_os = "linux")]
+    register_once(prometheus::process_collector::ProcessCollector::for_self());
+}
+
+/// Registers `collector` in the default registry, ignoring collectors that
+/// were already registered by a previous server in the same process.
+pub(crate) fn register_once(collector: impl Collector + 'static) {
+    match prometheus::register(Box::new(collector)) {
+        Ok(()) | Err(prometheus::Error::AlreadyReg) => {}
+        Err(err) => tracing::warn!(error = %err, "failed to register metrics collector"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future;
+
+    use poem::{Endpoint, Request};
+
+    use super::*;
+    use crate::health::ReadinessCheck;
+
+    async fn readyz_with(checks: Vec<ReadinessCheck>) -> (StatusCode, Value) {
+        let (_, reporter) = poem_grpc::health_service();
+        let health = Health::new(reporter, Vec::new(), checks, Duration::from_millis(20));
+        health.set_serving();
+        let ep = Route::new()
+            .at("/readyz", get(readyz))
+            .data(Arc::new(health));
+        let resp = ep
+            .call(Request::builder().uri_str("/readyz").finish())
+            .await
+            .unwrap();
+        let status = resp.status();
+        (status, resp.into_body().into_json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn reports_not_ready_while_a_check_hangs() {
+        let (status, report) = readyz_with(vec![ReadinessCheck::new(
+            "database".to_string(),
+            future::pending::<Result<(), String>>,
+        )])
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "ready": false,
+                "checks": {"database": "timed out after 20ms"},
+            })
+        );
+
+        let (status, report) = readyz_with(Vec::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report, serde_json::json!({"ready": true}));
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::Bytes;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use http_body_util::combinators::BoxBody;
+use poem::{Body, Response};
+use poem_grpc::Code;
+
+use crate::status::grpc_status;
+
+type OnEnd = Box<dyn FnOnce(Option<Code>) + Send + Sync>;
+
+/// Calls `on_end` with the gRPC status of `resp` once the call has ended.
+///
+/// For trailers-only responses, which carry the status in their headers,
+/// `on_end` is called right away. Otherwise the body is wrapped so that
+/// `on_end` is called when the trailers are sent, or with `None` if the body
+/// ends without a status or is dropped before it completes, e.g. because the
+/// client cancelled the call.
+pub(crate) fn on_grpc_status<F>(resp: Response, on_end: F) -> Response
+where
+    F: FnOnce(Option<Code>) + Send + Sync + 'static,
+{
+    if let Some(code) = grpc_status(resp.headers()) {
+        on_end(Some(code));
+        return resp;
+    }
+    let (parts, body) = resp.into_parts();
+    let body = StatusBody {
+        inner: body.into(),
+        on_end: Some(Box::new(on_end)),
+    };
+    Response::from_parts(parts, Body::from(BoxBody::new(body)))
+}
+
+struct StatusBody {
+    inner: BoxBody<Bytes, io::Error>,
+    on_end: Option<OnEnd>,
+}
+
+impl StatusBody {
+    fn end(&mut self, code: Option<Code>) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(code);
+        }
+    }
+}
+
+impl HttpBody for StatusBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(trailers) = frame.trailers_ref() {
+                    this.end(grpc_status(trailers));
+                }
+            }
+            Some(Err(_)) | None => this.end(None),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for StatusBody {
+    fn drop(&mut self) {
+        self.end(None);
+    }
+}
+
+/// Finds the length prefixes of the messages of a gRPC body as its data
+/// arrives, in chunks of any size.
+#[derive(Default)]
+pub(crate) struct MessageFramer {
+    /// The length prefix being read.
+    prefix: [u8; 5],
+    prefix_len: usize,
+    /// The bytes left of the current message.
+    remaining: usize,
+}
+
+impl MessageFramer {
+    /// Returns the offset in `data` and the length of the next message whose
+    /// length prefix ends in `data[*pos..]`, advancing `pos` past the prefix.
+    ///
+    /// A prefix split across chunks is reported at offset 0 of the chunk
+    /// completing it.
+    pub(crate) fn next_message(&mut self, data: &[u8], pos: &mut usize) -> Option<(usize, usize)> {
+        while *pos < data.len() {
+            if self.remaining > 0 {
+                let n = self.remaining.min(data.len() - *pos);
+                self.remaining -= n;
+                *pos += n;
+                continue;
+            }
+            let start = *pos;
+            let n = (5 - self.prefix_len).min(data.len() - start);
+            self.prefix[self.prefix_len..self.prefix_len + n]
+                .copy_from_slice(&data[start..start + n]);
+            self.prefix_len += n;
+            *pos += n;
+            if self.prefix_len == 5 {
+                self.prefix_len = 0;
+                let [_, len @ ..] = self.prefix;
+                let len = u32::from_be_bytes(len) as usize;
+                self.remaining = len;
+                return Some((start, len));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(len: usize) -> Vec<u8> {
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(len as u32).to_be_bytes());
+        frame.resize(5 + len, b'x');
+        frame
+    }
+
+    /// Feeds `chunks` to a framer, returning the index of the chunk, offset
+    /// and length of every message found.
+    fn messages(chunks: &[&[u8]]) -> Vec<(usize, usize, usize)> {
+        let mut framer = MessageFramer::default();
+        let mut found = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut pos = 0;
+            while let Some((offset, len)) = framer.next_message(chunk, &mut pos) {
+                found.push((i, offset, len));
+            }
+            assert_eq!(pos, chunk.len());
+        }
+        found
+    }
+
+    #[test]
+    fn finds_messages_in_one_chunk() {
+        let data = [frame(3), frame(0), frame(300)].concat();
+        assert_eq!(messages(&[&data]), [(0, 0, 3), (0, 8, 0), (0, 13, 300)]);
+    }
+
+    #[test]
+    fn finds_messages_split_at_any_byte() {
+        let data = [frame(3), frame(0), frame(70_000)].concat();
+        for i in 0..=data.len() {
+            let found = messages(&[&data[..i], &data[i..]])
+                .into_iter()
+                .map(|(_, _, len)| len)
+                .collect::<Vec<_>>();
+            assert_eq!(found, [3, 0, 70_000], "split at {i}");
+        }
+    }
+
+    #[test]
+    fn reports_split_prefixes_at_the_start_of_the_completing_chunk() {
+        let data = [frame(3), frame(2)].concat();
+        assert_eq!(
+            messages(&[&data[..10], &data[10..]]),
+            [(0, 0, 3), (1, 0, 2)]
+        );
+        // One byte at a time.
+        let chunks = data.chunks(1).collect::<Vec<_>>();
+        assert_eq!(messages(&chunks), [(4, 0, 3), (12, 0, 2)]);
+    }
+
+    #[test]
+    fn skips_message_bytes_spanning_chunks() {
+        let data = [frame(10), frame(1)].concat();
+        assert_eq!(
+            messages(&[&data[..7], &data[7..12], &data[12..]]),
+            [(0, 0, 10), (2, 3, 1)]
+        );
+    }
+}
@@ -0,0 +1,53 @@
+/// Identifies the application binary a [`GrpcServer`](crate::GrpcServer) is
+/// running in.
+///
+/// Exported on the admin listener as the `micro_build_info` Prometheus gauge.
+/// Use the [`build_info!`](crate::build_info!) macro to capture the values of
+/// the calling crate at compile time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// The package name of the application.
+    pub name: &'static str,
+    /// The package version of the application.
+    pub version: &'static str,
+    /// The git commit the application was built from, if known.
+    pub git_sha: Option<&'static str>,
+    /// The version of the compiler that built the application, if known.
+    pub rustc_version: Option<&'static str>,
+}
+
+impl Default for BuildInfo {
+    fn default() -> Self {
+        Self {
+            name: "unknown",
+            version: "unknown",
+            git_sha: None,
+            rustc_version: None,
+        }
+    }
+}
+
+/// Creates a [`BuildInfo`] describing the crate that invokes the macro.
+///
+/// The git commit and compiler version are read from the `GEAR_GIT_SHA` and
+/// `GEAR_RUSTC_VERSION` variables set by `gear_codegen::build`, and are
+/// `None` when the crate is built without it.
+///
+/// # Examples
+///
+/// ```rust
+/// use gear_microkit::GrpcServer;
+///
+/// let server = GrpcServer::new().with_build_info(gear_microkit::build_info!());
+/// ```
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::BuildInfo {
+            name: ::std::env!("CARGO_PKG_NAME"),
+            version: ::std::env!("CARGO_PKG_VERSION"),
+            git_sha: ::std::option_env!("GEAR_GIT_SHA"),
+            rustc_version: ::std::option_env!("GEAR_RUSTC_VERSION"),
+        }
+    };
+}
@@ -0,0 +1,177 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::{override_from_env, parse_bool, parse_list, parse_value, ConfigError};
+
+/// Access log configuration, the `[access_log]` section of
+/// [`GrpcServerConfig`](crate::GrpcServerConfig).
+///
+/// When enabled, one JSON line is written to stdout for every call once its
+/// response has been sent, e.g.:
+///
+/// ```json
+/// {"timestamp":"2024-05-01T08:00:00.123Z","method":"/order.OrderService/PlaceOrder","caller":"gateway","grpc_status":0,"grpc_code":"OK","duration_ms":3.2,"peer":"10.0.0.7:51234","trace_id":"4bf92f3577b34da6a3ce929d0e0e4736","span_id":"00f067aa0ba902b7","app_id":"longbridge","platform":"ios","member_id":"1001"}
+/// ```
+///
+/// `grpc_status` is `null` if the call ended without a status, e.g. because
+/// the client cancelled it. Metadata fields whose header is absent are
+/// omitted.
+///
+/// Lines are written by a dedicated thread, so a slow stdout does not hold up
+/// calls. If it falls behind by more than 8192 lines, further lines are
+/// dropped and counted in the `micro_access_log_dropped_total` Prometheus
+/// counter.
+///
+/// | Variable | Field |
+/// |---|---|
+/// | `GEAR_ACCESS_LOG` | [`enabled`](Self::enabled) |
+/// | `GEAR_ACCESS_LOG_SAMPLE_RATIO` | [`sample_ratio`](Self::sample_ratio) |
+/// | `GEAR_ACCESS_LOG_REDACT` | [`redact`](Self::redact) (comma-separated) |
+///
+/// # Examples
+///
+/// ```toml
+/// [access_log]
+/// enabled = true
+/// sample_ratio = 0.1
+/// redact = ["member_id", "real_ip"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessLogConfig {
+    /// Writes an access log record for every call.
+    ///
+    /// Defaults to `false`.
+    pub enabled: bool,
+
+    /// The fraction of successful calls that are logged, between `0.0` and
+    /// `1.0`.
+    ///
+    /// The decision is derived from the trace ID, so the records kept belong
+    /// to the same traces as those kept by the `traceidratio` sampler with the
+    /// same ratio. Calls without a trace, e.g. when tracing is disabled, are
+    /// sampled at random.
+    ///
+    /// Defaults to `1.0`.
+    pub sample_ratio: f64,
+
+    /// Logs every call that fails with a non-`OK` status, regardless of
+    /// [`sample_ratio`](Self::sample_ratio).
+    ///
+    /// Defaults to `true`.
+    pub always_log_errors: bool,
+
+    /// Fields whose values are replaced by `[REDACTED]`.
+    ///
+    /// Defaults to empty.
+    pub redact: Vec<AccessLogField>,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sample_ratio: 1.0,
+            always_log_errors: true,
+            redact: Vec::new(),
+        }
+    }
+}
+
+impl AccessLogConfig {
+    pub(super) fn apply_env(&mut self) -> Result<(), ConfigError> {
+        override_from_env("GEAR_ACCESS_LOG", &mut self.enabled, parse_bool)?;
+        override_from_env(
+            "GEAR_ACCESS_LOG_SAMPLE_RATIO",
+            &mut self.sample_ratio,
+            parse_value,
+        )?;
+        override_from_env(
+            "GEAR_ACCESS_LOG_REDACT",
+            &mut self.redact,
+            parse_list(parse_value),
+        )?;
+        Ok(())
+    }
+
+    pub(super) fn validate(&self) -> Result<(), ConfigError> {
+        if !(0.0..=1.0).contains(&self.sample_ratio) {
+            return Err(ConfigError::invalid(
+                "access_log.sample_ratio",
+                format!("{} is not between 0.0 and 1.0", self.sample_ratio),
+            ));
+        }
+        Ok(())
+    }
+}
+
+define_names! {
+    /// A field of the access log record that can be
+    /// [redacted](AccessLogConfig::redact).
+    pub enum AccessLogField {
+        /// The calling service from the `x-micro-from-service` header.
+        Caller => "caller",
+        /// The address of the connection's peer.
+        Peer => "peer",
+        /// [`RequestExt::app_id`](crate::RequestExt::app_id).
+        AppId => "app_id",
+        /// [`RequestExt::platform`](crate::RequestExt::platform).
+        Platform => "platform",
+        /// [`RequestExt::member_id`](crate::RequestExt::member_id).
+        MemberId => "member_id",
+        /// [`RequestExt::device_id`](crate::RequestExt::device_id).
+        DeviceId => "device_id",
+        /// [`RequestExt::real_ip`](crate::RequestExt::real_ip).
+        RealIp => "real_ip",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{env_error, invalid_field, with_env};
+
+    #[test]
+    fn applies_the_environment() {
+        let mut config = AccessLogConfig::default();
+        with_env(
+            &[
+                ("GEAR_ACCESS_LOG", "true"),
+                ("GEAR_ACCESS_LOG_SAMPLE_RATIO", "0.1"),
+                ("GEAR_ACCESS_LOG_REDACT", "member_id, real_ip"),
+            ],
+            || config.apply_env(),
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            AccessLogConfig {
+                enabled: true,
+                sample_ratio: 0.1,
+                redact: vec![AccessLogField::MemberId, AccessLogField::RealIp],
+                ..Default::default()
+            }
+        );
+
+        let res = with_env(&[("GEAR_ACCESS_LOG_REDACT", "password")], || {
+            AccessLogConfig::default().apply_env()
+        });
+        assert_eq!(env_error(res), Some("GEAR_ACCESS_LOG_REDACT"));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert_eq!(invalid_field(AccessLogConfig::default().validate()), None);
+        for sample_ratio in [-0.1, 1.5, f64::NAN] {
+            let config = AccessLogConfig {
+                sample_ratio,
+                ..Default::default()
+            };
+            assert_eq!(
+                invalid_field(config.validate()),
+                Some("access_log.sample_ratio")
+            );
+        }
+    }
+}
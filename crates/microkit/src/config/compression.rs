@@ -0,0 +1,201 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::{is_method_path, override_from_env, parse_bool, parse_list, parse_value, ConfigError};
+
+/// gRPC message compression, the `[compression]` section of
+/// [`GrpcServerConfig`](crate::GrpcServerConfig).
+///
+/// Requests compressed with any supported encoding, as announced by their
+/// `grpc-encoding` header, are decompressed before they reach the service.
+/// A decompressed message may be at most `max_decoding_message_size` bytes
+/// (see [`MessageSizeConfig`](super::MessageSizeConfig)), or 64 MiB when that
+/// limit is not set; larger messages fail the call with `RESOURCE_EXHAUSTED`.
+/// Responses are compressed with the first of the configured
+/// [`encodings`](Self::encodings) that the client lists in its
+/// `grpc-accept-encoding` header; messages smaller than
+/// [`min_size`](Self::min_size) are still sent uncompressed, which the gRPC
+/// protocol allows per message.
+///
+/// Generated clients send compressed requests after
+/// `set_send_compressed(CompressionEncoding::GZIP)`, and accept compressed
+/// responses after `set_accept_compressed` when they also send a
+/// `grpc-accept-encoding` header.
+///
+/// | Variable | Field |
+/// |---|---|
+/// | `GEAR_COMPRESSION` | [`enabled`](Self::enabled) |
+/// | `GEAR_COMPRESSION_ENCODINGS` | [`encodings`](Self::encodings) (comma-separated) |
+/// | `GEAR_COMPRESSION_MIN_SIZE` | [`min_size`](Self::min_size) |
+/// | `GEAR_COMPRESSION_EXCLUDE_METHODS` | [`exclude_methods`](Self::exclude_methods) (comma-separated) |
+///
+/// # Examples
+///
+/// ```toml
+/// [compression]
+/// encodings = ["gzip"]
+/// min_size = 4096
+/// exclude_methods = ["/media.MediaService/Download"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    /// Decompresses requests and compresses responses. When disabled,
+    /// compressed requests are rejected with `UNIMPLEMENTED`.
+    ///
+    /// Defaults to `true`.
+    pub enabled: bool,
+
+    /// The encodings responses may be compressed with, in order of
+    /// preference. Responses are never compressed when empty.
+    ///
+    /// Defaults to `zstd`, `gzip`, `deflate`.
+    pub encodings: Vec<MessageEncoding>,
+
+    /// The size in bytes below which response messages are sent
+    /// uncompressed.
+    ///
+    /// Compressing costs CPU time on the task sending the response, so small
+    /// messages, which gain little, are better left uncompressed. Messages of
+    /// 64 KiB and more are compressed on the blocking thread pool instead,
+    /// which keeps them from stalling other calls but adds a thread handoff
+    /// per message.
+    ///
+    /// Defaults to 1 KiB.
+    pub min_size: usize,
+
+    /// Methods whose responses are never compressed, as full method paths
+    /// such as `/media.MediaService/Download`, e.g. because their payloads are
+    /// already compressed.
+    ///
+    /// Defaults to empty.
+    pub exclude_methods: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            encodings: vec![
+                MessageEncoding::Zstd,
+                MessageEncoding::Gzip,
+                MessageEncoding::Deflate,
+            ],
+            min_size: 1024,
+            exclude_methods: Vec::new(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    pub(super) fn apply_env(&mut self) -> Result<(), ConfigError> {
+        override_from_env("GEAR_COMPRESSION", &mut self.enabled, parse_bool)?;
+        override_from_env(
+            "GEAR_COMPRESSION_ENCODINGS",
+            &mut self.encodings,
+            parse_list(parse_value),
+        )?;
+        override_from_env("GEAR_COMPRESSION_MIN_SIZE", &mut self.min_size, parse_value)?;
+        override_from_env(
+            "GEAR_COMPRESSION_EXCLUDE_METHODS",
+            &mut self.exclude_methods,
+            parse_list(parse_value),
+        )?;
+        Ok(())
+    }
+
+    pub(super) fn validate(&self) -> Result<(), ConfigError> {
+        for (i, encoding) in self.encodings.iter().enumerate() {
+            if self.encodings[..i].contains(encoding) {
+                return Err(ConfigError::invalid(
+                    "compression.encodings",
+                    format!("`{encoding}` is listed more than once"),
+                ));
+            }
+        }
+        if let Some(method) = self
+            .exclude_methods
+            .iter()
+            .find(|method| !is_method_path(method))
+        {
+            return Err(ConfigError::invalid(
+                "compression.exclude_methods",
+                format!("`{method}` is not a method path like `/package.Service/Method`"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+define_names! {
+    /// A gRPC message compression encoding, as named in the `grpc-encoding`
+    /// header.
+    pub enum MessageEncoding {
+        /// gzip.
+        Gzip => "gzip",
+        /// DEFLATE.
+        Deflate => "deflate",
+        /// Zstandard.
+        Zstd => "zstd",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{env_error, invalid_field, with_env};
+
+    #[test]
+    fn applies_the_environment() {
+        let mut config = CompressionConfig::default();
+        with_env(
+            &[
+                ("GEAR_COMPRESSION", "off"),
+                ("GEAR_COMPRESSION_ENCODINGS", "gzip, zstd"),
+                ("GEAR_COMPRESSION_MIN_SIZE", "0"),
+                (
+                    "GEAR_COMPRESSION_EXCLUDE_METHODS",
+                    "/media.MediaService/Download,",
+                ),
+            ],
+            || config.apply_env(),
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            CompressionConfig {
+                enabled: false,
+                encodings: vec![MessageEncoding::Gzip, MessageEncoding::Zstd],
+                min_size: 0,
+                exclude_methods: vec!["/media.MediaService/Download".to_string()],
+            }
+        );
+
+        let res = with_env(&[("GEAR_COMPRESSION_ENCODINGS", "gzip,br")], || {
+            CompressionConfig::default().apply_env()
+        });
+        assert_eq!(env_error(res), Some("GEAR_COMPRESSION_ENCODINGS"));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert_eq!(invalid_field(CompressionConfig::default().validate()), None);
+        let config = CompressionConfig {
+            encodings: vec![MessageEncoding::Gzip, MessageEncoding::Gzip],
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid_field(config.validate()),
+            Some("compression.encodings")
+        );
+        let config = CompressionConfig {
+            exclude_methods: vec!["Download".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid_field(config.validate()),
+            Some("compression.exclude_methods")
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    is_method_path, override_from_env, parse_duration, parse_optional, parse_value, ConfigError,
+};
+
+/// Limits on the number of calls processed at the same time, the
+/// `[concurrency]` section of [`GrpcServerConfig`](crate::GrpcServerConfig).
+///
+/// A call first takes a slot of its method's limit, if one is configured in
+/// [`methods`](Self::methods), and then a slot of the global
+/// [`max_in_flight`](Self::max_in_flight) limit. When no slot is free, the
+/// call waits in a bounded queue; once the queue is full, or the call has
+/// waited for [`queue_timeout`](Self::queue_timeout), it is rejected with
+/// `RESOURCE_EXHAUSTED` without reaching the handler. A call keeps its slots
+/// until its response stream has ended, so a long-lived streaming call holds
+/// them for its whole duration.
+///
+/// The following Prometheus metrics are exported, labeled by `limit` (the
+/// method path, or `global`):
+///
+/// | Metric | Type | Description |
+/// |---|---|---|
+/// | `micro_concurrency_in_flight` | Gauge | Calls holding a slot |
+/// | `micro_concurrency_queue_depth` | Gauge | Calls waiting for a slot |
+/// | `micro_concurrency_rejections_total` | Counter | Rejected calls, additionally labeled by `method` (`unknown` for paths no service answers) and `reason` (`queue_full` or `queue_timeout`) |
+///
+/// | Variable | Field |
+/// |---|---|
+/// | `GEAR_MAX_IN_FLIGHT` | [`max_in_flight`](Self::max_in_flight) (`none` for unlimited) |
+/// | `GEAR_MAX_QUEUED` | [`max_queued`](Self::max_queued) |
+/// | `GEAR_QUEUE_TIMEOUT` | [`queue_timeout`](Self::queue_timeout) (`none` to wait indefinitely) |
+///
+/// # Examples
+///
+/// ```toml
+/// [concurrency]
+/// max_in_flight = 512
+/// max_queued = 128
+/// queue_timeout = "100ms"
+///
+/// [concurrency.methods."/order.OrderService/PlaceOrder"]
+/// max_in_flight = 32
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConcurrencyConfig {
+    /// The maximum number of calls processed at the same time across all
+    /// methods, or `None` for no limit.
+    ///
+    /// Defaults to `None`.
+    pub max_in_flight: Option<u32>,
+
+    /// The maximum number of calls waiting for a slot of the global limit.
+    ///
+    /// Defaults to `0`, which rejects calls as soon as the limit is reached.
+    pub max_queued: u32,
+
+    /// The maximum time a call waits in a queue, for the global limit and
+    /// every method limit alike, or `None` to wait until a slot is free or the
+    /// caller's deadline passes.
+    ///
+    /// Defaults to `None`.
+    #[serde(with = "humantime_serde")]
+    pub queue_timeout: Option<Duration>,
+
+    /// Per-method limits, keyed by the full method path, e.g.
+    /// `/order.OrderService/PlaceOrder`.
+    ///
+    /// Defaults to empty.
+    pub methods: BTreeMap<String, ConcurrencyLimit>,
+}
+
+/// The concurrency limit of a single method in
+/// [`ConcurrencyConfig::methods`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConcurrencyLimit {
+    /// The maximum number of calls of the method processed at the same time.
+    pub max_in_flight: u32,
+
+    /// The maximum number of calls of the method waiting for a slot.
+    ///
+    /// Defaults to `0`.
+    #[serde(default)]
+    pub max_queued: u32,
+}
+
+impl ConcurrencyConfig {
+    /// Returns `true` if any limit is configured.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_in_flight.is_some() || !self.methods.is_empty()
+    }
+
+    pub(super) fn apply_env(&mut self) -> Result<(), ConfigError> {
+        override_from_env(
+            "GEAR_MAX_IN_FLIGHT",
+            &mut self.max_in_flight,
+            parse_optional(parse_value),
+        )?;
+        override_from_env("GEAR_MAX_QUEUED", &mut self.max_queued, parse_value)?;
+        override_from_env(
+            "GEAR_QUEUE_TIMEOUT",
+            &mut self.queue_timeout,
+            parse_optional(parse_duration),
+        )?;
+        Ok(())
+    }
+
+    pub(super) fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_in_flight == Some(0) {
+            return Err(ConfigError::invalid(
+                "concurrency.max_in_flight",
+                "must be greater than zero",
+            ));
+        }
+        if self.queue_timeout == Some(Duration::ZERO) {
+            return Err(ConfigError::invalid(
+                "concurrency.queue_timeout",
+                "must be greater than zero",
+            ));
+        }
+        for (method, limit) in &self.methods {
+            if !is_method_path(method) {
+                return Err(ConfigError::invalid(
+                    "concurrency.methods",
+                    format!("`{method}` is not a method path like `/package.Service/Method`"),
+                ));
+            }
+            if limit.max_in_flight == 0 {
+                return Err(ConfigError::invalid(
+                    "concurrency.methods",
+                    format!("`max_in_flight` of `{method}` must be greater than zero"),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{invalid_field, with_env};
+
+    #[test]
+    fn applies_the_environment() {
+        let mut config = ConcurrencyConfig {
+            max_in_flight: Some(512),
+            queue_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        with_env(
+            &[
+                ("GEAR_MAX_IN_FLIGHT", "none"),
+                ("GEAR_MAX_QUEUED", "64"),
+                ("GEAR_QUEUE_TIMEOUT", "250ms"),
+            ],
+            || config.apply_env(),
+        )
+        .unwrap();
+        assert_eq!(config.max_in_flight, None);
+        assert_eq!(config.max_queued, 64);
+        assert_eq!(config.queue_timeout, Some(Duration::from_millis(250)));
+        assert!(!config.is_enabled());
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let method = |method: &str, max_in_flight| ConcurrencyConfig {
+            methods: BTreeMap::from([(
+                method.to_string(),
+                ConcurrencyLimit {
+                    max_in_flight,
+                    max_queued: 0,
+                },
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(invalid_field(ConcurrencyConfig::default().validate()), None);
+        assert_eq!(
+            invalid_field(method("/order.OrderService/PlaceOrder", 32).validate()),
+            None
+        );
+        let config = ConcurrencyConfig {
+            max_in_flight: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid_field(config.validate()),
+            Some("concurrency.max_in_flight")
+        );
+        let config = ConcurrencyConfig {
+            queue_timeout: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid_field(config.validate()),
+            Some("concurrency.queue_timeout")
+        );
+        assert_eq!(
+            invalid_field(method("PlaceOrder", 32).validate()),
+            Some("concurrency.methods")
+        );
+        assert_eq!(
+            invalid_field(method("/order.OrderService/PlaceOrder", 0).validate()),
+            Some("concurrency.methods")
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use std::time::Duration;
+
+use poem::http::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+use super::{override_from_env, parse_bool, parse_duration, parse_list, parse_value, ConfigError};
+
+/// gRPC-Web configuration, the `[grpc_web]` section of
+/// [`GrpcServerConfig`](crate::GrpcServerConfig).
+///
+/// When enabled, calls using the `application/grpc-web` and
+/// `application/grpc-web-text` content types are translated to native gRPC
+/// before they reach the middleware stack, so browser clients can call the
+/// services over HTTP/1.1 or HTTP/2. Trailers are sent in the response body,
+/// base64-encoded along with the messages for `grpc-web-text`. Native gRPC
+/// calls are unaffected.
+///
+/// | Variable | Field |
+/// |---|---|
+/// | `GEAR_GRPC_WEB` | [`enabled`](Self::enabled) |
+/// | `GEAR_CORS_ALLOWED_ORIGINS` | [`cors.allowed_origins`](CorsConfig::allowed_origins) (comma-separated) |
+/// | `GEAR_CORS_ALLOW_CREDENTIALS` | [`cors.allow_credentials`](CorsConfig::allow_credentials) |
+/// | `GEAR_CORS_MAX_AGE` | [`cors.max_age`](CorsConfig::max_age) |
+///
+/// # Examples
+///
+/// ```toml
+/// [grpc_web]
+/// enabled = true
+///
+/// [grpc_web.cors]
+/// allowed_origins = ["https://app.example.com", "https://*.example.com"]
+/// allowed_headers = ["content-type", "x-grpc-web", "x-user-agent", "grpc-timeout", "authorization"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrpcWebConfig {
+    /// Accepts gRPC-Web calls.
+    ///
+    /// Defaults to `false`.
+    pub enabled: bool,
+
+    /// Cross-origin resource sharing for gRPC-Web calls.
+    pub cors: CorsConfig,
+}
+
+/// Cross-origin resource sharing (CORS) configuration, the `[grpc_web.cors]`
+/// section of [`GrpcServerConfig`](crate::GrpcServerConfig).
+///
+/// Preflight requests are answered without reaching the services, and
+/// requests from origins that are not allowed are rejected with
+/// `403 Forbidden`. Requests without an `Origin` header are not affected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// The origins allowed to call the services, which may contain `*`
+    /// wildcards, e.g. `https://*.example.com`. Any origin is allowed when
+    /// empty.
+    ///
+    /// Defaults to empty.
+    pub allowed_origins: Vec<String>,
+
+    /// The request headers browsers may send. Any header is allowed when
+    /// empty.
+    ///
+    /// Defaults to empty.
+    pub allowed_headers: Vec<String>,
+
+    /// Response headers exposed to browsers in addition to `grpc-status`,
+    /// `grpc-message` and `grpc-status-details-bin`.
+    ///
+    /// Defaults to empty.
+    pub exposed_headers: Vec<String>,
+
+    /// Allows requests carrying cookies or HTTP authentication.
+    ///
+    /// Requires explicit [`allowed_origins`](Self::allowed_origins), as any
+    /// website could otherwise make credentialed calls and read their
+    /// responses.
+    ///
+    /// Defaults to `false`.
+    pub allow_credentials: bool,
+
+    /// How long browsers may cache the result of a preflight request.
+    ///
+    /// Defaults to 24 hours.
+    #[serde(with = "humantime_serde")]
+    pub max_age: Duration,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_headers: Vec::new(),
+            exposed_headers: Vec::new(),
+            allow_credentials: false,
+            max_age: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl GrpcWebConfig {
+    pub(super) fn apply_env(&mut self) -> Result<(), ConfigError> {
+        override_from_env("GEAR_GRPC_WEB", &mut self.enabled, parse_bool)?;
+        override_from_env(
+            "GEAR_CORS_ALLOWED_ORIGINS",
+            &mut self.cors.allowed_origins,
+            parse_list(parse_value),
+        )?;
+        override_from_env(
+            "GEAR_CORS_ALLOW_CREDENTIALS",
+            &mut self.cors.allow_credentials,
+            parse_bool,
+        )?;
+        override_from_env("GEAR_CORS_MAX_AGE", &mut self.cors.max_age, parse_duration)?;
+        Ok(())
+    }
+
+    pub(super) fn validate(&self) -> Result<(), ConfigError> {
+        let cors = &self.cors;
+        if let Some(origin) = cors
+            .allowed_origins
+            .iter()
+            .find(|origin| HeaderValue::from_str(origin).is_err())
+        {
+            return Err(ConfigError::invalid(
+                "grpc_web.cors.allowed_origins",
+                format!("`{origin}` is not a valid origin"),
+            ));
+        }
+        for (field, headers) in [
+            ("grpc_web.cors.allowed_headers", &cors.allowed_headers),
+            ("grpc_web.cors.exposed_headers", &cors.exposed_headers),
+        ] {
+            if let Some(header) = headers
+                .iter()
+                .find(|header| HeaderName::from_bytes(header.as_bytes()).is_err())
+            {
+                return Err(ConfigError::invalid(
+                    field,
+                    format!("`{header}` is not a valid header name"),
+                ));
+            }
+        }
+        if cors.allow_credentials
+            && (cors.allowed_origins.is_empty() || cors.allowed_origins.iter().any(|o| o == "*"))
+        {
+            return Err(ConfigError::invalid(
+                "grpc_web.cors.allow_credentials",
+                "requires explicit `allowed_origins`",
+            ));
+        }
+        if i32::try_from(cors.max_age.as_secs()).is_err() {
+            return Err(ConfigError::invalid(
+                "grpc_web.cors.max_age",
+                "is too large",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{env_error, with_env};
+
+    fn invalid_field(config: &GrpcWebConfig) -> Option<&'static str> {
+        match config.validate() {
+            Err(ConfigError::Invalid { field, .. }) => Some(field),
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(()) => None,
+        }
+    }
+
+    fn cors(cors: CorsConfig) -> GrpcWebConfig {
+        GrpcWebConfig {
+            enabled: true,
+            cors,
+        }
+    }
+
+    #[test]
+    fn applies_the_environment() {
+        let mut config = GrpcWebConfig::default();
+        with_env(
+            &[
+                ("GEAR_GRPC_WEB", "yes"),
+                (
+                    "GEAR_CORS_ALLOWED_ORIGINS",
+                    "https://app.example.com, https://*.example.com",
+                ),
+                ("GEAR_CORS_ALLOW_CREDENTIALS", "true"),
+                ("GEAR_CORS_MAX_AGE", "10m"),
+            ],
+            || config.apply_env(),
+        )
+        .unwrap();
+        assert!(config.enabled);
+        assert_eq!(
+            config.cors.allowed_origins,
+            ["https://app.example.com", "https://*.example.com"]
+        );
+        assert!(config.cors.allow_credentials);
+        assert_eq!(config.cors.max_age, Duration::from_secs(600));
+        assert_eq!(invalid_field(&config), None);
+
+        let res = with_env(&[("GEAR_CORS_MAX_AGE", "600")], || {
+            GrpcWebConfig::default().apply_env()
+        });
+        assert_eq!(env_error(res), Some("GEAR_CORS_MAX_AGE"));
+    }
+
+    #[test]
+    fn accepts_the_defaults() {
+        assert_eq!(invalid_field(&GrpcWebConfig::default()), None);
+    }
+
+    #[test]
+    fn requires_explicit_origins_with_credentials() {
+        for allowed_origins in [vec![], vec!["*".to_string()]] {
+            let config = cors(CorsConfig {
+                allowed_origins,
+                allow_credentials: true,
+                ..Default::default()
+            });
+            assert_eq!(
+                invalid_field(&config),
+                Some("grpc_web.cors.allow_credentials")
+            );
+        }
+
+        let config = cors(CorsConfig {
+            allowed_origins: vec!["https://*.example.com".to_string()],
+            allow_credentials: true,
+            ..Default::default()
+        });
+        assert_eq!(invalid_field(&config), None);
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let config = cors(CorsConfig {
+            allowed_origins: vec!["https://a.example.com\n".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_field(&config),
+            Some("grpc_web.cors.allowed_origins")
+        );
+
+        let config = cors(CorsConfig {
+            allowed_headers: vec!["x header".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_field(&config),
+            Some("grpc_web.cors.allowed_headers")
+        );
+
+        let config = cors(CorsConfig {
+            exposed_headers: vec!["".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_field(&config),
+            Some("grpc_web.cors.exposed_headers")
+        );
+
+        let config = cors(CorsConfig {
+            max_age: Duration::from_secs(1 << 32),
+            ..Default::default()
+        });
+        assert_eq!(invalid_field(&config), Some("grpc_web.cors.max_age"));
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    fmt::{self, Display},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{is_socket_address, ConfigError};
+
+/// An additional socket the gRPC router is served on, besides
+/// [`GrpcServerConfig::address`](crate::GrpcServerConfig::address).
+///
+/// Addresses are written as URIs:
+///
+/// | Form | Listener |
+/// |---|---|
+/// | `tcp://0.0.0.0:9000` or `0.0.0.0:9000` | A TCP socket bound to the address |
+/// | `unix:///run/gear/grpc.sock` | A Unix domain socket bound to the path |
+/// | `fd://3` | An already listening TCP or Unix socket inherited from the parent process |
+///
+/// A stale socket file left at a Unix socket path by a previous process is
+/// removed before binding, while a socket another process still accepts
+/// connections on fails startup with `AddrInUse`. The socket file is removed
+/// again once the server has stopped. Inherited sockets support socket activation, where
+/// e.g. systemd passes the listening sockets starting at file descriptor 3.
+///
+/// [TLS](crate::TlsConfig) applies to every TCP listener, including inherited
+/// TCP sockets, while Unix domain sockets always serve plaintext HTTP/2.
+///
+/// # Examples
+///
+/// ```toml
+/// address = "0.0.0.0:8080"
+/// listeners = ["unix:///run/gear/grpc.sock"]
+/// ```
+///
+/// ```rust
+/// use gear_microkit::ListenAddress;
+///
+/// let address: ListenAddress = "unix:///run/gear/grpc.sock".parse().unwrap();
+/// assert_eq!(address, ListenAddress::Unix("/run/gear/grpc.sock".into()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddress {
+    /// A TCP socket address such as `0.0.0.0:9000`.
+    Tcp(String),
+    /// The filesystem path of a Unix domain socket.
+    Unix(PathBuf),
+    /// The file descriptor of an inherited listening socket.
+    Fd(i32),
+}
+
+impl ListenAddress {
+    pub(super) fn validate(&self) -> Result<(), ConfigError> {
+        match self {
+            Self::Tcp(address) if !is_socket_address(address) => Err(ConfigError::invalid(
+                "listeners",
+                format!("`{address}` is not a valid socket address"),
+            )),
+            Self::Unix(path) if path.as_os_str().is_empty() => Err(ConfigError::invalid(
+                "listeners",
+                "Unix socket path must not be empty",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix://") {
+            Ok(Self::Unix(PathBuf::from(path)))
+        } else if let Some(fd) = s.strip_prefix("fd://") {
+            fd.parse::<i32>()
+                .ok()
+                .filter(|fd| *fd >= 0)
+                .map(Self::Fd)
+                .ok_or_else(|| format!("`{fd}` is not a file descriptor"))
+        } else if let Some(address) = s.strip_prefix("tcp://") {
+            Ok(Self::Tcp(address.to_string()))
+        } else if s.contains("://") {
+            Err("expected a `tcp://`, `unix://` or `fd://` address".to_string())
+        } else {
+            Ok(Self::Tcp(s.to_string()))
+        }
+    }
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ListenAddress> for String {
+    fn from(address: ListenAddress) -> Self {
+        address.to_string()
+    }
+}
+
+impl Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "tcp://{address}"),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+            Self::Fd(fd) => write!(f, "fd://{fd}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::invalid_field;
+
+    #[test]
+    fn parses_addresses() {
+        for (address, expected) in [
+            (
+                "0.0.0.0:9000",
+                ListenAddress::Tcp("0.0.0.0:9000".to_string()),
+            ),
+            (
+                "tcp://[::]:9000",
+                ListenAddress::Tcp("[::]:9000".to_string()),
+            ),
+            (
+                "unix:///run/gear/grpc.sock",
+                ListenAddress::Unix("/run/gear/grpc.sock".into()),
+            ),
+            ("fd://3", ListenAddress::Fd(3)),
+        ] {
+            assert_eq!(address.parse::<ListenAddress>(), Ok(expected.clone()));
+            assert_eq!(expected.to_string().parse(), Ok(expected));
+        }
+        for address in ["fd://-1", "fd://stdin", "http://0.0.0.0:9000"] {
+            assert!(address.parse::<ListenAddress>().is_err(), "{address}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert_eq!(invalid_field(ListenAddress::Fd(3).validate()), None);
+        assert_eq!(
+            invalid_field(ListenAddress::Tcp("localhost".to_string()).validate()),
+            Some("listeners")
+        );
+        assert_eq!(
+            invalid_field(ListenAddress::Unix(PathBuf::new()).validate()),
+            Some("listeners")
+        );
+    }
+}
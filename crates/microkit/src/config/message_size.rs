@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{is_method_path, override_from_env, parse_optional, parse_value, ConfigError};
+
+/// Limits on the size of gRPC messages, the `[message_size]` section of
+/// [`GrpcServerConfig`](crate::GrpcServerConfig).
+///
+/// Sizes are checked against the length prefix of every message as soon as
+/// it arrives, so an oversized request message is rejected with
+/// `RESOURCE_EXHAUSTED` before it is buffered, whether it is protobuf or
+/// JSON. Compressed request messages are checked both as received and once
+/// decompressed. An oversized response message ends the call with
+/// `RESOURCE_EXHAUSTED` instead of being sent; since responses are checked
+/// as sent, the limit applies to compressed messages after compression.
+///
+/// The request limits also cover the other ways calls arrive: `grpc-web-text`
+/// bodies are decoded as they stream in and checked like any other, and the
+/// JSON body of a transcoded REST request is rejected with `413 Payload Too
+/// Large` once it exceeds the limit of its method.
+///
+/// | Variable | Field |
+/// |---|---|
+/// | `GEAR_MAX_DECODING_MESSAGE_SIZE` | [`max_decoding_message_size`](Self::max_decoding_message_size) (`none` for unlimited) |
+/// | `GEAR_MAX_ENCODING_MESSAGE_SIZE` | [`max_encoding_message_size`](Self::max_encoding_message_size) (`none` for unlimited) |
+///
+/// # Examples
+///
+/// ```toml
+/// [message_size]
+/// max_decoding_message_size = 1048576
+///
+/// [message_size.methods."/media.MediaService/Upload"]
+/// max_decoding_message_size = 67108864
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessageSizeConfig {
+    /// The maximum size in bytes of a request message, or `None` for no
+    /// limit.
+    ///
+    /// Defaults to 4 MiB, the limit of most gRPC implementations.
+    pub max_decoding_message_size: Option<usize>,
+
+    /// The maximum size in bytes of a response message, or `None` for no
+    /// limit.
+    ///
+    /// Defaults to `None`.
+    pub max_encoding_message_size: Option<usize>,
+
+    /// Per-method limits, keyed by the full method path, e.g.
+    /// `/media.MediaService/Upload`, replacing the global ones.
+    ///
+    /// A method limit can raise or lower the global one, but not remove it:
+    /// an unset method limit falls back to the global limit. To effectively
+    /// lift the limit for a method, set it to `4294967295`, the largest
+    /// message length the gRPC framing can express.
+    ///
+    /// Defaults to empty.
+    pub methods: BTreeMap<String, MessageSizeLimit>,
+}
+
+/// The message size limits of a single method in
+/// [`MessageSizeConfig::methods`]. Unset limits fall back to the global ones,
+/// so they cannot switch a global limit off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessageSizeLimit {
+    /// The maximum size in bytes of a request message of the method.
+    pub max_decoding_message_size: Option<usize>,
+
+    /// The maximum size in bytes of a response message of the method.
+    pub max_encoding_message_size: Option<usize>,
+}
+
+impl Default for MessageSizeConfig {
+    fn default() -> Self {
+        Self {
+            max_decoding_message_size: Some(4 * 1024 * 1024),
+            max_encoding_message_size: None,
+            methods: BTreeMap::new(),
+        }
+    }
+}
+
+impl MessageSizeConfig {
+    /// Returns `true` if any limit is configured.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_decoding_message_size.is_some()
+            || self.max_encoding_message_size.is_some()
+            || !self.methods.is_empty()
+    }
+
+    /// Returns the limits that apply to `method`.
+    pub(crate) fn limit(&self, method: &str) -> MessageSizeLimit {
+        let limit = self.methods.get(method).copied().unwrap_or_default();
+        MessageSizeLimit {
+            max_decoding_message_size: limit
+                .max_decoding_message_size
+                .or(self.max_decoding_message_size),
+            max_encoding_message_size: limit
+                .max_encoding_message_size
+                .or(self.max_encoding_message_size),
+        }
+    }
+
+    pub(super) fn apply_env(&mut self) -> Result<(), ConfigError> {
+        override_from_env(
+            "GEAR_MAX_DECODING_MESSAGE_SIZE",
+            &mut self.max_decoding_message_size,
+            parse_optional(parse_value),
+        )?;
+        override_from_env(
+            "GEAR_MAX_ENCODING_MESSAGE_SIZE",
+            &mut self.max_encoding_message_size,
+            parse_optional(parse_value),
+        )?;
+        Ok(())
+    }
+
+    pub(super) fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_decoding_message_size == Some(0) {
+            return Err(ConfigError::invalid(
+                "message_size.max_decoding_message_size",
+                "must be greater than zero",
+            ));
+        }
+        if self.max_encoding_message_size == Some(0) {
+            return Err(ConfigError::invalid(
+                "message_size.max_encoding_message_size",
+                "must be greater than zero",
+            ));
+        }
+        for (method, limit) in &self.methods {
+            if !is_method_path(method) {
+                return Err(ConfigError::invalid(
+                    "message_size.methods",
+                    format!("`{method}` is not a method path like `/package.Service/Method`"),
+                ));
+            }
+            for (field, size) in [
+                ("max_decoding_message_size", limit.max_decoding_message_size),
+                ("max_encoding_message_size", limit.max_encoding_message_size),
+            ] {
+                if size == Some(0) {
+                    return Err(ConfigError::invalid(
+                        "message_size.methods",
+                        format!("`{field}` of `{method}` must be greater than zero"),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{invalid_field, with_env};
+
+    fn method(limit: MessageSizeLimit) -> MessageSizeConfig {
+        MessageSizeConfig {
+            methods: BTreeMap::from([("/media.MediaService/Upload".to_string(), limit)]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn applies_the_environment() {
+        let mut config = MessageSizeConfig::default();
+        with_env(
+            &[
+                ("GEAR_MAX_DECODING_MESSAGE_SIZE", "none"),
+                ("GEAR_MAX_ENCODING_MESSAGE_SIZE", "1048576"),
+            ],
+            || config.apply_env(),
+        )
+        .unwrap();
+        assert_eq!(config.max_decoding_message_size, None);
+        assert_eq!(config.max_encoding_message_size, Some(1024 * 1024));
+    }
+
+    #[test]
+    fn falls_back_to_the_global_limits() {
+        let config = method(MessageSizeLimit {
+            max_decoding_message_size: Some(64 * 1024 * 1024),
+            max_encoding_message_size: None,
+        });
+        assert_eq!(
+            config.limit("/media.MediaService/Upload"),
+            MessageSizeLimit {
+                max_decoding_message_size: Some(64 * 1024 * 1024),
+                max_encoding_message_size: None,
+            }
+        );
+        assert_eq!(
+            config.limit("/media.MediaService/Download"),
+            MessageSizeLimit {
+                max_decoding_message_size: Some(4 * 1024 * 1024),
+                max_encoding_message_size: None,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert_eq!(invalid_field(MessageSizeConfig::default().validate()), None);
+        let config = MessageSizeConfig {
+            max_decoding_message_size: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid_field(config.validate()),
+            Some("message_size.max_decoding_message_size")
+        );
+        let config = MessageSizeConfig {
+            max_encoding_message_size: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid_field(config.validate()),
+            Some("message_size.max_encoding_message_size")
+        );
+        let config = method(MessageSizeLimit {
+            max_encoding_message_size: Some(0),
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_field(config.validate()),
+            Some("message_size.methods")
+        );
+        let config = MessageSizeConfig {
+            methods: BTreeMap::from([("Upload".to_string(), MessageSizeLimit::default())]),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid_field(config.validate()),
+            Some("message_size.methods")
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::{is_method_path, ConfigError};
+
+/// A token-bucket rate limit, an entry of the `[[rate_limits]]` array of
+/// [`GrpcServerConfig`](crate::GrpcServerConfig).
+///
+/// Every distinct combination of the [`key`](Self::key) values gets its own
+/// bucket holding up to [`burst`](Self::burst) tokens, refilled at
+/// [`limit`](Self::limit) tokens per [`period`](Self::period). Each call takes
+/// one token from the bucket of every rule that applies to it; a call finding a
+/// bucket empty fails with `RESOURCE_EXHAUSTED` without reaching the handler.
+/// The rejection carries a `grpc-retry-pushback-ms` header with the time until
+/// the next token is available.
+///
+/// A rule applies to a call if the call's method is listed in
+/// [`methods`](Self::methods) (or the list is empty) and the call carries every
+/// value named in the key. For example, a rule keyed by
+/// [`MemberId`](RateLimitKey::MemberId) ignores calls without a valid
+/// `member-id` header.
+///
+/// A call is only charged if every applicable bucket has a token; a rejected
+/// call takes none.
+///
+/// Rejected calls are counted in the `micro_rate_limited_total` Prometheus
+/// counter, labeled by `method` (`unknown` for paths no service answers) and
+/// `rule`.
+///
+/// Rate limits are only read from the configuration file.
+///
+/// # Examples
+///
+/// At most 10 calls per second to `PlaceOrder` for each member, and 500 calls
+/// per second to any method from each calling service:
+///
+/// ```toml
+/// [[rate_limits]]
+/// name = "place-order-per-member"
+/// key = ["member_id"]
+/// methods = ["/order.OrderService/PlaceOrder"]
+/// limit = 10
+///
+/// [[rate_limits]]
+/// name = "per-caller"
+/// key = ["caller"]
+/// limit = 500
+/// burst = 1000
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitRule {
+    /// The name of the rule, reported in rejections and metrics.
+    pub name: String,
+
+    /// The values calls are grouped by, each group getting its own bucket.
+    ///
+    /// Defaults to empty, which shares a single bucket between all calls the
+    /// rule applies to.
+    #[serde(default)]
+    pub key: Vec<RateLimitKey>,
+
+    /// The methods the rule applies to, as full method paths such as
+    /// `/order.OrderService/PlaceOrder`.
+    ///
+    /// Defaults to empty, which applies the rule to every method.
+    #[serde(default)]
+    pub methods: Vec<String>,
+
+    /// The number of calls allowed per [`period`](Self::period).
+    pub limit: u32,
+
+    /// The period [`limit`](Self::limit) applies to.
+    ///
+    /// Defaults to 1 second.
+    #[serde(default = "default_period", with = "humantime_serde")]
+    pub period: Duration,
+
+    /// The maximum number of calls allowed in a burst, or `None` to use
+    /// [`limit`](Self::limit).
+    ///
+    /// Defaults to `None`.
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+fn default_period() -> Duration {
+    Duration::from_secs(1)
+}
+
+impl RateLimitRule {
+    /// Creates a rule allowing `limit` calls per second, shared by all calls,
+    /// with the remaining fields set to their defaults.
+    pub fn new(name: impl Into<String>, limit: u32) -> Self {
+        Self {
+            name: name.into(),
+            key: Vec::new(),
+            methods: Vec::new(),
+            limit,
+            period: default_period(),
+            burst: None,
+        }
+    }
+
+    pub(super) fn validate(rules: &[Self]) -> Result<(), ConfigError> {
+        for (i, rule) in rules.iter().enumerate() {
+            if rule.name.is_empty() {
+                return Err(ConfigError::invalid(
+                    "rate_limits",
+                    "rule name must not be empty",
+                ));
+            }
+            if rules[..i].iter().any(|other| other.name == rule.name) {
+                return Err(ConfigError::invalid(
+                    "rate_limits",
+                    format!("rule `{}` is defined more than once", rule.name),
+                ));
+            }
+            for (j, key) in rule.key.iter().enumerate() {
+                if rule.key[..j].contains(key) {
+                    return Err(ConfigError::invalid(
+                        "rate_limits",
+                        format!(
+                            "`{key}` is listed more than once in the key of `{}`",
+                            rule.name
+                        ),
+                    ));
+                }
+            }
+            if let Some(method) = rule.methods.iter().find(|method| !is_method_path(method)) {
+                return Err(ConfigError::invalid(
+                    "rate_limits",
+                    format!(
+                        "`{method}` in `{}` is not a method path like `/package.Service/Method`",
+                        rule.name
+                    ),
+                ));
+            }
+            if rule.limit == 0 {
+                return Err(ConfigError::invalid(
+                    "rate_limits",
+                    format!("`limit` of `{}` must be greater than zero", rule.name),
+                ));
+            }
+            if rule.period.is_zero() {
+                return Err(ConfigError::invalid(
+                    "rate_limits",
+                    format!("`period` of `{}` must be greater than zero", rule.name),
+                ));
+            }
+            if rule.burst == Some(0) {
+                return Err(ConfigError::invalid(
+                    "rate_limits",
+                    format!("`burst` of `{}` must be greater than zero", rule.name),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+define_names! {
+    /// A value [`RateLimitRule`] buckets are keyed by.
+    pub enum RateLimitKey {
+        /// The method path, e.g. `/order.OrderService/PlaceOrder`.
+        Method => "method",
+        /// The calling service from the `x-micro-from-service` header.
+        Caller => "caller",
+        /// The member from the `member-id` header, see
+        /// [`RequestExt::member_id`](crate::RequestExt::member_id). Values
+        /// that are not a valid ID are ignored.
+        MemberId => "member_id",
+        /// The application from the `app-id` header, see
+        /// [`RequestExt::app_id`](crate::RequestExt::app_id).
+        AppId => "app_id",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(rules: &[RateLimitRule]) -> Option<String> {
+        match RateLimitRule::validate(rules) {
+            Err(ConfigError::Invalid { field, reason }) => {
+                assert_eq!(field, "rate_limits");
+                Some(reason)
+            }
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(()) => None,
+        }
+    }
+
+    #[test]
+    fn accepts_valid_rules() {
+        let rule = RateLimitRule {
+            key: vec![RateLimitKey::MemberId, RateLimitKey::Method],
+            methods: vec!["/order.OrderService/PlaceOrder".to_string()],
+            burst: Some(20),
+            ..RateLimitRule::new("place-order", 10)
+        };
+        assert_eq!(reason(&[rule, RateLimitRule::new("global", 500)]), None);
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let rule = RateLimitRule::new("global", 500);
+        for (rules, expected) in [
+            (vec![RateLimitRule::new("", 1)], "must not be empty"),
+            (vec![rule.clone(), rule.clone()], "defined more than once"),
+            (
+                vec![RateLimitRule {
+                    key: vec![RateLimitKey::Caller, RateLimitKey::Caller],
+                    ..rule.clone()
+                }],
+                "listed more than once",
+            ),
+            (
+                vec![RateLimitRule {
+                    methods: vec!["PlaceOrder".to_string()],
+                    ..rule.clone()
+                }],
+                "not a method path",
+            ),
+            (vec![RateLimitRule::new("global", 0)], "`limit`"),
+            (
+                vec![RateLimitRule {
+                    period: Duration::ZERO,
+                    ..rule.clone()
+                }],
+                "`period`",
+            ),
+            (
+                vec![RateLimitRule {
+                    burst: Some(0),
+                    ..rule.clone()
+                }],
+                "`burst`",
+            ),
+        ] {
+            let reason = reason(&rules).unwrap();
+            assert!(reason.contains(expected), "{reason}");
+        }
+    }
+
+    #[test]
+    fn reads_rules_from_toml() {
+        let rule: RateLimitRule = toml::from_str(
+            r#"
+            name = "per-caller"
+            key = ["caller", "app_id"]
+            limit = 500
+            period = "1m"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rule.key, [RateLimitKey::Caller, RateLimitKey::AppId]);
+        assert_eq!(rule.period, Duration::from_secs(60));
+        assert_eq!(rule.burst, None);
+
+        assert!(toml::from_str::<RateLimitRule>("name = \"a\"\nlimit = 1\nrate = 2").is_err());
+    }
+}
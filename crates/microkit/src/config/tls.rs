@@ -0,0 +1,285 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::{env_var, override_from_env, parse_duration, parse_optional, parse_value, ConfigError};
+
+/// TLS configuration for the gRPC listener, the `[tls]` section of
+/// [`GrpcServerConfig`](crate::GrpcServerConfig).
+///
+/// Certificates and keys are read from PEM files. The files are polled every
+/// [`reload_interval`](Self::reload_interval) and the new certificates are
+/// used for subsequent handshakes as soon as they change on disk, so rotation
+/// (e.g. by cert-manager updating a mounted Kubernetes secret) does not
+/// require a restart. Existing connections keep the certificate they were
+/// established with.
+///
+/// Setting [`client_ca_path`](Self::client_ca_path) enables mutual TLS. The
+/// identity of a verified client certificate is available to handlers as
+/// [`ClientIdentity`](crate::ClientIdentity) request data.
+///
+/// | Variable | Field |
+/// |---|---|
+/// | `GEAR_TLS_CERT_FILE` | [`cert_path`](Self::cert_path) |
+/// | `GEAR_TLS_KEY_FILE` | [`key_path`](Self::key_path) |
+/// | `GEAR_TLS_CLIENT_CA_FILE` | [`client_ca_path`](Self::client_ca_path) (`none` to disable mutual TLS) |
+/// | `GEAR_TLS_CLIENT_AUTH` | [`client_auth`](Self::client_auth) |
+/// | `GEAR_TLS_RELOAD_INTERVAL` | [`reload_interval`](Self::reload_interval) (`none` to disable) |
+/// | `GEAR_TLS_HANDSHAKE_TIMEOUT` | [`handshake_timeout`](Self::handshake_timeout) |
+///
+/// Setting both `GEAR_TLS_CERT_FILE` and `GEAR_TLS_KEY_FILE` enables TLS even
+/// if the configuration file has no `[tls]` section.
+///
+/// # Examples
+///
+/// ```toml
+/// [tls]
+/// cert_path = "/etc/tls/tls.crt"
+/// key_path = "/etc/tls/tls.key"
+/// client_ca_path = "/etc/tls/ca.crt"
+/// client_auth = "required"
+/// reload_interval = "30s"
+/// handshake_timeout = "5s"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// The PEM file holding the server certificate chain, leaf first.
+    pub cert_path: PathBuf,
+
+    /// The PEM file holding the private key of the server certificate.
+    pub key_path: PathBuf,
+
+    /// The PEM file holding the CA certificates client certificates are
+    /// verified against, or `None` to disable mutual TLS.
+    ///
+    /// Defaults to `None`.
+    #[serde(default)]
+    pub client_ca_path: Option<PathBuf>,
+
+    /// Whether clients must present a certificate when mutual TLS is enabled.
+    ///
+    /// Defaults to [`ClientAuth::Required`].
+    #[serde(default = "default_client_auth")]
+    pub client_auth: ClientAuth,
+
+    /// How often the certificate files are checked for changes, or `None` to
+    /// load them only once at startup.
+    ///
+    /// Defaults to 60 seconds.
+    #[serde(default = "default_reload_interval", with = "humantime_serde")]
+    pub reload_interval: Option<Duration>,
+
+    /// How long a client may take to complete the TLS handshake before its
+    /// connection is closed.
+    ///
+    /// Applies even when no idle timeout is configured, so that clients
+    /// stalling the handshake cannot hold connections open indefinitely.
+    ///
+    /// Defaults to 10 seconds.
+    #[serde(default = "default_handshake_timeout", with = "humantime_serde")]
+    pub handshake_timeout: Duration,
+}
+
+fn default_client_auth() -> ClientAuth {
+    ClientAuth::Required
+}
+
+fn default_reload_interval() -> Option<Duration> {
+    Some(Duration::from_secs(60))
+}
+
+fn default_handshake_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+impl TlsConfig {
+    /// Creates a configuration serving the given certificate and key, with
+    /// the remaining fields set to their defaults.
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path: None,
+            client_auth: default_client_auth(),
+            reload_interval: default_reload_interval(),
+            handshake_timeout: default_handshake_timeout(),
+        }
+    }
+
+    pub(super) fn apply_env(tls: &mut Option<Self>) -> Result<(), ConfigError> {
+        if tls.is_none() {
+            match (
+                env_var("GEAR_TLS_CERT_FILE")?,
+                env_var("GEAR_TLS_KEY_FILE")?,
+            ) {
+                (Some(cert_path), Some(key_path)) => {
+                    *tls = Some(Self::new(cert_path.trim(), key_path.trim()));
+                }
+                (Some(_), None) => {
+                    return Err(ConfigError::Env {
+                        name: "GEAR_TLS_CERT_FILE",
+                        reason: "`GEAR_TLS_KEY_FILE` must be set as well".to_string(),
+                    })
+                }
+                (None, Some(_)) => {
+                    return Err(ConfigError::Env {
+                        name: "GEAR_TLS_KEY_FILE",
+                        reason: "`GEAR_TLS_CERT_FILE` must be set as well".to_string(),
+                    })
+                }
+                (None, None) => return Ok(()),
+            }
+        }
+        let Some(tls) = tls else {
+            return Ok(());
+        };
+        override_from_env("GEAR_TLS_CERT_FILE", &mut tls.cert_path, parse_value)?;
+        override_from_env("GEAR_TLS_KEY_FILE", &mut tls.key_path, parse_value)?;
+        override_from_env(
+            "GEAR_TLS_CLIENT_CA_FILE",
+            &mut tls.client_ca_path,
+            parse_optional(parse_value),
+        )?;
+        override_from_env("GEAR_TLS_CLIENT_AUTH", &mut tls.client_auth, parse_value)?;
+        override_from_env(
+            "GEAR_TLS_RELOAD_INTERVAL",
+            &mut tls.reload_interval,
+            parse_optional(parse_duration),
+        )?;
+        override_from_env(
+            "GEAR_TLS_HANDSHAKE_TIMEOUT",
+            &mut tls.handshake_timeout,
+            parse_duration,
+        )?;
+        Ok(())
+    }
+
+    pub(super) fn validate(&self) -> Result<(), ConfigError> {
+        if self.cert_path.as_os_str().is_empty() {
+            return Err(ConfigError::invalid("tls.cert_path", "must not be empty"));
+        }
+        if self.key_path.as_os_str().is_empty() {
+            return Err(ConfigError::invalid("tls.key_path", "must not be empty"));
+        }
+        if self.reload_interval == Some(Duration::ZERO) {
+            return Err(ConfigError::invalid(
+                "tls.reload_interval",
+                "must be greater than zero",
+            ));
+        }
+        if self.handshake_timeout.is_zero() {
+            return Err(ConfigError::invalid(
+                "tls.handshake_timeout",
+                "must be greater than zero",
+            ));
+        }
+        Ok(())
+    }
+}
+
+define_names! {
+    /// Whether a client certificate is mandatory when mutual TLS is enabled.
+    pub enum ClientAuth {
+        /// Handshakes without a valid client certificate are rejected.
+        Required => "required",
+        /// Clients may connect without a certificate, in which case no
+        /// [`ClientIdentity`](crate::ClientIdentity) is attached to their
+        /// requests. Invalid certificates are still rejected.
+        Optional => "optional",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{env_error, invalid_field, with_env};
+
+    #[test]
+    fn enables_tls_from_the_environment() {
+        let mut tls = None;
+        with_env(
+            &[
+                ("GEAR_TLS_CERT_FILE", "/etc/tls/tls.crt"),
+                ("GEAR_TLS_KEY_FILE", "/etc/tls/tls.key"),
+                ("GEAR_TLS_CLIENT_AUTH", "optional"),
+                ("GEAR_TLS_RELOAD_INTERVAL", "none"),
+                ("GEAR_TLS_HANDSHAKE_TIMEOUT", "3s"),
+            ],
+            || TlsConfig::apply_env(&mut tls),
+        )
+        .unwrap();
+        assert_eq!(
+            tls,
+            Some(TlsConfig {
+                client_auth: ClientAuth::Optional,
+                reload_interval: None,
+                handshake_timeout: Duration::from_secs(3),
+                ..TlsConfig::new("/etc/tls/tls.crt", "/etc/tls/tls.key")
+            })
+        );
+
+        let mut tls = None;
+        with_env(&[], || TlsConfig::apply_env(&mut tls)).unwrap();
+        assert_eq!(tls, None);
+    }
+
+    #[test]
+    fn overrides_the_file_from_the_environment() {
+        let mut tls = Some(TlsConfig {
+            client_ca_path: Some("/etc/tls/ca.crt".into()),
+            ..TlsConfig::new("a.crt", "a.key")
+        });
+        with_env(
+            &[
+                ("GEAR_TLS_KEY_FILE", "b.key"),
+                ("GEAR_TLS_CLIENT_CA_FILE", "none"),
+            ],
+            || TlsConfig::apply_env(&mut tls),
+        )
+        .unwrap();
+        assert_eq!(tls, Some(TlsConfig::new("a.crt", "b.key")));
+    }
+
+    #[test]
+    fn requires_both_the_certificate_and_key() {
+        let res = with_env(&[("GEAR_TLS_CERT_FILE", "tls.crt")], || {
+            TlsConfig::apply_env(&mut None)
+        });
+        assert_eq!(env_error(res), Some("GEAR_TLS_CERT_FILE"));
+        let res = with_env(&[("GEAR_TLS_KEY_FILE", "tls.key")], || {
+            TlsConfig::apply_env(&mut None)
+        });
+        assert_eq!(env_error(res), Some("GEAR_TLS_KEY_FILE"));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let config = TlsConfig::new("tls.crt", "tls.key");
+        assert_eq!(invalid_field(config.validate()), None);
+        assert_eq!(
+            invalid_field(TlsConfig::new("", "tls.key").validate()),
+            Some("tls.cert_path")
+        );
+        assert_eq!(
+            invalid_field(TlsConfig::new("tls.crt", "").validate()),
+            Some("tls.key_path")
+        );
+        let zero_reload = TlsConfig {
+            reload_interval: Some(Duration::ZERO),
+            ..config.clone()
+        };
+        assert_eq!(
+            invalid_field(zero_reload.validate()),
+            Some("tls.reload_interval")
+        );
+        let zero_handshake = TlsConfig {
+            handshake_timeout: Duration::ZERO,
+            ..config
+        };
+        assert_eq!(
+            invalid_field(zero_handshake.validate()),
+            Some("tls.handshake_timeout")
+        );
+    }
+}
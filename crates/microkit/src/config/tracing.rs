@@ -0,0 +1,323 @@
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::{override_from_env, parse_bool, parse_optional, parse_value, ConfigError};
+
+/// OpenTelemetry tracing configuration, the `[tracing]` section of
+/// [`GrpcServerConfig`](crate::GrpcServerConfig).
+///
+/// The standard OpenTelemetry environment variables override the values from
+/// the configuration file:
+///
+/// | Variable | Field |
+/// |---|---|
+/// | `OTEL_SERVICE_NAME` | [`service_name`](Self::service_name) |
+/// | `OTEL_RESOURCE_ATTRIBUTES` | Merged into [`resource_attributes`](Self::resource_attributes) |
+/// | `OTEL_TRACES_SAMPLER` | [`sampler`](Self::sampler) |
+/// | `OTEL_TRACES_SAMPLER_ARG` | [`sampler_arg`](Self::sampler_arg) |
+/// | `OTEL_TRACES_EXPORTER` | [`exporter`](Self::exporter) |
+/// | `OTEL_EXPORTER_OTLP_TRACES_PROTOCOL`, `OTEL_EXPORTER_OTLP_PROTOCOL` | [`otlp_protocol`](Self::otlp_protocol) |
+/// | `OTEL_SDK_DISABLED` | Sets [`exporter`](Self::exporter) to `none` when `true` |
+/// | `GEAR_ENVIRONMENT` | [`environment`](Self::environment) |
+/// | `GEAR_DETECT_RESOURCE` | [`detect_resource`](Self::detect_resource) |
+///
+/// `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` and `OTEL_EXPORTER_OTLP_ENDPOINT`, as
+/// well as the other `OTEL_EXPORTER_OTLP_*` variables, are read by the OTLP
+/// exporter itself and take precedence over
+/// [`otlp_endpoint`](Self::otlp_endpoint).
+///
+/// # Examples
+///
+/// ```toml
+/// [tracing]
+/// service_name = "order-service"
+/// environment = "staging"
+/// sampler = "parentbased_traceidratio"
+/// sampler_arg = 0.1
+/// exporter = "otlp"
+/// otlp_protocol = "http/protobuf"
+/// otlp_endpoint = "http://otel-collector:4318/v1/traces"
+///
+/// [tracing.resource_attributes]
+/// "team" = "trading"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingConfig {
+    /// The `service.name` resource attribute.
+    ///
+    /// Defaults to the name from [`GrpcServer::with_build_info`](crate::GrpcServer::with_build_info),
+    /// or `unknown_service` if that is not set either.
+    pub service_name: Option<String>,
+
+    /// The deployment environment (e.g. `production`), reported as the
+    /// `deployment.environment.name` resource attribute.
+    ///
+    /// Defaults to `None`.
+    pub environment: Option<String>,
+
+    /// Additional resource attributes attached to every span.
+    ///
+    /// Defaults to empty.
+    pub resource_attributes: BTreeMap<String, String>,
+
+    /// Detects host and Kubernetes resource attributes (`host.name`,
+    /// `k8s.pod.name`, `k8s.namespace.name`, `k8s.node.name`) from the
+    /// environment.
+    ///
+    /// Pod metadata is read from the `POD_NAME`, `POD_NAMESPACE` and
+    /// `NODE_NAME` variables, which are typically populated through the
+    /// Kubernetes downward API.
+    ///
+    /// Defaults to `true`.
+    pub detect_resource: bool,
+
+    /// The sampler deciding which traces are recorded.
+    ///
+    /// Defaults to [`TraceSampler::ParentBasedAlwaysOn`].
+    pub sampler: TraceSampler,
+
+    /// The sampling ratio between `0.0` and `1.0` used by the ratio-based
+    /// samplers.
+    ///
+    /// Defaults to `None`, which samples every trace.
+    pub sampler_arg: Option<f64>,
+
+    /// Where finished spans are sent.
+    ///
+    /// Defaults to [`TraceExporter::Otlp`].
+    pub exporter: TraceExporter,
+
+    /// The transport used by the OTLP exporter.
+    ///
+    /// Defaults to [`OtlpProtocol::Grpc`].
+    pub otlp_protocol: OtlpProtocol,
+
+    /// The collector endpoint used by the OTLP exporter, or `None` to use
+    /// the exporter's default for the selected protocol.
+    ///
+    /// For [`OtlpProtocol::HttpProtobuf`] this is the full URL including the
+    /// `/v1/traces` path.
+    ///
+    /// Defaults to `None`.
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            service_name: None,
+            environment: None,
+            resource_attributes: BTreeMap::new(),
+            detect_resource: true,
+            sampler: TraceSampler::ParentBasedAlwaysOn,
+            sampler_arg: None,
+            exporter: TraceExporter::Otlp,
+            otlp_protocol: OtlpProtocol::Grpc,
+            otlp_endpoint: None,
+        }
+    }
+}
+
+impl TracingConfig {
+    pub(super) fn apply_env(&mut self) -> Result<(), ConfigError> {
+        override_from_env(
+            "OTEL_SERVICE_NAME",
+            &mut self.service_name,
+            parse_optional(parse_value),
+        )?;
+        let mut resource_attributes = None;
+        override_from_env(
+            "OTEL_RESOURCE_ATTRIBUTES",
+            &mut resource_attributes,
+            |value| Ok(Some(parse_resource_attributes(value))),
+        )?;
+        self.resource_attributes
+            .extend(resource_attributes.into_iter().flatten());
+        override_from_env(
+            "GEAR_ENVIRONMENT",
+            &mut self.environment,
+            parse_optional(parse_value),
+        )?;
+        override_from_env(
+            "GEAR_DETECT_RESOURCE",
+            &mut self.detect_resource,
+            parse_bool,
+        )?;
+        override_from_env("OTEL_TRACES_SAMPLER", &mut self.sampler, parse_value)?;
+        override_from_env(
+            "OTEL_TRACES_SAMPLER_ARG",
+            &mut self.sampler_arg,
+            parse_optional(parse_value),
+        )?;
+        override_from_env("OTEL_TRACES_EXPORTER", &mut self.exporter, parse_value)?;
+        override_from_env(
+            "OTEL_EXPORTER_OTLP_PROTOCOL",
+            &mut self.otlp_protocol,
+            parse_value,
+        )?;
+        override_from_env(
+            "OTEL_EXPORTER_OTLP_TRACES_PROTOCOL",
+            &mut self.otlp_protocol,
+            parse_value,
+        )?;
+        let mut sdk_disabled = false;
+        override_from_env("OTEL_SDK_DISABLED", &mut sdk_disabled, parse_bool)?;
+        if sdk_disabled {
+            self.exporter = TraceExporter::None;
+        }
+        Ok(())
+    }
+
+    pub(super) fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(ratio) = self.sampler_arg {
+            if !(0.0..=1.0).contains(&ratio) {
+                return Err(ConfigError::invalid(
+                    "tracing.sampler_arg",
+                    format!("`{ratio}` is not between 0.0 and 1.0"),
+                ));
+            }
+        }
+        if self.service_name.as_deref() == Some("") {
+            return Err(ConfigError::invalid(
+                "tracing.service_name",
+                "must not be empty",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Parses the `key1=value1,key2=value2` format of `OTEL_RESOURCE_ATTRIBUTES`.
+fn parse_resource_attributes(value: &str) -> BTreeMap<String, String> {
+    value
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+define_names! {
+    /// The trace sampler, named as in `OTEL_TRACES_SAMPLER`.
+    pub enum TraceSampler {
+        /// Records every trace.
+        AlwaysOn => "always_on",
+        /// Records no traces.
+        AlwaysOff => "always_off",
+        /// Records a fraction of traces given by
+        /// [`sampler_arg`](TracingConfig::sampler_arg).
+        TraceIdRatio => "traceidratio",
+        /// Follows the parent's decision, recording every root trace.
+        ParentBasedAlwaysOn => "parentbased_always_on",
+        /// Follows the parent's decision, recording no root traces.
+        ParentBasedAlwaysOff => "parentbased_always_off",
+        /// Follows the parent's decision, recording a fraction of root traces
+        /// given by [`sampler_arg`](TracingConfig::sampler_arg).
+        ParentBasedTraceIdRatio => "parentbased_traceidratio",
+    }
+}
+
+define_names! {
+    /// The span exporter, named as in `OTEL_TRACES_EXPORTER`.
+    pub enum TraceExporter {
+        /// Exports spans to an OpenTelemetry collector over OTLP.
+        Otlp => "otlp",
+        /// Writes one JSON line per span to stdout, for local development.
+        Stdout => "stdout" | "console",
+        /// Discards spans. Trace context is still propagated.
+        None => "none",
+    }
+}
+
+define_names! {
+    /// The OTLP transport, named as in `OTEL_EXPORTER_OTLP_PROTOCOL`.
+    pub enum OtlpProtocol {
+        /// OTLP over gRPC, by default to `http://localhost:4317`.
+        Grpc => "grpc",
+        /// OTLP over HTTP with protobuf payloads, by default to
+        /// `http://localhost:4318/v1/traces`.
+        HttpProtobuf => "http/protobuf",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::{env_error, invalid_field, with_env};
+
+    #[test]
+    fn applies_the_opentelemetry_variables() {
+        let mut config = TracingConfig {
+            resource_attributes: BTreeMap::from([
+                ("team".to_string(), "trading".to_string()),
+                ("region".to_string(), "hk".to_string()),
+            ]),
+            ..Default::default()
+        };
+        with_env(
+            &[
+                ("OTEL_SERVICE_NAME", "order-service"),
+                ("OTEL_RESOURCE_ATTRIBUTES", "region=sg, =ignored,zone=a"),
+                ("GEAR_ENVIRONMENT", "staging"),
+                ("GEAR_DETECT_RESOURCE", "false"),
+                ("OTEL_TRACES_SAMPLER", "parentbased_traceidratio"),
+                ("OTEL_TRACES_SAMPLER_ARG", "0.25"),
+                ("OTEL_TRACES_EXPORTER", "console"),
+                ("OTEL_EXPORTER_OTLP_PROTOCOL", "grpc"),
+                ("OTEL_EXPORTER_OTLP_TRACES_PROTOCOL", "http/protobuf"),
+            ],
+            || config.apply_env(),
+        )
+        .unwrap();
+        assert_eq!(config.service_name.as_deref(), Some("order-service"));
+        assert_eq!(
+            config.resource_attributes,
+            BTreeMap::from([
+                ("team".to_string(), "trading".to_string()),
+                ("region".to_string(), "sg".to_string()),
+                ("zone".to_string(), "a".to_string()),
+            ])
+        );
+        assert_eq!(config.environment.as_deref(), Some("staging"));
+        assert!(!config.detect_resource);
+        assert_eq!(config.sampler, TraceSampler::ParentBasedTraceIdRatio);
+        assert_eq!(config.sampler_arg, Some(0.25));
+        assert_eq!(config.exporter, TraceExporter::Stdout);
+        assert_eq!(config.otlp_protocol, OtlpProtocol::HttpProtobuf);
+    }
+
+    #[test]
+    fn disables_the_exporter_with_the_sdk() {
+        let mut config = TracingConfig::default();
+        with_env(&[("OTEL_SDK_DISABLED", "true")], || config.apply_env()).unwrap();
+        assert_eq!(config.exporter, TraceExporter::None);
+
+        let res = with_env(&[("OTEL_TRACES_SAMPLER", "sometimes")], || {
+            TracingConfig::default().apply_env()
+        });
+        assert_eq!(env_error(res), Some("OTEL_TRACES_SAMPLER"));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert_eq!(invalid_field(TracingConfig::default().validate()), None);
+        let config = TracingConfig {
+            sampler_arg: Some(1.5),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid_field(config.validate()),
+            Some("tracing.sampler_arg")
+        );
+        let config = TracingConfig {
+            service_name: Some(String::new()),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid_field(config.validate()),
+            Some("tracing.service_name")
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use poem::http::HeaderValue;
+
+tokio::task_local! {
+    static CURRENT_DEADLINE: Deadline;
+}
+
+/// The point in time by which the caller of a gRPC method expects a response.
+///
+/// The server reads the deadline from the `grpc-timeout` header of incoming
+/// calls, stores it as request data (see
+/// [`RequestExt::deadline`](crate::RequestExt::deadline)) and cancels the
+/// handler with `DEADLINE_EXCEEDED` once it passes. While the handler runs, the
+/// deadline is also available through [`Deadline::current`], which is how the
+/// [`PropagateDeadline`](crate::middlewares::PropagateDeadline) client
+/// middleware forwards the remaining budget to downstream calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// Creates a deadline at the given instant.
+    pub fn at(instant: Instant) -> Self {
+        Self(instant)
+    }
+
+    /// Creates a deadline `timeout` from now.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resulting point in time cannot be represented.
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant::now() + timeout)
+    }
+
+    /// Returns the deadline of the gRPC call being handled by the current
+    /// task, if the caller sent one.
+    ///
+    /// The deadline is scoped to the handler's task; tasks spawned from a
+    /// handler do not inherit it.
+    pub fn current() -> Option<Self> {
+        CURRENT_DEADLINE.try_with(|deadline| *deadline).ok()
+    }
+
+    /// Returns the instant of the deadline.
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// Returns the time left until the deadline, or zero if it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    /// Returns `true` if the deadline has passed.
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// Runs `fut` with this deadline as the [current](Self::current) one.
+    pub(crate) fn scope<F: Future>(self, fut: F) -> impl Future<Output = F::Output> {
+        CURRENT_DEADLINE.scope(self, fut)
+    }
+}
+
+/// Parses a `grpc-timeout` header value, e.g. `100m` for 100 milliseconds.
+///
+/// Returns `None` if the value does not follow the gRPC wire format, which
+/// allows at most eight digits followed by a unit.
+pub(crate) fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let (digits, unit) = value.split_at_checked(value.len().checked_sub(1)?)?;
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let value: u64 = digits.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(value * 3600),
+        "M" => Duration::from_secs(value * 60),
+        "S" => Duration::from_secs(value),
+        "m" => Duration::from_millis(value),
+        "u" => Duration::from_micros(value),
+        "n" => Duration::from_nanos(value),
+        _ => return None,
+    })
+}
+
+/// Formats `timeout` as a `grpc-timeout` header value, using the finest unit
+/// that fits into eight digits and rounding down so the callee never sees a
+/// longer budget than the caller has left.
+pub(crate) fn format_grpc_timeout(timeout: Duration) -> HeaderValue {
+    const MAX: u128 = 99_999_999;
+
+    let nanos = timeout.as_nanos();
+    let (value, unit) = [
+        (1, "n"),
+        (1_000, "u"),
+        (1_000_000, "m"),
+        (1_000_000_000, "S"),
+        (60_000_000_000, "M"),
+        (3_600_000_000_000, "H"),
+    ]
+    .into_iter()
+    .map(|(scale, unit)| (nanos / scale, unit))
+    .find(|(value, _)| *value <= MAX)
+    .unwrap_or((MAX, "H"));
+    HeaderValue::from_str(&format!("{value}{unit}")).expect("valid grpc-timeout header")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_unit() {
+        for (value, expected) in [
+            ("2H", Duration::from_secs(7200)),
+            ("3M", Duration::from_secs(180)),
+            ("4S", Duration::from_secs(4)),
+            ("100m", Duration::from_millis(100)),
+            ("250u", Duration::from_micros(250)),
+            ("999n", Duration::from_nanos(999)),
+            ("0m", Duration::ZERO),
+            ("00000001S", Duration::from_secs(1)),
+        ] {
+            assert_eq!(parse_grpc_timeout(value), Some(expected), "{value}");
+        }
+    }
+
+    #[test]
+    fn parses_the_largest_value() {
+        assert_eq!(
+            parse_grpc_timeout("99999999H"),
+            Some(Duration::from_secs(99_999_999 * 3600))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_values() {
+        for value in [
+            "",
+            "m",
+            "S",
+            "10",
+            "10x",
+            "10s",
+            "10h",
+            "-1m",
+            "+1m",
+            "1.5S",
+            " 1S",
+            "1S ",
+            "1 S",
+            "123456789m",
+            "1é",
+            "é",
+        ] {
+            assert_eq!(parse_grpc_timeout(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn formats_with_the_finest_unit_that_fits() {
+        for (timeout, expected) in [
+            (Duration::ZERO, "0n"),
+            (Duration::from_nanos(99_999_999), "99999999n"),
+            (Duration::from_millis(100), "100000u"),
+            (Duration::from_secs(100), "100000m"),
+            (Duration::from_secs(1_000_000), "1000000S"),
+            (Duration::from_secs(100_000_000), "1666666M"),
+            (Duration::from_secs(10_000_000_000), "2777777H"),
+        ] {
+            assert_eq!(format_grpc_timeout(timeout), expected, "{timeout:?}");
+        }
+    }
+
+    #[test]
+    fn formats_rounding_down() {
+        assert_eq!(
+            format_grpc_timeout(Duration::new(100_000, 999_999_999)),
+            "100000S"
+        );
+        for timeout in [
+            Duration::from_nanos(123_456_789_123),
+            Duration::from_secs(123_456_789),
+        ] {
+            let formatted = format_grpc_timeout(timeout);
+            let parsed = parse_grpc_timeout(formatted.to_str().unwrap()).unwrap();
+            assert!(parsed <= timeout, "{formatted:?} exceeds {timeout:?}");
+        }
+    }
+
+    #[test]
+    fn formats_overflowing_timeouts_as_the_largest_value() {
+        for timeout in [Duration::from_secs(100_000_000 * 3600), Duration::MAX] {
+            assert_eq!(format_grpc_timeout(timeout), "99999999H");
+        }
+    }
+}
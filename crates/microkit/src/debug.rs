@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use prost::Message;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{descriptor::FileDescriptorSet, BuildInfo, GrpcServerConfig};
+
+/// The services registered with [`GrpcServer::add_service`], and the methods
+/// of those described by a registered descriptor set.
+///
+/// [`GrpcServer::add_service`]: crate::GrpcServer::add_service
+#[derive(Default)]
+pub(crate) struct ServiceCatalog {
+    services: Vec<(&'static str, Option<&'static str>)>,
+    methods: HashMap<String, Vec<MethodInfo>>,
+}
+
+#[derive(Clone, Serialize)]
+struct MethodInfo {
+    name: String,
+    path: String,
+    client_streaming: bool,
+    server_streaming: bool,
+}
+
+#[derive(Serialize)]
+struct ServiceInfo<'a> {
+    name: &'static str,
+    /// The middleware registered for this service only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    middleware: Option<&'static str>,
+    /// `None` when no registered descriptor set describes the service.
+    methods: Option<&'a [MethodInfo]>,
+}
+
+impl ServiceCatalog {
+    pub(crate) fn add_service(&mut self, name: &'static str, middleware: Option<&'static str>) {
+        self.services.push((name, middleware));
+    }
+
+    pub(crate) fn add_file_descriptor_set(&mut self, data: &[u8]) {
+        let set = FileDescriptorSet::decode(data).expect("valid file descriptor set");
+        for file in set.file {
+            for service in file.service {
+                let name = match file.package.as_str() {
+                    "" => service.name,
+                    package => format!("{package}.{}", service.name),
+                };
+                let methods = service
+                    .method
+                    .into_iter()
+                    .map(|method| MethodInfo {
+                        path: format!("/{name}/{}", method.name),
+                        name: method.name,
+                        client_streaming: method.client_streaming,
+                        server_streaming: method.server_streaming,
+                    })
+                    .collect();
+                self.methods.insert(name, methods);
+            }
+        }
+    }
+
+    fn report(&self) -> Value {
+        let services = self
+            .services
+            .iter()
+            .map(|&(name, middleware)| ServiceInfo {
+                name,
+                middleware,
+                methods: self.methods.get(name).map(Vec::as_slice),
+            })
+            .collect::<Vec<_>>();
+        json!({ "services": services })
+    }
+}
+
+/// The reports served under `/debug` on the admin listener, computed once at
+/// startup.
+pub(crate) struct DebugInfo {
+    pub(crate) services: Value,
+    pub(crate) config: Value,
+    pub(crate) build: Value,
+    pub(crate) middleware: Value,
+}
+
+impl DebugInfo {
+    pub(crate) fn new(
+        catalog: &ServiceCatalog,
+        config: &GrpcServerConfig,
+        build_info: BuildInfo,
+        middleware: &[&str],
+    ) -> Self {
+        Self {
+            services: catalog.report(),
+            config: serde_json::to_value(config.redacted()).unwrap_or(Value::Null),
+            build: json!({
+                "name": build_info.name,
+                "version": build_info.version,
+                "git_sha": build_info.git_sha,
+                "rustc_version": build_info.rustc_version,
+                "gear_version": env!("CARGO_PKG_VERSION"),
+            }),
+            middleware: json!({ "middleware": middleware }),
+        }
+    }
+}
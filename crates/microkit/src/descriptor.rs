@@ -0,0 +1,151 @@
+//! The subset of `google/protobuf/descriptor.proto` and
+//! `google/api/http.proto` needed to list methods and read `google.api.http`
+//! annotations.
+//!
+//! `prost_types` drops extensions when decoding `MethodOptions`, so the
+//! descriptor messages are redeclared here with the `google.api.http`
+//! extension as a regular field.
+
+use prost::{Message, Oneof};
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct FileDescriptorSet {
+    #[prost(message, repeated, tag = "1")]
+    pub(crate) file: Vec<FileDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct FileDescriptorProto {
+    #[prost(string, tag = "2")]
+    pub(crate) package: String,
+    #[prost(message, repeated, tag = "4")]
+    pub(crate) message_type: Vec<DescriptorProto>,
+    #[prost(message, repeated, tag = "5")]
+    pub(crate) enum_type: Vec<EnumDescriptorProto>,
+    #[prost(message, repeated, tag = "6")]
+    pub(crate) service: Vec<ServiceDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct DescriptorProto {
+    #[prost(string, tag = "1")]
+    pub(crate) name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub(crate) field: Vec<FieldDescriptorProto>,
+    #[prost(message, repeated, tag = "3")]
+    pub(crate) nested_type: Vec<DescriptorProto>,
+    #[prost(message, repeated, tag = "4")]
+    pub(crate) enum_type: Vec<EnumDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct FieldDescriptorProto {
+    #[prost(string, tag = "1")]
+    pub(crate) name: String,
+    #[prost(int32, tag = "4")]
+    pub(crate) label: i32,
+    #[prost(int32, tag = "5")]
+    pub(crate) r#type: i32,
+    #[prost(string, tag = "6")]
+    pub(crate) type_name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct EnumDescriptorProto {
+    #[prost(string, tag = "1")]
+    pub(crate) name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub(crate) value: Vec<EnumValueDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct EnumValueDescriptorProto {
+    #[prost(string, tag = "1")]
+    pub(crate) name: String,
+    #[prost(int32, tag = "2")]
+    pub(crate) number: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct ServiceDescriptorProto {
+    #[prost(string, tag = "1")]
+    pub(crate) name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub(crate) method: Vec<MethodDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct MethodDescriptorProto {
+    #[prost(string, tag = "1")]
+    pub(crate) name: String,
+    #[prost(string, tag = "2")]
+    pub(crate) input_type: String,
+    #[prost(message, optional, tag = "4")]
+    pub(crate) options: Option<MethodOptions>,
+    #[prost(bool, tag = "5")]
+    pub(crate) client_streaming: bool,
+    #[prost(bool, tag = "6")]
+    pub(crate) server_streaming: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct MethodOptions {
+    /// The `google.api.http` extension.
+    #[prost(message, optional, tag = "72295728")]
+    pub(crate) http: Option<HttpRule>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct HttpRule {
+    #[prost(oneof = "Pattern", tags = "2, 3, 4, 5, 6, 8")]
+    pub(crate) pattern: Option<Pattern>,
+    #[prost(string, tag = "7")]
+    pub(crate) body: String,
+    #[prost(string, tag = "12")]
+    pub(crate) response_body: String,
+    #[prost(message, repeated, tag = "11")]
+    pub(crate) additional_bindings: Vec<HttpRule>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub(crate) enum Pattern {
+    #[prost(string, tag = "2")]
+    Get(String),
+    #[prost(string, tag = "3")]
+    Put(String),
+    #[prost(string, tag = "4")]
+    Post(String),
+    #[prost(string, tag = "5")]
+    Delete(String),
+    #[prost(string, tag = "6")]
+    Patch(String),
+    #[prost(message, tag = "8")]
+    Custom(CustomHttpPattern),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct CustomHttpPattern {
+    #[prost(string, tag = "1")]
+    pub(crate) kind: String,
+    #[prost(string, tag = "2")]
+    pub(crate) path: String,
+}
+
+pub(crate) const LABEL_REPEATED: i32 = 3;
+
+pub(crate) const TYPE_DOUBLE: i32 = 1;
+pub(crate) const TYPE_FLOAT: i32 = 2;
+pub(crate) const TYPE_INT64: i32 = 3;
+pub(crate) const TYPE_UINT64: i32 = 4;
+pub(crate) const TYPE_INT32: i32 = 5;
+pub(crate) const TYPE_FIXED64: i32 = 6;
+pub(crate) const TYPE_FIXED32: i32 = 7;
+pub(crate) const TYPE_BOOL: i32 = 8;
+pub(crate) const TYPE_STRING: i32 = 9;
+pub(crate) const TYPE_BYTES: i32 = 12;
+pub(crate) const TYPE_UINT32: i32 = 13;
+pub(crate) const TYPE_ENUM: i32 = 14;
+pub(crate) const TYPE_SFIXED32: i32 = 15;
+pub(crate) const TYPE_SFIXED64: i32 = 16;
+pub(crate) const TYPE_SINT32: i32 = 17;
+pub(crate) const TYPE_SINT64: i32 = 18;
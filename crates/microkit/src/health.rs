@@ -0,0 +1,304 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use futures_util::future::{join_all, BoxFuture};
+use poem::{endpoint::BoxEndpoint, Endpoint, EndpointExt, IntoEndpoint, Request, Response, Result};
+use poem_grpc::{HealthReporter, RouteGrpc, Service};
+use serde::Serialize;
+
+/// How long a readiness check may take by default.
+pub(crate) const DEFAULT_READINESS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The path prefix of the `grpc.health.v1.Health` methods.
+const HEALTH_PATH: &str = "/grpc.health.v1.Health/";
+
+/// Updates the `grpc.health.v1` status of a single registered service.
+///
+/// [`HealthReporter`] only exposes methods that are generic over the service
+/// type, so the type is captured when the service is added.
+pub(crate) type StatusSetter = fn(&HealthReporter, bool);
+
+pub(crate) fn status_setter<S: Service>() -> StatusSetter {
+    |reporter, serving| {
+        if serving {
+            reporter.set_serving::<S>();
+        } else {
+            reporter.set_not_serving::<S>();
+        }
+    }
+}
+
+/// The overall server health, reported under the empty service name as
+/// recommended by the gRPC health checking protocol.
+struct Server;
+
+impl Service for Server {
+    const NAME: &'static str = "";
+}
+
+/// A named asynchronous check that must succeed for the server to be ready.
+pub(crate) struct ReadinessCheck {
+    name: String,
+    check: Box<dyn Fn() -> BoxFuture<'static, Result<(), String>> + Send + Sync>,
+}
+
+impl ReadinessCheck {
+    pub(crate) fn new<F, Fut, E>(name: String, check: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: ToString,
+    {
+        Self {
+            name,
+            check: Box::new(move || {
+                let fut = check();
+                Box::pin(async move { fut.await.map_err(|err| err.to_string()) })
+            }),
+        }
+    }
+}
+
+/// The shared health state behind the gRPC health service and the HTTP probes
+/// on the admin listener.
+pub(crate) struct Health {
+    reporter: HealthReporter,
+    services: Vec<StatusSetter>,
+    checks: Vec<ReadinessCheck>,
+    timeout: Duration,
+    started: AtomicBool,
+    shutting_down: AtomicBool,
+}
+
+/// The JSON body returned by the readiness probe.
+#[derive(Debug, Serialize)]
+pub(crate) struct ReadinessReport {
+    pub(crate) ready: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) checks: BTreeMap<String, String>,
+}
+
+impl Health {
+    pub(crate) fn new(
+        reporter: HealthReporter,
+        services: Vec<StatusSetter>,
+        checks: Vec<ReadinessCheck>,
+        timeout: Duration,
+    ) -> Self {
+        let health = Self {
+            reporter,
+            services,
+            checks,
+            timeout,
+            started: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+        };
+        health.set_status(false);
+        health
+    }
+
+    /// Marks the server and every registered service as `SERVING` once
+    /// startup has completed.
+    pub(crate) fn set_serving(&self) {
+        self.started.store(true, Ordering::Release);
+        if !self.shutting_down.load(Ordering::Acquire) {
+            self.set_status(true);
+        }
+    }
+
+    /// Marks the server and every registered service as `NOT_SERVING` and
+    /// makes the readiness probe fail from now on.
+    pub(crate) fn set_shutting_down(&self) {
+        self.shutting_down.store(true, Ordering::Release);
+        self.set_status(false);
+    }
+
+    fn set_status(&self, serving: bool) {
+        status_setter::<Server>()(&self.reporter, serving);
+        for setter in &self.services {
+            setter(&self.reporter, serving);
+        }
+    }
+
+    /// Runs all readiness checks concurrently, failing those that take longer
+    /// than the timeout.
+    pub(crate) async fn readiness(&self) -> ReadinessReport {
+        if self.shutting_down.load(Ordering::Acquire) {
+            return ReadinessReport {
+                ready: false,
+                checks: BTreeMap::from([("server".to_string(), "shutting down".to_string())]),
+            };
+        }
+        if !self.started.load(Ordering::Acquire) {
+            return ReadinessReport {
+                ready: false,
+                checks: BTreeMap::from([("server".to_string(), "starting".to_string())]),
+            };
+        }
+
+        let results = join_all(self.checks.iter().map(|check| async {
+            tokio::time::timeout(self.timeout, (check.check)())
+                .await
+                .unwrap_or_else(|_| Err(format!("timed out after {:?}", self.timeout)))
+        }))
+        .await;
+        let mut ready = true;
+        let checks = self
+            .checks
+            .iter()
+            .zip(results)
+            .map(|(check, res)| {
+                let status = match res {
+                    Ok(()) => "ok".to_string(),
+                    Err(err) => {
+                        ready = false;
+                        err
+                    }
+                };
+                (check.name.clone(), status)
+            })
+            .collect();
+        ReadinessReport { ready, checks }
+    }
+}
+
+/// Answers health checks ahead of the middleware stack wrapping `app`.
+///
+/// Probes are thereby never shed by the concurrency or rate limits, so an
+/// overloaded server keeps reporting its actual health rather than failing
+/// its probes with `RESOURCE_EXHAUSTED` and being restarted.
+pub(crate) struct BypassHealth {
+    health: BoxEndpoint<'static, Response>,
+    app: BoxEndpoint<'static, Response>,
+}
+
+impl BypassHealth {
+    pub(crate) fn new<S>(health_service: S, app: BoxEndpoint<'static, Response>) -> Self
+    where
+        S: IntoEndpoint<Endpoint = BoxEndpoint<'static, Response>> + Service,
+    {
+        Self {
+            health: RouteGrpc::new().add_service(health_service).boxed(),
+            app,
+        }
+    }
+}
+
+impl Endpoint for BypassHealth {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        if req.uri().path().starts_with(HEALTH_PATH) {
+            self.health.call(req).await
+        } else {
+            self.app.call(req).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future, io, sync::Arc};
+
+    use bytes::Bytes;
+    use futures_util::stream;
+    use poem::{endpoint::make_sync, http::Version, Body, IntoResponse};
+    use poem_grpc::Code;
+
+    use super::*;
+    use crate::{
+        methods::KnownMethods, middlewares::ConcurrencyLimit, status::grpc_status,
+        ConcurrencyConfig,
+    };
+
+    fn health(checks: Vec<ReadinessCheck>, timeout: Duration) -> Health {
+        let (_, reporter) = poem_grpc::health_service();
+        Health::new(reporter, Vec::new(), checks, timeout)
+    }
+
+    #[tokio::test]
+    async fn fails_checks_that_exceed_the_timeout() {
+        let health = health(
+            vec![
+                ReadinessCheck::new("database".to_string(), || {
+                    future::pending::<Result<(), String>>()
+                }),
+                ReadinessCheck::new("cache".to_string(), || async { Ok::<_, String>(()) }),
+            ],
+            Duration::from_millis(20),
+        );
+        health.set_serving();
+
+        let report = health.readiness().await;
+        assert!(!report.ready);
+        assert_eq!(report.checks["cache"], "ok");
+        assert_eq!(report.checks["database"], "timed out after 20ms");
+    }
+
+    #[tokio::test]
+    async fn reports_the_server_lifecycle() {
+        let health = health(Vec::new(), DEFAULT_READINESS_TIMEOUT);
+        let report = health.readiness().await;
+        assert!(!report.ready);
+        assert_eq!(report.checks["server"], "starting");
+
+        health.set_serving();
+        assert!(health.readiness().await.ready);
+
+        health.set_shutting_down();
+        let report = health.readiness().await;
+        assert!(!report.ready);
+        assert_eq!(report.checks["server"], "shutting down");
+    }
+
+    #[tokio::test]
+    async fn answers_health_checks_while_the_concurrency_limit_is_saturated() {
+        let (health_service, reporter) = poem_grpc::health_service();
+        Health::new(reporter, Vec::new(), Vec::new(), DEFAULT_READINESS_TIMEOUT).set_serving();
+        let app = make_sync(|_| {
+            Body::from_bytes_stream(stream::pending::<Result<Bytes, io::Error>>()).into_response()
+        })
+        .with(ConcurrencyLimit::new(
+            &ConcurrencyConfig {
+                max_in_flight: Some(1),
+                ..Default::default()
+            },
+            KnownMethods::default(),
+        ))
+        .boxed();
+        let ep = Arc::new(BypassHealth::new(health_service, app));
+        let grpc = |path: &str, body: &'static [u8]| {
+            Request::builder()
+                .version(Version::HTTP_2)
+                .uri_str(path)
+                .content_type("application/grpc")
+                .body(body)
+        };
+
+        let _streaming = ep
+            .call(grpc("/helloworld.Greeter/SayHello", &[]))
+            .await
+            .unwrap();
+        let rejected = ep
+            .call(grpc("/helloworld.Greeter/SayHello", &[]))
+            .await
+            .unwrap();
+        assert_eq!(
+            grpc_status(rejected.headers()),
+            Some(Code::ResourceExhausted)
+        );
+
+        // An empty `HealthCheckRequest`, answered with `SERVING`.
+        let resp = ep
+            .call(grpc("/grpc.health.v1.Health/Check", &[0, 0, 0, 0, 0]))
+            .await
+            .unwrap();
+        assert_eq!(grpc_status(resp.headers()), None);
+        let body = resp.into_body().into_vec().await.unwrap();
+        assert_eq!(body, [0, 0, 0, 0, 2, 0x08, 1]);
+    }
+}
@@ -8,9 +8,13 @@
 //!
 //! - [`GrpcServer`] — A gRPC server with built-in OpenTelemetry tracing, Prometheus
 //!   metrics, compression, and other production-ready middleware.
+//! - [`GrpcServerConfig`] — Typed server configuration loaded from defaults, an
+//!   optional TOML file and environment variables.
 //! - [`RequestExt`] — An extension trait for gRPC requests that extracts common
 //!   business fields (e.g. `member_id`, `app_id`, `platform`) from request metadata.
 //! - [`middlewares`] — Poem middleware used by codegen-generated gRPC clients.
+//! - [`testing::TestServer`] — Runs a [`GrpcServer`] in-process for integration
+//!   tests, capturing its spans.
 //!
 //! ## Quick Start
 //!
@@ -34,10 +38,45 @@
 ///   `x-micro-from-service` headers to outgoing requests.
 /// - [`middlewares::ClientTracing`] — Creates an OpenTelemetry client span and
 ///   propagates trace context on outgoing requests.
+/// - [`middlewares::PropagateDeadline`] — Forwards the remaining deadline of the
+///   call being handled as `grpc-timeout` on outgoing requests.
 pub mod middlewares;
 
+/// An in-process harness for integration tests of [`GrpcServer`]s.
+pub mod testing;
+
+mod admin;
+mod body;
+mod build_info;
+mod config;
+mod deadline;
+mod debug;
+mod descriptor;
+mod health;
+mod lifecycle;
+mod listener;
+mod methods;
+mod reflection;
 mod request_ext;
+mod serve;
 mod server;
+mod shutdown;
+mod stack;
+mod status;
+mod telemetry;
+mod tls;
+mod transcoding;
 
+pub use build_info::BuildInfo;
+pub use config::{
+    AccessLogConfig, AccessLogField, ClientAuth, CompressionConfig, ConcurrencyConfig,
+    ConcurrencyLimit, ConfigError, CorsConfig, GrpcServerConfig, GrpcWebConfig, ListenAddress,
+    MessageEncoding, MessageSizeConfig, MessageSizeLimit, OtlpProtocol, RateLimitKey,
+    RateLimitRule, TlsConfig, TraceExporter, TraceSampler, TracingConfig,
+};
+pub use deadline::Deadline;
+pub use lifecycle::ShutdownToken;
 pub use request_ext::RequestExt;
 pub use server::GrpcServer;
+pub use stack::BuiltinMiddleware;
+pub use tls::ClientIdentity;
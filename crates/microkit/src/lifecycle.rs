@@ -0,0 +1,324 @@
+use std::{future::Future, io, panic::AssertUnwindSafe, sync::Arc, time::Duration};
+
+use futures_util::{future::BoxFuture, FutureExt};
+use tokio::{sync::watch, task::JoinSet, time::Instant};
+
+use crate::middlewares::panic_message;
+
+/// A signal telling background tasks that the server is shutting down.
+///
+/// Every task started with
+/// [`GrpcServer::spawn_background`](crate::GrpcServer::spawn_background) or
+/// [`spawn_critical`](crate::GrpcServer::spawn_critical) receives a token.
+/// Tasks should stop taking new work once it is [cancelled](Self::cancelled)
+/// and return within the shutdown timeout, after which they are aborted.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use gear_microkit::ShutdownToken;
+///
+/// async fn refresh_loop(shutdown: ShutdownToken) -> Result<(), std::io::Error> {
+///     loop {
+///         tokio::select! {
+///             _ = shutdown.cancelled() => return Ok(()),
+///             _ = tokio::time::sleep(Duration::from_secs(30)) => {
+///                 // refresh the cache
+///             }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShutdownToken(watch::Receiver<bool>);
+
+impl ShutdownToken {
+    /// Resolves once shutdown has been triggered.
+    pub async fn cancelled(&self) {
+        let mut rx = self.0.clone();
+        // An error means the server is gone, which is as good as a shutdown.
+        let _ = rx.wait_for(|cancelled| *cancelled).await;
+    }
+
+    /// Returns `true` if shutdown has been triggered.
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+/// Triggers shutdown from within the server, e.g. when a startup hook or a
+/// critical background task fails.
+#[derive(Clone)]
+pub(crate) struct Shutdown(Arc<watch::Sender<bool>>);
+
+impl Shutdown {
+    pub(crate) fn new() -> Self {
+        Self(Arc::new(watch::Sender::new(false)))
+    }
+
+    pub(crate) fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub(crate) fn token(&self) -> ShutdownToken {
+        ShutdownToken(self.0.subscribe())
+    }
+}
+
+type BoxHook = Box<dyn FnOnce() -> BoxFuture<'static, Result<(), String>> + Send>;
+
+/// A named hook run once, at startup or after shutdown.
+pub(crate) struct Hook {
+    name: String,
+    hook: BoxHook,
+}
+
+impl Hook {
+    pub(crate) fn new<F, Fut, E>(name: String, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: ToString,
+    {
+        Self {
+            name,
+            hook: Box::new(move || {
+                let fut = hook();
+                Box::pin(async move { fut.await.map_err(|err| err.to_string()) })
+            }),
+        }
+    }
+}
+
+/// Runs the startup hooks one after another, stopping at the first failure.
+pub(crate) async fn run_start_hooks(hooks: Vec<Hook>) -> io::Result<()> {
+    for Hook { name, hook } in hooks {
+        tracing::debug!(hook = name, "running startup hook");
+        if let Err(err) = hook().await {
+            return Err(io::Error::other(format!(
+                "startup hook `{name}` failed: {err}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Runs the shutdown hooks one after another, logging failures.
+pub(crate) async fn run_shutdown_hooks(hooks: Vec<Hook>) {
+    for Hook { name, hook } in hooks {
+        tracing::debug!(hook = name, "running shutdown hook");
+        if let Err(err) = hook().await {
+            tracing::warn!(hook = name, error = err, "shutdown hook failed");
+        }
+    }
+}
+
+type BoxTask = Box<dyn FnOnce(ShutdownToken) -> BoxFuture<'static, Result<(), String>> + Send>;
+
+/// A named background task started once the server is ready.
+pub(crate) struct BackgroundTask {
+    name: String,
+    critical: bool,
+    task: BoxTask,
+}
+
+impl BackgroundTask {
+    pub(crate) fn new<F, Fut, E>(name: String, critical: bool, task: F) -> Self
+    where
+        F: FnOnce(ShutdownToken) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: ToString,
+    {
+        Self {
+            name,
+            critical,
+            task: Box::new(move |token| {
+                let fut = task(token);
+                Box::pin(async move { fut.await.map_err(|err| err.to_string()) })
+            }),
+        }
+    }
+}
+
+/// Spawns the background tasks and waits for them to finish.
+///
+/// A critical task failing, by returning an error or panicking, triggers
+/// shutdown, and its error is returned once all tasks have finished. Failures
+/// of other tasks are only logged. Tasks still running `grace` after shutdown
+/// was triggered are aborted.
+pub(crate) async fn supervise(
+    tasks: Vec<BackgroundTask>,
+    shutdown: Shutdown,
+    grace: Duration,
+) -> io::Result<()> {
+    let mut set = JoinSet::new();
+    for BackgroundTask {
+        name,
+        critical,
+        task,
+    } in tasks
+    {
+        let fut = AssertUnwindSafe(task(shutdown.token())).catch_unwind();
+        set.spawn(async move {
+            let res = match fut.await {
+                Ok(res) => res,
+                Err(payload) => Err(format!("panicked: {}", panic_message(&*payload))),
+            };
+            (name, critical, res)
+        });
+    }
+
+    let token = shutdown.token();
+    let mut failure = None;
+    let mut abort_at = None;
+    let mut aborted = false;
+    loop {
+        tokio::select! {
+            joined = set.join_next() => {
+                let Some(joined) = joined else {
+                    break;
+                };
+                match joined {
+                    Ok((_, _, Ok(()))) => {}
+                    Ok((name, true, Err(err))) => {
+                        tracing::error!(task = name, error = err, "critical background task failed");
+                        if failure.is_none() {
+                            failure = Some(io::Error::other(format!(
+                                "critical background task `{name}` failed: {err}"
+                            )));
+                        }
+                        shutdown.trigger();
+                    }
+                    Ok((name, false, Err(err))) => {
+                        tracing::error!(task = name, error = err, "background task failed");
+                    }
+                    // Only tasks aborted after the grace period end up here.
+                    Err(_) => {}
+                }
+            }
+            _ = token.cancelled(), if abort_at.is_none() => {
+                abort_at = Some(Instant::now() + grace);
+            }
+            _ = tokio::time::sleep_until(abort_at.unwrap_or_else(Instant::now)),
+                if abort_at.is_some() && !aborted =>
+            {
+                tracing::warn!(
+                    tasks = set.len(),
+                    "aborting background tasks still running after the shutdown timeout"
+                );
+                set.abort_all();
+                aborted = true;
+            }
+        }
+    }
+    failure.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use super::*;
+
+    const GRACE: Duration = Duration::from_secs(5);
+
+    fn task<Fut>(
+        name: &str,
+        critical: bool,
+        task: impl FnOnce(ShutdownToken) -> Fut + Send + 'static,
+    ) -> BackgroundTask
+    where
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        BackgroundTask::new(name.to_string(), critical, task)
+    }
+
+    /// A task that returns once shutdown has been triggered.
+    fn until_shutdown(name: &str) -> BackgroundTask {
+        task(name, true, |token| async move {
+            token.cancelled().await;
+            Ok(())
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shuts_down_when_a_critical_task_fails() {
+        let shutdown = Shutdown::new();
+        let tasks = vec![
+            until_shutdown("server"),
+            task("refresh", true, |_| async {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Err("connection refused".to_string())
+            }),
+        ];
+        let err = supervise(tasks, shutdown.clone(), GRACE).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "critical background task `refresh` failed: connection refused"
+        );
+        assert!(shutdown.token().is_cancelled());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shuts_down_when_a_critical_task_panics() {
+        let shutdown = Shutdown::new();
+        let tasks = vec![
+            until_shutdown("server"),
+            task("refresh", true, |_| async { panic!("cache poisoned") }),
+        ];
+        let err = supervise(tasks, shutdown.clone(), GRACE).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "critical background task `refresh` failed: panicked: cache poisoned"
+        );
+        assert!(shutdown.token().is_cancelled());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_logs_other_failures() {
+        let shutdown = Shutdown::new();
+        let tasks = vec![
+            task("warmup", false, |_| async { Err("timed out".to_string()) }),
+            task("metrics", false, |_| async { panic!("no registry") }),
+            task("refresh", true, |_| async { Ok(()) }),
+        ];
+        supervise(tasks, shutdown.clone(), GRACE).await.unwrap();
+        assert!(!shutdown.token().is_cancelled());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_tasks_after_the_grace_period() {
+        struct SetOnDrop(Arc<AtomicBool>);
+
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::Release);
+            }
+        }
+
+        let shutdown = Shutdown::new();
+        let dropped = Arc::new(AtomicBool::new(false));
+        let guard = SetOnDrop(dropped.clone());
+        let tasks = vec![
+            until_shutdown("server"),
+            task("stuck", false, move |_| async move {
+                let _guard = guard;
+                future::pending().await
+            }),
+        ];
+        let start = Instant::now();
+        let supervisor = tokio::spawn(supervise(tasks, shutdown.clone(), GRACE));
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(!supervisor.is_finished());
+
+        shutdown.trigger();
+        supervisor.await.unwrap().unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(60) + GRACE);
+        assert!(dropped.load(Ordering::Acquire));
+    }
+}
@@ -0,0 +1,271 @@
+use std::{io, path::Path};
+
+use poem::{
+    http::uri::Scheme,
+    listener::{Acceptor, AcceptorExt, BoxAcceptor, TcpAcceptor},
+    web::{LocalAddr, RemoteAddr},
+};
+use socket2::{SockRef, TcpKeepalive};
+
+use crate::{
+    tls::{ClientRegistry, TlsAcceptor},
+    GrpcServerConfig, ListenAddress,
+};
+
+/// Binds every gRPC listener described by `config` and combines them into a
+/// single acceptor.
+///
+/// All sockets are bound before any of them is served, so a single
+/// unavailable address fails startup. With TLS enabled, the identities of
+/// verified client certificates are recorded in `client_registry`.
+pub(crate) async fn bind(
+    config: &GrpcServerConfig,
+    client_registry: &ClientRegistry,
+) -> io::Result<BoxAcceptor> {
+    let listener = tokio::net::TcpListener::bind(&config.address).await?;
+    let mut acceptor = tcp_acceptor(listener, config, client_registry)?;
+    for address in &config.listeners {
+        let other = match address {
+            ListenAddress::Tcp(address) => {
+                let listener = tokio::net::TcpListener::bind(address).await?;
+                tcp_acceptor(listener, config, client_registry)?
+            }
+            ListenAddress::Unix(path) => bind_unix(path)?,
+            ListenAddress::Fd(fd) => from_fd(*fd, config, client_registry)?,
+        };
+        acceptor = acceptor.combine(other).boxed();
+    }
+    Ok(acceptor)
+}
+
+/// Wraps a bound TCP listener, applying TLS if configured.
+///
+/// TCP keepalive is configured on the listening socket; accepted connections
+/// inherit it.
+fn tcp_acceptor(
+    listener: tokio::net::TcpListener,
+    config: &GrpcServerConfig,
+    client_registry: &ClientRegistry,
+) -> io::Result<BoxAcceptor> {
+    if let Some(keepalive) = config.tcp_keepalive {
+        SockRef::from(&listener).set_tcp_keepalive(&TcpKeepalive::new().with_time(keepalive))?;
+    }
+    let acceptor = TcpAcceptor::from_tokio(listener)?;
+    Ok(match &config.tls {
+        Some(tls) => TlsAcceptor::new(acceptor, tls, client_registry.clone())?.boxed(),
+        None => acceptor.boxed(),
+    })
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> io::Result<BoxAcceptor> {
+    use std::os::unix::{
+        fs::{FileTypeExt, MetadataExt},
+        net::{UnixListener, UnixStream},
+    };
+
+    use poem::listener::UnixAcceptor;
+
+    // A socket file outlives the process that bound it, so remove the one
+    // left behind by a previous run, unless a process is still listening on
+    // it. Anything else at the path is an error.
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            match UnixStream::connect(path) {
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                    std::fs::remove_file(path)?;
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("Unix socket `{}` is in use", path.display()),
+                    ));
+                }
+            }
+        }
+    }
+    let listener = UnixListener::bind(path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to bind Unix socket `{}`: {err}", path.display()),
+        )
+    })?;
+    listener.set_nonblocking(true)?;
+    let metadata = std::fs::symlink_metadata(path)?;
+    Ok(UnixSocketAcceptor {
+        inner: UnixAcceptor::from_std(listener)?,
+        path: path.to_path_buf(),
+        file: (metadata.dev(), metadata.ino()),
+    }
+    .boxed())
+}
+
+/// A Unix domain socket listener that removes its socket file when dropped.
+#[cfg(unix)]
+struct UnixSocketAcceptor {
+    inner: poem::listener::UnixAcceptor,
+    path: std::path::PathBuf,
+    /// The device and inode of the socket file, so a file bound at the same
+    /// path by another process in the meantime is left alone.
+    file: (u64, u64),
+}
+
+#[cfg(unix)]
+impl Acceptor for UnixSocketAcceptor {
+    type Io = tokio::net::UnixStream;
+
+    fn local_addr(&self) -> Vec<LocalAddr> {
+        self.inner.local_addr()
+    }
+
+    async fn accept(&mut self) -> io::Result<(Self::Io, LocalAddr, RemoteAddr, Scheme)> {
+        self.inner.accept().await
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocketAcceptor {
+    fn drop(&mut self) {
+        use std::os::unix::fs::MetadataExt;
+
+        let Ok(metadata) = std::fs::symlink_metadata(&self.path) else {
+            return;
+        };
+        if (metadata.dev(), metadata.ino()) != self.file {
+            return;
+        }
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            tracing::warn!(
+                path = %self.path.display(),
+                error = %err,
+                "failed to remove Unix socket file",
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn bind_unix(path: &Path) -> io::Result<BoxAcceptor> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "Unix socket `{}` is not supported on this platform",
+            path.display()
+        ),
+    ))
+}
+
+/// Takes ownership of an inherited listening socket.
+///
+/// The descriptor is only taken over once it is known to be a listening
+/// stream socket, so a mistyped one is left open for its actual owner.
+#[cfg(unix)]
+fn from_fd(
+    fd: i32,
+    config: &GrpcServerConfig,
+    client_registry: &ClientRegistry,
+) -> io::Result<BoxAcceptor> {
+    use std::os::{
+        fd::{BorrowedFd, FromRawFd, OwnedFd},
+        unix::net::UnixListener,
+    };
+
+    use poem::listener::UnixAcceptor;
+    use socket2::{Socket, Type};
+
+    // SAFETY: the descriptor was handed to this process for it to serve and
+    // is not closed while borrowed; a descriptor that is not open merely
+    // fails the checks below.
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let socket = SockRef::from(&borrowed);
+    let socket_type = socket.r#type().map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("file descriptor {fd} is not a socket: {err}"),
+        )
+    })?;
+    if socket_type != Type::STREAM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("file descriptor {fd} is not a stream socket"),
+        ));
+    }
+    #[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+    if !socket.is_listener()? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("file descriptor {fd} is not a listening socket"),
+        ));
+    }
+
+    // SAFETY: the descriptor is an open socket, and validation rejects
+    // listing it twice, so it is owned only once.
+    let socket = Socket::from(unsafe { OwnedFd::from_raw_fd(fd) });
+    socket.set_nonblocking(true)?;
+    if socket.local_addr()?.as_socket().is_some() {
+        let listener = tokio::net::TcpListener::from_std(socket.into())?;
+        tcp_acceptor(listener, config, client_registry)
+    } else {
+        Ok(UnixAcceptor::from_std(UnixListener::from(socket))?.boxed())
+    }
+}
+
+#[cfg(not(unix))]
+fn from_fd(
+    fd: i32,
+    _config: &GrpcServerConfig,
+    _client_registry: &ClientRegistry,
+) -> io::Result<BoxAcceptor> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("inheriting file descriptor {fd} is not supported on this platform"),
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{os::unix::net::UnixListener, path::PathBuf};
+
+    use super::*;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("gear-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn replaces_a_stale_socket_file() {
+        let path = socket_path("stale");
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let acceptor = bind_unix(&path).unwrap();
+        assert!(path.exists());
+        drop(acceptor);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn keeps_a_socket_another_process_listens_on() {
+        let path = socket_path("live");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let err = bind_unix(&path).err().expect("the socket is in use");
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_to_remove_other_files() {
+        let path = socket_path("file");
+        std::fs::write(&path, "").unwrap();
+
+        assert!(bind_unix(&path).is_err());
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    collections::HashSet,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, RwLock},
+};
+
+use futures_util::FutureExt;
+use poem::{Endpoint, Request, Result};
+
+use crate::GrpcServerConfig;
+
+/// The `method` metric label of calls to paths no service answers.
+const UNKNOWN: &str = "unknown";
+
+/// The method paths served by a server, used to label metrics.
+///
+/// Labeling metrics with the raw request path would let clients create an
+/// unbounded number of time series by calling made-up paths, so paths are
+/// only used as labels once they are known: named in the configuration, or
+/// answered by the router at least once (see [`RecordMethods`]).
+#[derive(Clone, Default)]
+pub(crate) struct KnownMethods(Arc<RwLock<HashSet<String>>>);
+
+impl KnownMethods {
+    /// Creates the set from the methods named in `config`.
+    pub(crate) fn new(config: &GrpcServerConfig) -> Self {
+        let methods = config
+            .concurrency
+            .methods
+            .keys()
+            .chain(config.message_size.methods.keys())
+            .chain(config.rate_limits.iter().flat_map(|rule| &rule.methods))
+            .cloned()
+            .collect();
+        Self(Arc::new(RwLock::new(methods)))
+    }
+
+    /// Returns the label of the method at `path`: the path itself if it is
+    /// known, `"unknown"` otherwise.
+    pub(crate) fn label<'a>(&self, path: &'a str) -> &'a str {
+        match self.0.read().expect("known methods lock").contains(path) {
+            true => path,
+            false => UNKNOWN,
+        }
+    }
+
+    fn insert(&self, path: &str) {
+        if self.0.read().expect("known methods lock").contains(path) {
+            return;
+        }
+        self.0
+            .write()
+            .expect("known methods lock")
+            .insert(path.to_string());
+    }
+}
+
+/// Wraps the router, adding the path of every call it answers to the
+/// [`KnownMethods`].
+///
+/// Calls to unknown paths fail with an error before reaching a service, so
+/// only successful results, and panics raised by a handler, make a path
+/// known.
+pub(crate) struct RecordMethods<E> {
+    inner: E,
+    known: KnownMethods,
+}
+
+impl<E> RecordMethods<E> {
+    pub(crate) fn new(inner: E, known: KnownMethods) -> Self {
+        Self { inner, known }
+    }
+}
+
+impl<E: Endpoint> Endpoint for RecordMethods<E> {
+    type Output = E::Output;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let path = req.uri().path().to_string();
+        match AssertUnwindSafe(self.inner.call(req)).catch_unwind().await {
+            Ok(Ok(resp)) => {
+                self.known.insert(&path);
+                Ok(resp)
+            }
+            Ok(Err(err)) => Err(err),
+            Err(payload) => {
+                self.known.insert(&path);
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+}
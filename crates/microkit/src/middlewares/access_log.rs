@@ -0,0 +1,320 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc,
+    },
+    thread,
+    time::{Instant, SystemTime},
+};
+
+use once_cell::sync::Lazy;
+use opentelemetry::{
+    trace::{TraceContextExt, TraceId},
+    Context,
+};
+use opentelemetry_sdk::trace::{IdGenerator, RandomIdGenerator};
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use poem_grpc::Code;
+use prometheus::{register_int_counter, IntCounter};
+use serde::Serialize;
+
+use crate::{body::on_grpc_status, status::code_name, AccessLogConfig, AccessLogField};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// The number of records waiting to be written before new ones are dropped.
+const QUEUE_CAPACITY: usize = 8192;
+
+static DROPPED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "micro_access_log_dropped_total",
+        "number of access log records dropped because stdout could not keep up"
+    )
+    .expect("failed to create micro_access_log_dropped_total counter")
+});
+
+/// Queues records for a dedicated thread writing them to stdout, so that a
+/// slow or blocked stdout never stalls the runtime's worker threads.
+static WRITER: Lazy<SyncSender<String>> = Lazy::new(|| {
+    let (sender, receiver) = mpsc::sync_channel::<String>(QUEUE_CAPACITY);
+    thread::Builder::new()
+        .name("gear-access-log".to_string())
+        .spawn(move || {
+            for line in receiver {
+                let _ = writeln!(io::stdout().lock(), "{line}");
+            }
+        })
+        .expect("failed to spawn the access log writer thread");
+    sender
+});
+
+/// Writes one JSON line per call to stdout, as described by
+/// [`AccessLogConfig`].
+///
+/// The record is written once the response body has been sent, so the
+/// duration and status cover the whole call, including streamed responses.
+/// Records are handed to a writer thread; while it lags behind by more than
+/// [`QUEUE_CAPACITY`] records, new ones are dropped and counted in
+/// `micro_access_log_dropped_total`.
+pub(crate) struct AccessLog {
+    config: Arc<AccessLogConfig>,
+    writer: SyncSender<String>,
+}
+
+impl AccessLog {
+    pub(crate) fn new(config: &AccessLogConfig) -> Self {
+        Self::with_writer(config, WRITER.clone())
+    }
+
+    /// Creates the middleware sending its records to `writer` rather than to
+    /// the stdout writer thread.
+    fn with_writer(config: &AccessLogConfig, writer: SyncSender<String>) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+            writer,
+        }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for AccessLog {
+    type Output = AccessLogEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        AccessLogEndpoint {
+            inner: ep,
+            config: self.config.clone(),
+            writer: self.writer.clone(),
+        }
+    }
+}
+
+pub(crate) struct AccessLogEndpoint<E> {
+    inner: E,
+    config: Arc<AccessLogConfig>,
+    writer: SyncSender<String>,
+}
+
+#[derive(Serialize)]
+struct Record {
+    timestamp: String,
+    method: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    caller: Option<String>,
+    grpc_status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grpc_code: Option<&'static str>,
+    duration_ms: f64,
+    peer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    span_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    app_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    platform: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    member_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    real_ip: Option<String>,
+}
+
+impl<E: Endpoint> Endpoint for AccessLogEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let start = Instant::now();
+        let span_context = Context::current().span().span_context().clone();
+        // Calls without a span, e.g. when tracing is disabled, are sampled
+        // with a random ID at the same ratio.
+        let trace_id = match span_context.is_valid() {
+            true => span_context.trace_id(),
+            false => RandomIdGenerator::default().new_trace_id(),
+        };
+        let sampled = is_sampled(trace_id, self.config.sample_ratio);
+        if !sampled && !self.config.always_log_errors {
+            return self.inner.call(req).await.map(IntoResponse::into_response);
+        }
+
+        let config = &self.config;
+        let field = |field: AccessLogField, value: Option<&str>| {
+            value.map(|value| {
+                if config.redact.contains(&field) {
+                    REDACTED.to_string()
+                } else {
+                    value.to_string()
+                }
+            })
+        };
+        let peer = match req.remote_addr().as_socket_addr() {
+            Some(addr) => addr.to_string(),
+            None => req.remote_addr().to_string(),
+        };
+        let record = Record {
+            timestamp: String::new(),
+            method: req.uri().path().to_string(),
+            caller: field(AccessLogField::Caller, req.header("x-micro-from-service")),
+            grpc_status: None,
+            grpc_code: None,
+            duration_ms: 0.0,
+            peer: field(AccessLogField::Peer, Some(&peer)).unwrap_or_default(),
+            trace_id: span_context
+                .is_valid()
+                .then(|| span_context.trace_id().to_string()),
+            span_id: span_context
+                .is_valid()
+                .then(|| span_context.span_id().to_string()),
+            app_id: field(AccessLogField::AppId, req.header("app-id")),
+            platform: field(AccessLogField::Platform, req.header("x-platform")),
+            member_id: field(AccessLogField::MemberId, req.header("member-id")),
+            device_id: field(AccessLogField::DeviceId, req.header("x-device-id")),
+            real_ip: field(AccessLogField::RealIp, req.header("x-real-ip")),
+        };
+
+        let res = self.inner.call(req).await;
+        let writer = self.writer.clone();
+        let finish = move |code: Option<Code>| {
+            if !sampled && code == Some(Code::Ok) {
+                return;
+            }
+            let mut record = record;
+            record.timestamp = humantime::format_rfc3339_millis(SystemTime::now()).to_string();
+            record.grpc_status = code.map(|code| code.as_u16());
+            record.grpc_code = code.map(code_name);
+            record.duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+            if let Ok(line) = serde_json::to_string(&record) {
+                write(&writer, line);
+            }
+        };
+        match res {
+            Ok(resp) => Ok(on_grpc_status(resp.into_response(), finish)),
+            Err(err) => {
+                finish(None);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Queues `line` for the writer thread, dropping it if the queue is full.
+fn write(writer: &SyncSender<String>, line: String) {
+    // Also fails if the writer thread has stopped, which it only does if
+    // writing to stdout panicked.
+    if writer.try_send(line).is_err() {
+        DROPPED.inc();
+    }
+}
+
+/// Decides whether the call of `trace_id` is logged, using the same scheme as
+/// the OpenTelemetry `TraceIdRatioBased` sampler.
+fn is_sampled(trace_id: TraceId, ratio: f64) -> bool {
+    if ratio >= 1.0 {
+        return true;
+    }
+    let bytes = trace_id.to_bytes();
+    let value = u64::from_be_bytes(bytes[8..16].try_into().expect("8 bytes")) >> 1;
+    value < (ratio * (1u64 << 63) as f64) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Receiver;
+
+    use poem::{endpoint::make_sync, EndpointExt};
+    use poem_grpc::Status;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::status::status_response;
+
+    fn trace_id(low: u64) -> TraceId {
+        TraceId::from_bytes((u128::from(u64::MAX) << 64 | u128::from(low)).to_be_bytes())
+    }
+
+    /// Calls an endpoint answering with `code` through the access log and
+    /// returns the record written, if any.
+    async fn log_call(config: AccessLogConfig, code: Code, req: Request) -> Option<Value> {
+        let (sender, receiver): (_, Receiver<String>) = mpsc::sync_channel(1);
+        let ep = make_sync(move |_| status_response(&Status::new(code)))
+            .with(AccessLog::with_writer(&config, sender));
+        ep.call(req)
+            .await
+            .unwrap()
+            .into_body()
+            .into_bytes()
+            .await
+            .unwrap();
+        drop(ep);
+        receiver
+            .recv()
+            .ok()
+            .map(|line| serde_json::from_str(&line).unwrap())
+    }
+
+    #[test]
+    fn samples_by_trace_id() {
+        for low in [0, 1, u64::MAX / 2, u64::MAX] {
+            assert!(!is_sampled(trace_id(low), 0.0));
+            assert!(is_sampled(trace_id(low), 1.0));
+        }
+        assert!(is_sampled(trace_id(0), 0.5));
+        assert!(is_sampled(trace_id(u64::MAX / 2), 0.5));
+        assert!(!is_sampled(trace_id(u64::MAX / 2 + 1), 0.5));
+        assert!(!is_sampled(trace_id(u64::MAX), 0.5));
+
+        let ids = RandomIdGenerator::default();
+        let sampled = (0..10_000)
+            .filter(|_| is_sampled(ids.new_trace_id(), 0.5))
+            .count();
+        assert!((4_500..5_500).contains(&sampled), "{sampled} sampled");
+    }
+
+    #[tokio::test]
+    async fn redacts_fields() {
+        let config = AccessLogConfig {
+            enabled: true,
+            redact: vec![AccessLogField::MemberId, AccessLogField::Peer],
+            ..AccessLogConfig::default()
+        };
+        let req = Request::builder()
+            .uri_str("/helloworld.Greeter/SayHello")
+            .header("member-id", "1001")
+            .header("app-id", "longbridge")
+            .finish();
+        let record = log_call(config, Code::Ok, req).await.unwrap();
+        assert_eq!(record["method"], "/helloworld.Greeter/SayHello");
+        assert_eq!(record["grpc_status"], 0);
+        assert_eq!(record["member_id"], REDACTED);
+        assert_eq!(record["peer"], REDACTED);
+        assert_eq!(record["app_id"], "longbridge");
+        assert!(record.get("platform").is_none());
+    }
+
+    #[tokio::test]
+    async fn logs_unsampled_errors_only_when_configured() {
+        let config = AccessLogConfig {
+            enabled: true,
+            sample_ratio: 0.0,
+            ..AccessLogConfig::default()
+        };
+        assert!(log_call(config.clone(), Code::Ok, Request::default())
+            .await
+            .is_none());
+        let record = log_call(config.clone(), Code::Unavailable, Request::default())
+            .await
+            .unwrap();
+        assert_eq!(record["grpc_status"], 14);
+        assert_eq!(record["grpc_code"], "UNAVAILABLE");
+
+        let config = AccessLogConfig {
+            always_log_errors: false,
+            ..config
+        };
+        assert!(log_call(config, Code::Unavailable, Request::default())
+            .await
+            .is_none());
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    collections::HashSet,
+    future::Future,
+    io::{self, Read, Write},
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flate2::{
+    read::{DeflateDecoder, GzDecoder, ZlibDecoder},
+    write::{DeflateEncoder, GzEncoder},
+};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use http_body_util::combinators::BoxBody;
+use poem::{
+    http::HeaderValue, Body, Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+use poem_grpc::{Code, Status};
+use tokio::task::JoinHandle;
+
+use crate::{
+    middlewares::DecodeLimit,
+    status::{is_grpc, status_response},
+    CompressionConfig, MessageEncoding,
+};
+
+const GRPC_ENCODING: &str = "grpc-encoding";
+const GRPC_ACCEPT_ENCODING: &str = "grpc-accept-encoding";
+
+/// The encodings compressed requests are accepted in.
+const SUPPORTED: &str = "gzip, deflate, zstd";
+
+/// The size a request message may be decompressed to when no
+/// `max_decoding_message_size` applies, so that a small compressed message
+/// cannot inflate into unbounded memory.
+const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// The size from which messages are compressed or decompressed on the
+/// blocking thread pool instead of the worker thread polling the body.
+const BLOCKING_SIZE: usize = 64 * 1024;
+
+/// Negotiates gRPC message compression as described by
+/// [`CompressionConfig`].
+///
+/// Compressed request messages are decompressed before they reach the
+/// service, which then sees an uncompressed call. Messages inflating beyond
+/// the decode limit of `MessageSizeLimit`, or [`MAX_DECOMPRESSED_SIZE`]
+/// without one, fail the call with `RESOURCE_EXHAUSTED`. Response messages are
+/// compressed with the first configured encoding the client accepts.
+///
+/// Messages of at least [`BLOCKING_SIZE`] bytes are compressed or
+/// decompressed with [`spawn_blocking`](tokio::task::spawn_blocking), so
+/// that large payloads do not stall the other tasks of a worker thread;
+/// smaller ones are processed in place.
+pub(crate) struct Compression {
+    settings: Arc<Settings>,
+}
+
+struct Settings {
+    encodings: Vec<MessageEncoding>,
+    min_size: usize,
+    exclude_methods: HashSet<String>,
+}
+
+impl Compression {
+    pub(crate) fn new(config: &CompressionConfig) -> Self {
+        Self {
+            settings: Arc::new(Settings {
+                encodings: config.encodings.clone(),
+                min_size: config.min_size,
+                exclude_methods: config.exclude_methods.iter().cloned().collect(),
+            }),
+        }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for Compression {
+    type Output = CompressionEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        CompressionEndpoint {
+            inner: ep,
+            settings: self.settings.clone(),
+        }
+    }
+}
+
+pub(crate) struct CompressionEndpoint<E> {
+    inner: E,
+    settings: Arc<Settings>,
+}
+
+impl<E: Endpoint> Endpoint for CompressionEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        if !is_grpc(req.headers()) {
+            return self.inner.call(req).await.map(IntoResponse::into_response);
+        }
+
+        let mut decode_limit = None;
+        if let Some(value) = req.headers_mut().remove(GRPC_ENCODING) {
+            match value.to_str().unwrap_or_default() {
+                "identity" => {}
+                name => match name.parse::<MessageEncoding>() {
+                    Ok(encoding) => {
+                        let limit = req
+                            .data::<DecodeLimit>()
+                            .cloned()
+                            .unwrap_or_else(|| DecodeLimit::new(MAX_DECOMPRESSED_SIZE));
+                        let body = MessageBody::wrap(
+                            req.take_body(),
+                            Codec::Decompress(encoding, limit.clone()),
+                        );
+                        req.set_body(body);
+                        decode_limit = Some(limit);
+                    }
+                    Err(_) => {
+                        let status = Status::new(Code::Unimplemented)
+                            .with_message(format!("unsupported grpc-encoding `{name}`"));
+                        let mut resp = status_response(&status);
+                        resp.headers_mut()
+                            .insert(GRPC_ACCEPT_ENCODING, HeaderValue::from_static(SUPPORTED));
+                        return Ok(resp);
+                    }
+                },
+            }
+        }
+        let encoding = if self.settings.exclude_methods.contains(req.uri().path()) {
+            None
+        } else {
+            req.header(GRPC_ACCEPT_ENCODING)
+                .and_then(|accepted| self.settings.negotiate(accepted))
+        };
+
+        let mut resp = self.inner.call(req).await?.into_response();
+        if let Some(status) = decode_limit.as_ref().and_then(DecodeLimit::status) {
+            resp = status_response(&status);
+        }
+        resp.headers_mut()
+            .insert(GRPC_ACCEPT_ENCODING, HeaderValue::from_static(SUPPORTED));
+        match encoding {
+            Some(encoding)
+                if is_grpc(resp.headers()) && !resp.headers().contains_key(GRPC_ENCODING) =>
+            {
+                resp.headers_mut()
+                    .insert(GRPC_ENCODING, HeaderValue::from_static(encoding.as_str()));
+                let body = resp.take_body();
+                resp.set_body(MessageBody::wrap(
+                    body,
+                    Codec::Compress(encoding, self.settings.min_size),
+                ));
+                Ok(resp)
+            }
+            _ => Ok(resp),
+        }
+    }
+}
+
+impl Settings {
+    /// Returns the most preferred configured encoding listed in a
+    /// `grpc-accept-encoding` header.
+    fn negotiate(&self, accepted: &str) -> Option<MessageEncoding> {
+        let accepted = accepted
+            .split(',')
+            .filter_map(|name| name.trim().parse::<MessageEncoding>().ok())
+            .collect::<Vec<_>>();
+        self.encodings
+            .iter()
+            .copied()
+            .find(|encoding| accepted.contains(encoding))
+    }
+}
+
+#[derive(Clone)]
+enum Codec {
+    /// Decompresses messages, failing those that exceed the limit once
+    /// decompressed.
+    Decompress(MessageEncoding, DecodeLimit),
+    /// Compresses messages of at least the given size.
+    Compress(MessageEncoding, usize),
+}
+
+impl Codec {
+    /// Returns whether a message with the compressed flag `compressed` and
+    /// `len` bytes is rewritten rather than passed through.
+    fn applies(&self, compressed: bool, len: usize) -> bool {
+        match self {
+            Self::Decompress(..) => compressed,
+            Self::Compress(_, min_size) => !compressed && len >= *min_size,
+        }
+    }
+
+    /// Rewrites a message, returning it framed with its length prefix.
+    fn rewrite(&self, compressed: bool, message: Bytes) -> io::Result<Bytes> {
+        let (compressed, message) = match self {
+            Self::Decompress(encoding, limit) if compressed => {
+                let message = decompress(*encoding, &message, limit.max())?;
+                limit.check_decompressed(message.len())?;
+                (false, message.into())
+            }
+            Self::Compress(encoding, min_size) if !compressed && message.len() >= *min_size => {
+                (true, compress(*encoding, &message)?.into())
+            }
+            _ => (compressed, message),
+        };
+        let mut frame = BytesMut::with_capacity(5 + message.len());
+        frame.put_u8(compressed.into());
+        frame.put_u32(message.len() as u32);
+        frame.extend_from_slice(&message);
+        Ok(frame.freeze())
+    }
+}
+
+/// Rewrites every length-prefixed message of a gRPC body, passing trailers
+/// through.
+struct MessageBody {
+    inner: BoxBody<Bytes, io::Error>,
+    buf: BytesMut,
+    codec: Codec,
+    /// A large message being rewritten on the blocking thread pool.
+    pending: Option<JoinHandle<io::Result<Bytes>>>,
+}
+
+impl MessageBody {
+    fn wrap(body: Body, codec: Codec) -> Body {
+        Body::from(BoxBody::new(Self {
+            inner: body.into(),
+            buf: BytesMut::new(),
+            codec,
+            pending: None,
+        }))
+    }
+
+    /// Takes the next complete message from the buffer, if any, along with
+    /// its compressed flag.
+    fn next_message(&mut self) -> Option<(bool, Bytes)> {
+        if self.buf.len() < 5 {
+            return None;
+        }
+        let len = u32::from_be_bytes([self.buf[1], self.buf[2], self.buf[3], self.buf[4]]) as usize;
+        if self.buf.len() < 5 + len {
+            return None;
+        }
+        let compressed = self.buf.get_u8() == 1;
+        self.buf.advance(4);
+        Some((compressed, self.buf.split_to(len).freeze()))
+    }
+}
+
+impl HttpBody for MessageBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(pending) = &mut this.pending {
+                let frame = ready!(Pin::new(pending).poll(cx)).unwrap_or_else(|err| {
+                    Err(io::Error::other(format!(
+                        "failed to rewrite gRPC message: {err}"
+                    )))
+                });
+                this.pending = None;
+                return Poll::Ready(Some(frame.map(Frame::data)));
+            }
+            if let Some((compressed, message)) = this.next_message() {
+                if message.len() >= BLOCKING_SIZE && this.codec.applies(compressed, message.len()) {
+                    let codec = this.codec.clone();
+                    this.pending = Some(tokio::task::spawn_blocking(move || {
+                        codec.rewrite(compressed, message)
+                    }));
+                    continue;
+                }
+                let frame = this.codec.rewrite(compressed, message)?;
+                return Poll::Ready(Some(Ok(Frame::data(frame))));
+            }
+            match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => this.buf.extend_from_slice(&data),
+                    Err(frame) if this.buf.is_empty() => return Poll::Ready(Some(Ok(frame))),
+                    Err(_) => return Poll::Ready(Some(Err(truncated()))),
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None if this.buf.is_empty() => return Poll::Ready(None),
+                None => return Poll::Ready(Some(Err(truncated()))),
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.buf.is_empty() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "truncated gRPC message")
+}
+
+fn compress(encoding: MessageEncoding, data: &[u8]) -> io::Result<Vec<u8>> {
+    match encoding {
+        MessageEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        // Raw DEFLATE, as sent and expected by poem-grpc clients.
+        MessageEncoding::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        MessageEncoding::Zstd => zstd::bulk::compress(data, 0),
+    }
+}
+
+/// Decompresses `data`, reading at most one byte more than `max` so that
+/// oversized messages are detected without inflating them completely.
+fn decompress(encoding: MessageEncoding, data: &[u8], max: usize) -> io::Result<Vec<u8>> {
+    let decoder: Box<dyn Read + '_> = match encoding {
+        MessageEncoding::Gzip => Box::new(GzDecoder::new(data)),
+        // Other implementations send DEFLATE in a zlib wrapper, recognizable by
+        // its header checksum.
+        MessageEncoding::Deflate if is_zlib(data) => Box::new(ZlibDecoder::new(data)),
+        MessageEncoding::Deflate => Box::new(DeflateDecoder::new(data)),
+        MessageEncoding::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+    };
+    let mut buf = Vec::new();
+    decoder.take(max as u64 + 1).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+fn is_zlib(data: &[u8]) -> bool {
+    matches!(data, [cmf, flg, ..] if cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream;
+    use poem::endpoint::make;
+
+    use super::*;
+    use crate::status::grpc_status;
+
+    fn frame(message: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(message);
+        frame
+    }
+
+    fn chunked(data: &[u8], chunk_size: usize) -> Body {
+        let chunks = data
+            .chunks(chunk_size)
+            .map(|chunk| Ok::<_, io::Error>(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        Body::from_bytes_stream(stream::iter(chunks))
+    }
+
+    async fn rewrite(codec: Codec, data: Vec<u8>, chunk_size: usize) -> Bytes {
+        let body = MessageBody::wrap(chunked(&data, chunk_size), codec);
+        body.into_bytes().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn compresses_small_and_large_messages_alike() {
+        let small = b"x".repeat(2 * 1024);
+        let large = b"y".repeat(2 * BLOCKING_SIZE);
+        let data = [frame(&small), frame(b"tiny"), frame(&large)].concat();
+
+        for encoding in [
+            MessageEncoding::Gzip,
+            MessageEncoding::Deflate,
+            MessageEncoding::Zstd,
+        ] {
+            let compressed = rewrite(Codec::Compress(encoding, 1024), data.clone(), 4096).await;
+            assert!(compressed.len() < data.len());
+            let limit = DecodeLimit::new(MAX_DECOMPRESSED_SIZE);
+            let decompressed =
+                rewrite(Codec::Decompress(encoding, limit), compressed.to_vec(), 7).await;
+            assert_eq!(decompressed, data);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_decompression_bombs_without_a_message_size_limit() {
+        let bomb = zstd::bulk::compress(&vec![0; MAX_DECOMPRESSED_SIZE + 1], 0).unwrap();
+        assert!(bomb.len() < 64 * 1024);
+        let mut data = vec![1];
+        data.extend_from_slice(&(bomb.len() as u32).to_be_bytes());
+        data.extend_from_slice(&bomb);
+
+        let ep = Compression::new(&CompressionConfig::default()).transform(make(
+            |req: Request| async move {
+                let res = req.into_body().into_bytes().await;
+                assert!(res.is_err());
+                status_response(&Status::new(Code::Internal))
+            },
+        ));
+        let req = Request::builder()
+            .content_type("application/grpc")
+            .header(GRPC_ENCODING, "zstd")
+            .body(chunked(&data, 1024));
+        let resp = ep.call(req).await.unwrap();
+        assert_eq!(grpc_status(resp.headers()), Some(Code::ResourceExhausted));
+    }
+}
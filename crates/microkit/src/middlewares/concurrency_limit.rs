@@ -0,0 +1,403 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use poem_grpc::{Code, Status};
+use prometheus::{
+    opts, register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGauge, IntGaugeVec,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    body::on_grpc_status, methods::KnownMethods, status::status_response, ConcurrencyConfig,
+};
+
+const GLOBAL: &str = "global";
+
+static METRICS: Lazy<Metrics> = Lazy::new(|| Metrics {
+    in_flight: register_int_gauge_vec!(
+        opts!(
+            "micro_concurrency_in_flight",
+            "number of rpc calls holding a concurrency slot"
+        ),
+        &["limit"]
+    )
+    .expect("failed to create micro_concurrency_in_flight gauge"),
+    queue_depth: register_int_gauge_vec!(
+        opts!(
+            "micro_concurrency_queue_depth",
+            "number of rpc calls waiting for a concurrency slot"
+        ),
+        &["limit"]
+    )
+    .expect("failed to create micro_concurrency_queue_depth gauge"),
+    rejections: register_int_counter_vec!(
+        opts!(
+            "micro_concurrency_rejections_total",
+            "number of rpc calls rejected by a concurrency limit"
+        ),
+        &["method", "limit", "reason"]
+    )
+    .expect("failed to create micro_concurrency_rejections_total counter"),
+});
+
+/// Bounds the number of calls processed at the same time, globally and per
+/// method, as described by [`ConcurrencyConfig`].
+///
+/// Calls that cannot take a slot wait in a bounded queue; calls that find the
+/// queue full, or wait longer than the queue timeout, fail with
+/// `RESOURCE_EXHAUSTED`. A call holds its slots until its response stream
+/// has ended, so streaming calls count against the limits for as long as
+/// they last.
+pub(crate) struct ConcurrencyLimit {
+    limits: Arc<Limits>,
+}
+
+struct Limits {
+    global: Option<Limiter>,
+    methods: HashMap<String, Limiter>,
+    queue_timeout: Option<Duration>,
+    known_methods: KnownMethods,
+    metrics: Metrics,
+}
+
+#[derive(Clone)]
+struct Metrics {
+    in_flight: IntGaugeVec,
+    queue_depth: IntGaugeVec,
+    rejections: IntCounterVec,
+}
+
+/// A single limit: a semaphore with one permit per slot and a count of the
+/// calls waiting for one.
+struct Limiter {
+    name: String,
+    semaphore: Arc<Semaphore>,
+    max_queued: u32,
+    queued: AtomicU32,
+}
+
+/// Why a call was rejected, used as the `reason` metric label.
+#[derive(Clone, Copy)]
+enum Rejection {
+    QueueFull,
+    QueueTimeout,
+}
+
+impl ConcurrencyLimit {
+    pub(crate) fn new(config: &ConcurrencyConfig, known_methods: KnownMethods) -> Self {
+        let global = config
+            .max_in_flight
+            .map(|max_in_flight| Limiter::new(GLOBAL, max_in_flight, config.max_queued));
+        let methods = config
+            .methods
+            .iter()
+            .map(|(method, limit)| {
+                (
+                    method.clone(),
+                    Limiter::new(method, limit.max_in_flight, limit.max_queued),
+                )
+            })
+            .collect();
+        Self {
+            limits: Arc::new(Limits {
+                global,
+                methods,
+                queue_timeout: config.queue_timeout,
+                known_methods,
+                metrics: METRICS.clone(),
+            }),
+        }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for ConcurrencyLimit {
+    type Output = ConcurrencyLimitEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ConcurrencyLimitEndpoint {
+            inner: ep,
+            limits: self.limits.clone(),
+        }
+    }
+}
+
+pub(crate) struct ConcurrencyLimitEndpoint<E> {
+    inner: E,
+    limits: Arc<Limits>,
+}
+
+impl<E: Endpoint> Endpoint for ConcurrencyLimitEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let limits = &*self.limits;
+        let method = req.uri().path();
+
+        // The method slot is taken first, so calls queued on a busy method do
+        // not hold global slots that other methods could use.
+        let mut slots = Vec::with_capacity(2);
+        for limiter in [limits.methods.get(method), limits.global.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            match limiter.acquire(limits).await {
+                Ok(slot) => slots.push(slot),
+                Err(rejection) => {
+                    limits
+                        .metrics
+                        .rejections
+                        .with_label_values(&[
+                            limits.known_methods.label(method),
+                            &limiter.name,
+                            rejection.as_str(),
+                        ])
+                        .inc();
+                    return Ok(status_response(
+                        &Status::new(Code::ResourceExhausted)
+                            .with_message(rejection.message(&limiter.name)),
+                    ));
+                }
+            }
+        }
+
+        let resp = self.inner.call(req).await?.into_response();
+        Ok(on_grpc_status(resp, move |_| drop(slots)))
+    }
+}
+
+impl Limiter {
+    fn new(name: &str, max_in_flight: u32, max_queued: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            semaphore: Arc::new(Semaphore::new(max_in_flight as usize)),
+            max_queued,
+            queued: AtomicU32::new(0),
+        }
+    }
+
+    async fn acquire(&self, limits: &Limits) -> Result<Slot, Rejection> {
+        let permit = match self.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => self.wait(limits).await?,
+        };
+        let in_flight = limits.metrics.in_flight.with_label_values(&[&self.name]);
+        in_flight.inc();
+        Ok(Slot {
+            _permit: permit,
+            in_flight,
+        })
+    }
+
+    /// Waits in the queue for a permit, if the queue has room.
+    async fn wait(&self, limits: &Limits) -> Result<OwnedSemaphorePermit, Rejection> {
+        let reserved = self
+            .queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+                (queued < self.max_queued).then_some(queued + 1)
+            });
+        if reserved.is_err() {
+            return Err(Rejection::QueueFull);
+        }
+
+        let queue_depth = limits.metrics.queue_depth.with_label_values(&[&self.name]);
+        queue_depth.inc();
+        let _queued = Queued {
+            limiter: self,
+            queue_depth,
+        };
+
+        let acquire = self.semaphore.clone().acquire_owned();
+        let permit = match limits.queue_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, acquire)
+                .await
+                .map_err(|_| Rejection::QueueTimeout)?,
+            None => acquire.await,
+        };
+        Ok(permit.expect("concurrency semaphore is never closed"))
+    }
+}
+
+/// A slot taken from a [`Limiter`], released on drop.
+struct Slot {
+    _permit: OwnedSemaphorePermit,
+    in_flight: IntGauge,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.in_flight.dec();
+    }
+}
+
+/// A place in the queue of a [`Limiter`], given up on drop, including when
+/// the waiting call is cancelled.
+struct Queued<'a> {
+    limiter: &'a Limiter,
+    queue_depth: IntGauge,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.queue_depth.dec();
+        self.limiter.queued.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Rejection {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::QueueFull => "queue_full",
+            Self::QueueTimeout => "queue_timeout",
+        }
+    }
+
+    fn message(self, limit: &str) -> String {
+        match self {
+            Self::QueueFull => format!("concurrency limit `{limit}` reached"),
+            Self::QueueTimeout => format!("timed out waiting for concurrency limit `{limit}`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io};
+
+    use bytes::Bytes;
+    use futures_util::stream;
+    use poem::{
+        endpoint::{make_sync, BoxEndpoint},
+        Body, EndpointExt,
+    };
+
+    use super::*;
+    use crate::{config::ConcurrencyLimit as MethodLimit, status::grpc_status};
+
+    const METHOD: &str = "/helloworld.Greeter/SayHello";
+
+    /// Returns an endpoint behind the limits of `config` whose responses
+    /// stream until they are dropped.
+    fn endpoint(config: &ConcurrencyConfig) -> Arc<BoxEndpoint<'static, Response>> {
+        let ep = make_sync(|_| {
+            Body::from_bytes_stream(stream::pending::<Result<Bytes, io::Error>>()).into_response()
+        })
+        .with(ConcurrencyLimit::new(config, KnownMethods::default()))
+        .boxed();
+        Arc::new(ep)
+    }
+
+    async fn call(ep: &BoxEndpoint<'static, Response>, method: &str) -> Response {
+        ep.call(Request::builder().uri_str(method).finish())
+            .await
+            .unwrap()
+    }
+
+    fn is_rejected(resp: &Response) -> bool {
+        grpc_status(resp.headers()) == Some(Code::ResourceExhausted)
+    }
+
+    #[tokio::test]
+    async fn holds_slots_while_the_response_streams() {
+        let ep = endpoint(&ConcurrencyConfig {
+            max_in_flight: Some(1),
+            ..Default::default()
+        });
+        let streaming = call(&ep, METHOD).await;
+        assert!(!is_rejected(&streaming));
+        assert!(is_rejected(&call(&ep, METHOD).await));
+
+        drop(streaming);
+        let resp = call(&ep, METHOD).await;
+        assert!(!is_rejected(&resp));
+    }
+
+    #[tokio::test]
+    async fn releases_slots_when_the_response_ends() {
+        let ep = make_sync(|_| "done")
+            .with(ConcurrencyLimit::new(
+                &ConcurrencyConfig {
+                    max_in_flight: Some(1),
+                    ..Default::default()
+                },
+                KnownMethods::default(),
+            ))
+            .boxed();
+        for _ in 0..3 {
+            let resp = call(&ep, METHOD).await;
+            assert!(!is_rejected(&resp));
+            assert_eq!(resp.into_body().into_string().await.unwrap(), "done");
+        }
+    }
+
+    #[tokio::test]
+    async fn queues_calls_up_to_the_bound() {
+        let ep = endpoint(&ConcurrencyConfig {
+            max_in_flight: Some(1),
+            max_queued: 1,
+            ..Default::default()
+        });
+        let streaming = call(&ep, METHOD).await;
+        let queued = tokio::spawn({
+            let ep = ep.clone();
+            async move { call(&ep, METHOD).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!queued.is_finished());
+
+        let resp = call(&ep, METHOD).await;
+        assert!(is_rejected(&resp));
+        assert_eq!(
+            resp.headers().get("grpc-message").unwrap(),
+            "concurrency%20limit%20%60global%60%20reached"
+        );
+
+        drop(streaming);
+        assert!(!is_rejected(&queued.await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn rejects_calls_queued_beyond_the_timeout() {
+        let ep = endpoint(&ConcurrencyConfig {
+            max_in_flight: Some(1),
+            max_queued: 1,
+            queue_timeout: Some(Duration::from_millis(20)),
+            ..Default::default()
+        });
+        let _streaming = call(&ep, METHOD).await;
+        let resp = call(&ep, METHOD).await;
+        assert!(is_rejected(&resp));
+        assert!(resp
+            .headers()
+            .get("grpc-message")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("timed%20out"));
+    }
+
+    #[tokio::test]
+    async fn limits_methods_separately() {
+        let ep = endpoint(&ConcurrencyConfig {
+            methods: BTreeMap::from([(
+                METHOD.to_string(),
+                MethodLimit {
+                    max_in_flight: 1,
+                    max_queued: 0,
+                },
+            )]),
+            ..Default::default()
+        });
+        let _streaming = call(&ep, METHOD).await;
+        assert!(is_rejected(&call(&ep, METHOD).await));
+        let other = call(&ep, "/helloworld.Greeter/SayGoodbye").await;
+        assert!(!is_rejected(&other));
+    }
+}
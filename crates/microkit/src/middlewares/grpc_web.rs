@@ -0,0 +1,359 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{ready, Context, Poll},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::{BufMut, Bytes, BytesMut};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use http_body_util::combinators::BoxBody;
+use poem::{
+    http::{header, HeaderMap, HeaderValue, Method, Version},
+    middleware::Cors,
+    Body, Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+use poem_grpc::{Code, Status};
+
+use crate::{status::status_response, CorsConfig};
+
+const GRPC: &str = "application/grpc";
+const GRPC_WEB: &str = "application/grpc-web";
+const GRPC_WEB_TEXT: &str = "application/grpc-web-text";
+
+/// The flag marking a gRPC-Web frame that carries trailers instead of a
+/// message.
+const TRAILERS_FLAG: u8 = 0x80;
+
+/// Translates gRPC-Web calls to native gRPC and their responses back.
+///
+/// Requests with an `application/grpc-web[-text][+codec]` content type are
+/// rewritten to `application/grpc[+codec]`. The body of `grpc-web-text`
+/// requests is decoded as it arrives, so the message size limits apply to it
+/// like to any other request. In the response, trailers are moved into a final body
+/// frame, since browsers cannot read HTTP trailers, and the body is
+/// base64-encoded for `grpc-web-text`. Other requests pass through unchanged.
+pub(crate) struct GrpcWeb;
+
+impl<E: Endpoint> Middleware<E> for GrpcWeb {
+    type Output = GrpcWebEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        GrpcWebEndpoint { inner: ep }
+    }
+}
+
+pub(crate) struct GrpcWebEndpoint<E> {
+    inner: E,
+}
+
+impl<E: Endpoint> Endpoint for GrpcWebEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        let Some((text, codec)) = req.content_type().and_then(parse_content_type) else {
+            return self.inner.call(req).await.map(IntoResponse::into_response);
+        };
+        let codec = codec.to_string();
+
+        if let Ok(content_type) = HeaderValue::from_str(&format!("{GRPC}{codec}")) {
+            req.headers_mut().insert(header::CONTENT_TYPE, content_type);
+        }
+        req.headers_mut().remove(header::CONTENT_LENGTH);
+        // Generated services only accept HTTP/2, but web calls may arrive over
+        // HTTP/1.1.
+        let version = req.version();
+        req.set_version(Version::HTTP_2);
+        let invalid = Arc::new(OnceLock::new());
+        if text {
+            let body = req.take_body();
+            req.set_body(TextBody::wrap(body, invalid.clone()));
+        }
+
+        let mut resp = self.inner.call(req).await?.into_response();
+        if let Some(status) = invalid.get() {
+            return Ok(into_web_response(status_response(status), text, &codec));
+        }
+        resp.set_version(version);
+        Ok(into_web_response(resp, text, &codec))
+    }
+}
+
+/// Returns whether `content_type` is `grpc-web-text` and the codec suffix,
+/// e.g. `+proto`, if it is a gRPC-Web content type.
+fn parse_content_type(content_type: &str) -> Option<(bool, &str)> {
+    let (text, rest) = if let Some(rest) = content_type.strip_prefix(GRPC_WEB_TEXT) {
+        (true, rest)
+    } else {
+        (false, content_type.strip_prefix(GRPC_WEB)?)
+    };
+    (rest.is_empty() || rest.starts_with('+')).then_some((text, rest))
+}
+
+/// Decodes a `grpc-web-text` body, which may consist of several padded
+/// base64 segments when the client flushed the stream more than once.
+fn decode_text(body: &[u8]) -> Result<Vec<u8>, base64::DecodeError> {
+    let mut decoded = Vec::with_capacity(body.len() / 4 * 3);
+    let mut rest = body;
+    while !rest.is_empty() {
+        let end = rest
+            .chunks(4)
+            .position(|group| group.ends_with(b"="))
+            .map_or(rest.len(), |i| (i + 1) * 4)
+            .min(rest.len());
+        decoded.extend(STANDARD.decode(&rest[..end])?);
+        rest = &rest[end..];
+    }
+    Ok(decoded)
+}
+
+/// Decodes the body of a `grpc-web-text` request as it arrives.
+///
+/// If the body is not valid base64, it fails and the status the call ends
+/// with is stored in `invalid`.
+struct TextBody {
+    inner: BoxBody<Bytes, io::Error>,
+    /// The start of a base64 group whose remaining bytes have not arrived yet.
+    partial: BytesMut,
+    invalid: Arc<OnceLock<Status>>,
+    done: bool,
+}
+
+impl TextBody {
+    fn wrap(body: Body, invalid: Arc<OnceLock<Status>>) -> Body {
+        Body::from(BoxBody::new(Self {
+            inner: body.into(),
+            partial: BytesMut::new(),
+            invalid,
+            done: false,
+        }))
+    }
+
+    /// Decodes the complete base64 groups of `data`, keeping the rest for the
+    /// next chunk.
+    fn decode(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        self.partial.extend_from_slice(data);
+        let complete = self.partial.split_to(self.partial.len() / 4 * 4);
+        self.finish(&complete)
+    }
+
+    fn finish(&self, data: &[u8]) -> io::Result<Bytes> {
+        decode_text(data).map(Bytes::from).map_err(|err| {
+            let message = format!("invalid grpc-web-text body: {err}");
+            let _ = self
+                .invalid
+                .set(Status::new(Code::InvalidArgument).with_message(&message));
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })
+    }
+}
+
+impl HttpBody for TextBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+            let data = match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => this.decode(&data),
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => {
+                    this.done = true;
+                    // Fails unless the body ended with a complete group.
+                    let rest = std::mem::take(&mut this.partial);
+                    this.finish(&rest)
+                }
+            };
+            match data {
+                Ok(data) if data.is_empty() => continue,
+                other => return Poll::Ready(Some(other.map(Frame::data))),
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done || (self.partial.is_empty() && self.inner.is_end_stream())
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+fn into_web_response(resp: Response, text: bool, codec: &str) -> Response {
+    let (mut parts, body) = resp.into_parts();
+    let content_type = if text { GRPC_WEB_TEXT } else { GRPC_WEB };
+    if let Ok(content_type) = HeaderValue::from_str(&format!("{content_type}{codec}")) {
+        parts.headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let body = WebBody {
+        inner: body.into(),
+        text,
+    };
+    Response::from_parts(parts, Body::from(BoxBody::new(body)))
+}
+
+/// Encodes a gRPC response body for gRPC-Web clients.
+struct WebBody {
+    inner: BoxBody<Bytes, io::Error>,
+    text: bool,
+}
+
+impl WebBody {
+    fn encode(&self, data: Bytes) -> Bytes {
+        if self.text {
+            STANDARD.encode(data).into()
+        } else {
+            data
+        }
+    }
+}
+
+impl HttpBody for WebBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        let frame = match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => frame,
+            other => return Poll::Ready(other),
+        };
+        let data = match frame.into_data() {
+            Ok(data) => data,
+            Err(frame) => match frame.into_trailers() {
+                Ok(trailers) => encode_trailers(&trailers),
+                Err(_) => Bytes::new(),
+            },
+        };
+        Poll::Ready(Some(Ok(Frame::data(this.encode(data)))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+/// Encodes `trailers` as a gRPC-Web trailers frame.
+fn encode_trailers(trailers: &HeaderMap) -> Bytes {
+    let mut block = Vec::new();
+    for (name, value) in trailers {
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.extend_from_slice(b": ");
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
+    let mut frame = BytesMut::with_capacity(5 + block.len());
+    frame.put_u8(TRAILERS_FLAG);
+    frame.put_u32(block.len() as u32);
+    frame.extend_from_slice(&block);
+    frame.freeze()
+}
+
+/// Builds the CORS middleware applied to gRPC-Web calls.
+pub(crate) fn cors(config: &CorsConfig) -> Cors {
+    let mut cors = Cors::new()
+        .allow_method(Method::POST)
+        .allow_credentials(config.allow_credentials)
+        .expose_headers(["grpc-status", "grpc-message", "grpc-status-details-bin"])
+        .expose_headers(config.exposed_headers.iter().map(String::as_str))
+        .allow_headers(config.allowed_headers.iter().map(String::as_str))
+        .max_age(config.max_age.as_secs().try_into().unwrap_or(i32::MAX));
+    for origin in &config.allowed_origins {
+        cors = if origin.contains('*') {
+            cors.allow_origin_regex(origin)
+        } else {
+            cors.allow_origin(origin.as_str())
+        };
+    }
+    cors
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream;
+
+    use super::*;
+
+    #[test]
+    fn decodes_a_single_segment() {
+        assert_eq!(decode_text(b"AAAAAAJoaQ==").unwrap(), b"\0\0\0\0\x02hi");
+        assert_eq!(decode_text(b"aGk6").unwrap(), b"hi:");
+        assert_eq!(decode_text(b"").unwrap(), b"");
+    }
+
+    #[test]
+    fn decodes_several_padded_segments() {
+        // "a", "bc" and "def", each flushed and padded separately.
+        assert_eq!(decode_text(b"YQ==YmM=ZGVm").unwrap(), b"abcdef");
+        assert_eq!(decode_text(b"YQ==YQ==YQ==").unwrap(), b"aaa");
+        assert_eq!(decode_text(b"ZGVmYQ==").unwrap(), b"defa");
+    }
+
+    #[test]
+    fn rejects_invalid_base64() {
+        for body in [
+            &b"YQ="[..],
+            b"YQ",
+            b"Y",
+            b"Y===",
+            b"YQ==YQ",
+            b"!!!!",
+            b"YQ==\r\n",
+        ] {
+            assert!(decode_text(body).is_err(), "{body:?}");
+        }
+    }
+
+    async fn decode_chunks(chunks: &[&'static [u8]]) -> (io::Result<Bytes>, Option<Status>) {
+        let invalid = Arc::new(OnceLock::new());
+        let chunks = chunks
+            .iter()
+            .map(|chunk| Ok::<_, io::Error>(Bytes::from_static(chunk)));
+        let body = TextBody::wrap(
+            Body::from_bytes_stream(stream::iter(chunks.collect::<Vec<_>>())),
+            invalid.clone(),
+        );
+        let decoded = body.into_bytes().await.map_err(io::Error::other);
+        (decoded, invalid.get().cloned())
+    }
+
+    #[tokio::test]
+    async fn decodes_bodies_split_at_any_byte() {
+        let body: &'static [u8] = b"YQ==YmM=ZGVm";
+        for i in 0..=body.len() {
+            for j in i..=body.len() {
+                let (decoded, invalid) =
+                    decode_chunks(&[&body[..i], &body[i..j], &body[j..]]).await;
+                assert_eq!(decoded.unwrap(), &b"abcdef"[..], "split at {i} and {j}");
+                assert!(invalid.is_none());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_bodies_ending_with_an_incomplete_group() {
+        let (decoded, invalid) = decode_chunks(&[b"YQ==", b"Ym"]).await;
+        assert!(decoded.is_err());
+        assert_eq!(invalid.unwrap().code(), Code::InvalidArgument);
+    }
+}
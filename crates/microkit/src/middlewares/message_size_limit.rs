@@ -0,0 +1,260 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{ready, Context, Poll},
+};
+
+use bytes::Bytes;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use http_body_util::combinators::BoxBody;
+use poem::{Body, Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use poem_grpc::{Code, Status};
+
+use crate::{
+    body::MessageFramer,
+    status::{is_grpc, status_headers, status_response},
+    MessageSizeConfig,
+};
+
+/// Rejects request and response messages exceeding the limits of
+/// [`MessageSizeConfig`] with `RESOURCE_EXHAUSTED`.
+///
+/// Request messages are checked against their length prefix as soon as it
+/// arrives, before the message is buffered by the service. The body then
+/// fails, and whatever status poem-grpc derives from that failure is
+/// replaced. Response messages are checked as they are sent; an oversized
+/// one ends the call in its place.
+///
+/// `GrpcWeb` and `Transcode` sit outside this layer: the former streams
+/// decoded messages through it, the latter bounds the JSON body it reads
+/// with the same limits.
+pub(crate) struct MessageSizeLimit {
+    config: Arc<MessageSizeConfig>,
+}
+
+impl MessageSizeLimit {
+    pub(crate) fn new(config: &MessageSizeConfig) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+        }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for MessageSizeLimit {
+    type Output = MessageSizeLimitEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        MessageSizeLimitEndpoint {
+            inner: ep,
+            config: self.config.clone(),
+        }
+    }
+}
+
+pub(crate) struct MessageSizeLimitEndpoint<E> {
+    inner: E,
+    config: Arc<MessageSizeConfig>,
+}
+
+impl<E: Endpoint> Endpoint for MessageSizeLimitEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        if !is_grpc(req.headers()) {
+            return self.inner.call(req).await.map(IntoResponse::into_response);
+        }
+
+        let limit = self.config.limit(req.uri().path());
+        let decode_limit = limit.max_decoding_message_size.map(|max| {
+            let decode_limit = DecodeLimit::new(max);
+            let body = req.take_body();
+            req.set_body(LimitBody::wrap(body, Limit::Decode(decode_limit.clone())));
+            // Lets `Compression` bound the size of decompressed messages too.
+            req.set_data(decode_limit.clone());
+            decode_limit
+        });
+
+        let mut resp = self.inner.call(req).await?.into_response();
+        if let Some(status) = decode_limit.as_ref().and_then(DecodeLimit::status) {
+            return Ok(status_response(&status));
+        }
+        if decode_limit.is_some() || limit.max_encoding_message_size.is_some() {
+            let body = resp.take_body();
+            resp.set_body(LimitBody::wrap(
+                body,
+                Limit::Encode(limit.max_encoding_message_size, decode_limit),
+            ));
+        }
+        Ok(resp)
+    }
+}
+
+/// The maximum size of the request messages of a call, shared with the
+/// layers that decode them.
+#[derive(Clone)]
+pub(crate) struct DecodeLimit {
+    max: usize,
+    /// The status the call fails with once a message was too large.
+    exceeded: Arc<OnceLock<Status>>,
+}
+
+impl DecodeLimit {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            max,
+            exceeded: Arc::default(),
+        }
+    }
+
+    /// The maximum size of a request message in bytes.
+    pub(crate) fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Fails the call if a request message of `len` bytes is too large.
+    pub(crate) fn check(&self, len: usize) -> io::Result<()> {
+        if len <= self.max {
+            return Ok(());
+        }
+        Err(self.exceed(format!(
+            "request message of {len} bytes exceeds the limit of {} bytes",
+            self.max
+        )))
+    }
+
+    /// Fails the call if a decompressed request message, read up to one byte
+    /// past the limit, is too large.
+    pub(crate) fn check_decompressed(&self, len: usize) -> io::Result<()> {
+        if len <= self.max {
+            return Ok(());
+        }
+        Err(self.exceed(format!(
+            "decompressed request message exceeds the limit of {} bytes",
+            self.max
+        )))
+    }
+
+    fn exceed(&self, message: String) -> io::Error {
+        let _ = self
+            .exceeded
+            .set(Status::new(Code::ResourceExhausted).with_message(&message));
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+
+    /// Returns the status the call fails with, once a message was too large.
+    pub(crate) fn status(&self) -> Option<Status> {
+        self.exceeded.get().cloned()
+    }
+}
+
+enum Limit {
+    /// Checks request messages.
+    Decode(DecodeLimit),
+    /// Checks response messages against the maximum, if any, and reports a
+    /// request message exceeding the decode limit in the trailers.
+    Encode(Option<usize>, Option<DecodeLimit>),
+}
+
+/// Checks the length prefix of every message of a gRPC body, passing the
+/// data through unbuffered.
+struct LimitBody {
+    inner: BoxBody<Bytes, io::Error>,
+    limit: Limit,
+    framer: MessageFramer,
+    /// The trailers ending a response in place of an oversized message.
+    status: Option<Status>,
+    done: bool,
+}
+
+impl LimitBody {
+    fn wrap(body: Body, limit: Limit) -> Body {
+        Body::from(BoxBody::new(Self {
+            inner: body.into(),
+            limit,
+            framer: MessageFramer::default(),
+            status: None,
+            done: false,
+        }))
+    }
+
+    /// Scans `data` for length prefixes, returning the offset of the first
+    /// one announcing an oversized message, if any, along with its length.
+    fn scan(&mut self, data: &[u8], max: usize) -> Option<(usize, usize)> {
+        let mut pos = 0;
+        while let Some((offset, len)) = self.framer.next_message(data, &mut pos) {
+            if len > max {
+                return Some((offset, len));
+            }
+        }
+        None
+    }
+}
+
+impl HttpBody for LimitBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        if let Some(status) = this.status.take() {
+            this.done = true;
+            return Poll::Ready(Some(Ok(Frame::trailers(status_headers(&status)))));
+        }
+        if this.done {
+            return Poll::Ready(None);
+        }
+        let frame = match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => frame,
+            other => return Poll::Ready(other),
+        };
+        let frame = match frame.into_data() {
+            Ok(data) => data,
+            Err(frame) => {
+                // Reports a rejected request message, which the service may
+                // have seen as an internal error.
+                let status = match (&this.limit, frame.trailers_ref()) {
+                    (Limit::Encode(_, Some(decode_limit)), Some(_)) => decode_limit.status(),
+                    _ => None,
+                };
+                return Poll::Ready(Some(Ok(match status {
+                    Some(status) => Frame::trailers(status_headers(&status)),
+                    None => frame,
+                })));
+            }
+        };
+        let max = match &this.limit {
+            Limit::Decode(decode_limit) => Some(decode_limit.max),
+            Limit::Encode(max, _) => *max,
+        };
+        if let Some((offset, len)) = max.and_then(|max| this.scan(&frame, max)) {
+            let max = match &this.limit {
+                Limit::Decode(decode_limit) => {
+                    return Poll::Ready(Some(decode_limit.check(len).map(|()| Frame::data(frame))))
+                }
+                Limit::Encode(max, _) => max.unwrap_or_default(),
+            };
+            let status = Status::new(Code::ResourceExhausted).with_message(format!(
+                "response message of {len} bytes exceeds the limit of {max} bytes"
+            ));
+            if offset > 0 {
+                this.status = Some(status);
+                return Poll::Ready(Some(Ok(Frame::data(frame.slice(..offset)))));
+            }
+            this.done = true;
+            return Poll::Ready(Some(Ok(Frame::trailers(status_headers(&status)))));
+        }
+        Poll::Ready(Some(Ok(Frame::data(frame))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.status.is_none() && (self.done || self.inner.is_end_stream())
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
@@ -1,10 +1,32 @@
+mod access_log;
 mod add_client_headers;
 mod client_tracing;
+mod compression;
+mod concurrency_limit;
+mod grpc_web;
+mod message_size_limit;
+mod propagate_deadline;
+mod rate_limit;
+mod recover_panic;
 mod request_duration_metrics;
+mod server_deadline;
+mod set_client_identity;
 mod set_current_service;
+mod stream_telemetry;
 
+pub(crate) use access_log::AccessLog;
 pub use add_client_headers::AddClientHeaders;
 pub use client_tracing::ClientTracing;
+pub(crate) use compression::Compression;
+pub(crate) use concurrency_limit::ConcurrencyLimit;
+pub(crate) use grpc_web::{cors, GrpcWeb};
+pub(crate) use message_size_limit::{DecodeLimit, MessageSizeLimit};
+pub use propagate_deadline::PropagateDeadline;
+pub(crate) use rate_limit::RateLimit;
+pub(crate) use recover_panic::{panic_message, RecoverPanic};
 pub(crate) use request_duration_metrics::RequestDurationMiddleware;
+pub(crate) use server_deadline::ServerDeadline;
+pub(crate) use set_client_identity::SetClientIdentity;
 pub(crate) use set_current_service::CurrentServiceName;
 pub(crate) use set_current_service::SetCurrentService;
+pub(crate) use stream_telemetry::StreamTelemetry;
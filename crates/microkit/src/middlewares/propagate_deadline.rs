@@ -0,0 +1,72 @@
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use poem_grpc::{Code, Status};
+
+use crate::{
+    deadline::{format_grpc_timeout, parse_grpc_timeout, Deadline},
+    status::status_response,
+};
+
+/// Client-side middleware that forwards the caller's remaining deadline to
+/// outgoing gRPC requests.
+///
+/// The deadline is taken from [`Deadline`] request data if present, and
+/// otherwise from the [current](Deadline::current) deadline of the call the
+/// server is handling. The middleware then:
+///
+/// 1. Fails the call immediately with `DEADLINE_EXCEEDED` if the deadline has
+///    already passed.
+/// 2. Sends the remaining time in the `grpc-timeout` header, unless the
+///    request already carries a shorter one.
+/// 3. Fails the call with `DEADLINE_EXCEEDED` if no response headers arrive
+///    before the deadline.
+///
+/// Requests made outside a call with a deadline are forwarded as-is.
+///
+/// This middleware is typically not used directly — it is registered automatically
+/// by the code generator via
+/// [`client_middleware("gear_microkit::middlewares::PropagateDeadline")`](https://docs.rs/poem-grpc-build).
+pub struct PropagateDeadline;
+
+impl<E: Endpoint> Middleware<E> for PropagateDeadline {
+    type Output = PropagateDeadlineEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        PropagateDeadlineEndpoint { inner: ep }
+    }
+}
+
+/// The endpoint wrapper produced by [`PropagateDeadline`].
+///
+/// See [`PropagateDeadline`] for details on how deadlines are propagated.
+pub struct PropagateDeadlineEndpoint<E> {
+    inner: E,
+}
+
+impl<E: Endpoint> Endpoint for PropagateDeadlineEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        let Some(deadline) = req.data::<Deadline>().copied().or_else(Deadline::current) else {
+            return self.inner.call(req).await.map(IntoResponse::into_response);
+        };
+
+        let remaining = deadline.remaining();
+        if remaining.is_zero() {
+            return Ok(deadline_exceeded());
+        }
+        let explicit = req.header("grpc-timeout").and_then(parse_grpc_timeout);
+        if explicit.is_none_or(|explicit| explicit > remaining) {
+            req.headers_mut()
+                .insert("grpc-timeout", format_grpc_timeout(remaining));
+        }
+
+        match tokio::time::timeout_at(deadline.instant().into(), self.inner.call(req)).await {
+            Ok(res) => res.map(IntoResponse::into_response),
+            Err(_) => Ok(deadline_exceeded()),
+        }
+    }
+}
+
+fn deadline_exceeded() -> Response {
+    status_response(&Status::new(Code::DeadlineExceeded).with_message("deadline exceeded"))
+}
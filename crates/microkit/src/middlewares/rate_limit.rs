@@ -0,0 +1,324 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use poem::{http::HeaderValue, Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use poem_grpc::{Code, Status};
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+
+use crate::{methods::KnownMethods, status::status_response, RateLimitKey, RateLimitRule};
+
+static COUNTER: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts!(
+            "micro_rate_limited_total",
+            "number of rpc calls rejected by a rate limit"
+        ),
+        &["method", "rule"]
+    )
+    .expect("failed to create micro_rate_limited_total counter")
+});
+
+/// Applies the token-bucket [`RateLimitRule`]s to incoming calls.
+///
+/// Calls finding a bucket empty fail with `RESOURCE_EXHAUSTED` and a
+/// `grpc-retry-pushback-ms` header, and are counted in the
+/// `micro_rate_limited_total` Prometheus counter. A rejected call takes no
+/// token from any bucket.
+pub(crate) struct RateLimit {
+    rules: Arc<[Rule]>,
+    counter: Arc<IntCounterVec>,
+    known_methods: KnownMethods,
+}
+
+struct Rule {
+    name: String,
+    key: Vec<RateLimitKey>,
+    methods: Vec<String>,
+    /// Tokens added per second.
+    rate: f64,
+    /// The capacity of each bucket.
+    burst: f64,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    last_sweep: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    pub(crate) fn new(rules: &[RateLimitRule], known_methods: KnownMethods) -> Self {
+        let now = Instant::now();
+        let rules = rules
+            .iter()
+            .map(|rule| Rule {
+                name: rule.name.clone(),
+                key: rule.key.clone(),
+                methods: rule.methods.clone(),
+                rate: f64::from(rule.limit) / rule.period.as_secs_f64(),
+                burst: f64::from(rule.burst.unwrap_or(rule.limit)),
+                buckets: Mutex::new(Buckets {
+                    buckets: HashMap::new(),
+                    last_sweep: now,
+                }),
+            })
+            .collect();
+        Self {
+            rules,
+            counter: Arc::new(COUNTER.clone()),
+            known_methods,
+        }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for RateLimit {
+    type Output = RateLimitEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        RateLimitEndpoint {
+            inner: ep,
+            rules: self.rules.clone(),
+            counter: self.counter.clone(),
+            known_methods: self.known_methods.clone(),
+        }
+    }
+}
+
+pub(crate) struct RateLimitEndpoint<E> {
+    inner: E,
+    rules: Arc<[Rule]>,
+    counter: Arc<IntCounterVec>,
+    known_methods: KnownMethods,
+}
+
+impl<E: Endpoint> Endpoint for RateLimitEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let buckets = self
+            .rules
+            .iter()
+            .filter_map(|rule| Some((rule, rule.bucket_key(&req)?)))
+            .collect::<Vec<_>>();
+        if let Err((rule, retry_after)) = take_all(&buckets, Instant::now()) {
+            let method = self.known_methods.label(req.uri().path());
+            self.counter.with_label_values(&[method, &rule.name]).inc();
+            // Rounded up, so a client retrying after the hint finds a token.
+            let retry_after_ms = retry_after.as_nanos().div_ceil(1_000_000) as u64;
+            let mut resp =
+                status_response(&Status::new(Code::ResourceExhausted).with_message(format!(
+                    "rate limit `{}` exceeded, retry after {retry_after_ms}ms",
+                    rule.name
+                )));
+            resp.headers_mut()
+                .insert("grpc-retry-pushback-ms", HeaderValue::from(retry_after_ms));
+            return Ok(resp);
+        }
+        self.inner.call(req).await.map(IntoResponse::into_response)
+    }
+}
+
+/// Takes a token from every bucket in `buckets`, or from none of them if one
+/// is empty, in which case its rule and the time until it has a token are
+/// returned.
+fn take_all<'a>(buckets: &[(&'a Rule, String)], now: Instant) -> Result<(), (&'a Rule, Duration)> {
+    for (i, (rule, key)) in buckets.iter().enumerate() {
+        if let Err(retry_after) = rule.take(key, now) {
+            // A rejected call must not use up the tokens of the other rules.
+            for (rule, key) in &buckets[..i] {
+                rule.refund(key);
+            }
+            return Err((rule, retry_after));
+        }
+    }
+    Ok(())
+}
+
+impl Rule {
+    /// Returns the key of the bucket `req` draws from, or `None` if the rule
+    /// does not apply to it.
+    fn bucket_key(&self, req: &Request) -> Option<String> {
+        let method = req.uri().path();
+        if !self.methods.is_empty() && !self.methods.iter().any(|m| m == method) {
+            return None;
+        }
+        let mut key = String::new();
+        for part in &self.key {
+            let value = match part {
+                RateLimitKey::Method => method,
+                RateLimitKey::Caller => req.header("x-micro-from-service")?,
+                // Parsed like `RequestExt::member_id`, so that spellings of the
+                // same ID share a bucket and malformed values are ignored.
+                RateLimitKey::MemberId => {
+                    let member_id: u64 = req.header("member-id")?.parse().ok()?;
+                    key.push_str(&member_id.to_string());
+                    key.push('\0');
+                    continue;
+                }
+                RateLimitKey::AppId => req.header("app-id")?,
+            };
+            key.push_str(value);
+            key.push('\0');
+        }
+        Some(key)
+    }
+
+    /// Takes a token from the bucket `key`, or returns the time until one is
+    /// available.
+    fn take(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        self.sweep(&mut buckets, now);
+        let bucket = buckets.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// Returns a token taken from the bucket `key`.
+    fn refund(&self, key: &str) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        // A bucket swept in the meantime has refilled completely.
+        if let Some(bucket) = buckets.buckets.get_mut(key) {
+            bucket.tokens = (bucket.tokens + 1.0).min(self.burst);
+        }
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+
+    /// Drops the buckets that have refilled completely, which behave exactly
+    /// like new ones, so that keys seen only once do not accumulate. Runs at
+    /// most once per time it takes to refill an empty bucket.
+    fn sweep(&self, buckets: &mut Buckets, now: Instant) {
+        let refill_time = Duration::from_secs_f64(self.burst / self.rate);
+        if now.saturating_duration_since(buckets.last_sweep) < refill_time {
+            return;
+        }
+        buckets.last_sweep = now;
+        buckets
+            .buckets
+            .retain(|_, bucket| self.refill(bucket, now) < self.burst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[RateLimitRule]) -> Arc<[Rule]> {
+        RateLimit::new(rules, KnownMethods::default()).rules
+    }
+
+    /// A rule allowing 10 calls per second in bursts of 2.
+    fn rule(name: &str) -> RateLimitRule {
+        RateLimitRule {
+            burst: Some(2),
+            ..RateLimitRule::new(name, 10)
+        }
+    }
+
+    #[test]
+    fn allows_bursts_then_hints_at_the_next_token() {
+        let rules = rules(&[rule("a")]);
+        let now = Instant::now();
+        assert_eq!(rules[0].take("", now), Ok(()));
+        assert_eq!(rules[0].take("", now), Ok(()));
+        assert_eq!(rules[0].take("", now), Err(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn refills_at_the_rate_up_to_the_burst() {
+        let rules = rules(&[rule("a")]);
+        let now = Instant::now();
+        for _ in 0..2 {
+            rules[0].take("", now).unwrap();
+        }
+
+        // A quarter of a token has been added after 25ms.
+        let later = now + Duration::from_millis(25);
+        let retry_after = rules[0].take("", later).unwrap_err();
+        assert!(
+            retry_after.abs_diff(Duration::from_millis(75)) < Duration::from_micros(1),
+            "{retry_after:?}"
+        );
+
+        let later = now + Duration::from_millis(100);
+        assert_eq!(rules[0].take("", later), Ok(()));
+        assert!(rules[0].take("", later).is_err());
+
+        // Waiting for longer than the time to refill does not exceed the burst.
+        let later = now + Duration::from_secs(10);
+        assert_eq!(rules[0].take("", later), Ok(()));
+        assert_eq!(rules[0].take("", later), Ok(()));
+        assert!(rules[0].take("", later).is_err());
+    }
+
+    #[test]
+    fn keeps_a_bucket_per_key() {
+        let rules = rules(&[rule("a")]);
+        let now = Instant::now();
+        for _ in 0..2 {
+            rules[0].take("x", now).unwrap();
+        }
+        assert!(rules[0].take("x", now).is_err());
+        assert_eq!(rules[0].take("y", now), Ok(()));
+    }
+
+    #[test]
+    fn takes_no_token_from_any_rule_when_one_is_empty() {
+        let rules = rules(&[
+            rule("a"),
+            RateLimitRule {
+                burst: Some(1),
+                ..RateLimitRule::new("b", 1)
+            },
+        ]);
+        let buckets = rules
+            .iter()
+            .map(|rule| (rule, String::new()))
+            .collect::<Vec<_>>();
+        let now = Instant::now();
+        assert!(take_all(&buckets, now).is_ok());
+
+        // `b` is empty, so the token taken from `a` is given back each time.
+        for _ in 0..5 {
+            let (rule, retry_after) = take_all(&buckets, now).unwrap_err();
+            assert_eq!(rule.name, "b");
+            assert_eq!(retry_after, Duration::from_secs(1));
+        }
+        assert_eq!(rules[0].take("", now), Ok(()));
+        assert!(rules[0].take("", now).is_err());
+    }
+
+    #[test]
+    fn refunds_up_to_the_burst() {
+        let rules = rules(&[rule("a")]);
+        let now = Instant::now();
+        rules[0].take("", now).unwrap();
+        rules[0].refund("");
+        rules[0].refund("");
+        for _ in 0..2 {
+            rules[0].take("", now).unwrap();
+        }
+        assert!(rules[0].take("", now).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Once},
+};
+
+use futures_util::FutureExt;
+use once_cell::sync::Lazy;
+use opentelemetry::{
+    trace::{Status as SpanStatus, TraceContextExt},
+    Context, KeyValue,
+};
+use opentelemetry_semantic_conventions::attribute::{
+    CODE_COLUMN_NUMBER, CODE_FILE_PATH, CODE_LINE_NUMBER, EXCEPTION_MESSAGE, EXCEPTION_TYPE,
+};
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use poem_grpc::{Code, Status};
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+
+use crate::{methods::KnownMethods, status::status_response};
+
+static COUNTER: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts!(
+            "micro_panics_total",
+            "number of rpc method handlers that panicked"
+        ),
+        &["method"]
+    )
+    .expect("failed to create micro_panics_total counter")
+});
+
+thread_local! {
+    /// The location of the most recent panic on this thread, recorded by the
+    /// panic hook because the unwind payload only carries the message.
+    static PANIC_LOCATION: RefCell<Option<PanicLocation>> = const { RefCell::new(None) };
+}
+
+struct PanicLocation {
+    file: String,
+    line: u32,
+    column: u32,
+}
+
+/// Catches panics unwinding out of a handler and turns them into an
+/// `INTERNAL` status instead of tearing down the connection's task.
+///
+/// The panic message and location are recorded on the active OpenTelemetry
+/// span as an `exception` event, and counted in the `micro_panics_total`
+/// Prometheus counter labeled by method, or `unknown` for paths no service
+/// answers. Panics raised while streaming a
+/// response body after the handler has returned are not caught.
+pub(crate) struct RecoverPanic {
+    counter: Arc<IntCounterVec>,
+    known_methods: KnownMethods,
+}
+
+impl RecoverPanic {
+    pub(crate) fn new(known_methods: KnownMethods) -> Self {
+        install_panic_hook();
+        Self {
+            counter: Arc::new(COUNTER.clone()),
+            known_methods,
+        }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for RecoverPanic {
+    type Output = RecoverPanicEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        RecoverPanicEndpoint {
+            inner: ep,
+            counter: self.counter.clone(),
+            known_methods: self.known_methods.clone(),
+        }
+    }
+}
+
+pub(crate) struct RecoverPanicEndpoint<E> {
+    inner: E,
+    counter: Arc<IntCounterVec>,
+    known_methods: KnownMethods,
+}
+
+impl<E: Endpoint> Endpoint for RecoverPanicEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let method = req.uri().path().to_string();
+        match AssertUnwindSafe(self.inner.call(req)).catch_unwind().await {
+            Ok(res) => res.map(IntoResponse::into_response),
+            Err(payload) => {
+                let message = panic_message(&*payload);
+                let location = PANIC_LOCATION.with(|location| location.borrow_mut().take());
+                self.counter
+                    .with_label_values(&[self.known_methods.label(&method)])
+                    .inc();
+                record_panic(&message, location.as_ref());
+                match &location {
+                    Some(location) => tracing::error!(
+                        method,
+                        message,
+                        location = %format_args!("{}:{}:{}", location.file, location.line, location.column),
+                        "rpc handler panicked"
+                    ),
+                    None => tracing::error!(method, message, "rpc handler panicked"),
+                }
+                Ok(status_response(&Status::new(Code::Internal)))
+            }
+        }
+    }
+}
+
+/// Records the panic on the span of the current call.
+fn record_panic(message: &str, location: Option<&PanicLocation>) {
+    let cx = Context::current();
+    let span = cx.span();
+    let mut attributes = vec![
+        KeyValue::new(EXCEPTION_TYPE, "panic"),
+        KeyValue::new(EXCEPTION_MESSAGE, message.to_string()),
+    ];
+    if let Some(location) = location {
+        attributes.push(KeyValue::new(CODE_FILE_PATH, location.file.clone()));
+        attributes.push(KeyValue::new(CODE_LINE_NUMBER, i64::from(location.line)));
+        attributes.push(KeyValue::new(
+            CODE_COLUMN_NUMBER,
+            i64::from(location.column),
+        ));
+    }
+    span.add_event("exception", attributes);
+    span.set_status(SpanStatus::error(format!("panicked: {message}")));
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+/// Chains a hook recording the panic location in front of the existing panic
+/// hook, which keeps printing the panic as before.
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Some(location) = info.location() {
+                PANIC_LOCATION.with(|slot| {
+                    *slot.borrow_mut() = Some(PanicLocation {
+                        file: location.file().to_string(),
+                        line: location.line(),
+                        column: location.column(),
+                    });
+                });
+            }
+            previous(info);
+        }));
+    });
+}
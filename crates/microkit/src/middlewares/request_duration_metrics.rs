@@ -1,8 +1,20 @@
 use std::{sync::Arc, time::Instant};
 
+use once_cell::sync::Lazy;
 use poem::{Endpoint, Middleware, Request, Result};
 use prometheus::{histogram_opts, register_histogram_vec, HistogramVec};
 
+/// Registered once per process, so that several servers, e.g. in tests, share
+/// the histogram instead of failing to register it again.
+static HISTOGRAM: Lazy<HistogramVec> = Lazy::new(|| {
+    let opts = histogram_opts!(
+        "micro_request_duration_seconds",
+        "rpc method request time in seconds"
+    );
+    register_histogram_vec!(opts, &["method", "status", "caller"])
+        .expect("failed to create request_duration_seconds histogram")
+});
+
 pub(crate) struct RequestDurationMiddleware {
     histogram: Arc<HistogramVec>,
 }
@@ -15,14 +27,8 @@ impl Default for RequestDurationMiddleware {
 
 impl RequestDurationMiddleware {
     pub(crate) fn new() -> Self {
-        let opts = histogram_opts!(
-            "micro_request_duration_seconds",
-            "rpc method request time in seconds"
-        );
-        let histogram = register_histogram_vec!(opts, &["method", "status", "caller"])
-            .expect("failed to create request_duration_seconds histogram");
         Self {
-            histogram: Arc::new(histogram),
+            histogram: Arc::new(HISTOGRAM.clone()),
         }
     }
 }
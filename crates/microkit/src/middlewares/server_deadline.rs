@@ -0,0 +1,53 @@
+use std::time::Instant;
+
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use poem_grpc::{Code, Status};
+
+use crate::{
+    deadline::{parse_grpc_timeout, Deadline},
+    status::status_response,
+};
+
+/// Enforces the deadline sent by the caller in the `grpc-timeout` header.
+///
+/// The [`Deadline`] is stored as request data and made
+/// [current](Deadline::current) for the handler. If the handler has not
+/// produced a response by then, it is dropped and the call fails with
+/// `DEADLINE_EXCEEDED`. Malformed `grpc-timeout` values are ignored.
+pub(crate) struct ServerDeadline;
+
+impl<E: Endpoint> Middleware<E> for ServerDeadline {
+    type Output = ServerDeadlineEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ServerDeadlineEndpoint { inner: ep }
+    }
+}
+
+pub(crate) struct ServerDeadlineEndpoint<E> {
+    inner: E,
+}
+
+impl<E: Endpoint> Endpoint for ServerDeadlineEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        let deadline = req
+            .header("grpc-timeout")
+            .and_then(parse_grpc_timeout)
+            .and_then(|timeout| Instant::now().checked_add(timeout))
+            .map(Deadline::at);
+        let Some(deadline) = deadline else {
+            return self.inner.call(req).await.map(IntoResponse::into_response);
+        };
+
+        req.set_data(deadline);
+        let call = deadline.scope(self.inner.call(req));
+        match tokio::time::timeout_at(deadline.instant().into(), call).await {
+            Ok(res) => res.map(IntoResponse::into_response),
+            Err(_) => Ok(status_response(
+                &Status::new(Code::DeadlineExceeded).with_message("deadline exceeded"),
+            )),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use poem::{Endpoint, Middleware, Request, Result};
+
+use crate::tls::ClientRegistry;
+
+/// Attaches the [`ClientIdentity`](crate::ClientIdentity) of the TLS
+/// connection a request arrived on as request data.
+pub(crate) struct SetClientIdentity {
+    registry: ClientRegistry,
+}
+
+impl SetClientIdentity {
+    pub(crate) fn new(registry: ClientRegistry) -> Self {
+        Self { registry }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for SetClientIdentity {
+    type Output = SetClientIdentityEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        SetClientIdentityEndpoint {
+            inner: ep,
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+pub(crate) struct SetClientIdentityEndpoint<E> {
+    inner: E,
+    registry: ClientRegistry,
+}
+
+impl<E: Endpoint> Endpoint for SetClientIdentityEndpoint<E> {
+    type Output = E::Output;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        if let Some(identity) = self.registry.get(req.local_addr(), req.remote_addr()) {
+            req.set_data(identity);
+        }
+        self.inner.call(req).await
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    task::{ready, Context as TaskContext, Poll},
+    time::Instant,
+};
+
+use bytes::Bytes;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use http_body_util::combinators::BoxBody;
+use once_cell::sync::Lazy;
+use opentelemetry::{trace::TraceContextExt, Context, KeyValue};
+use opentelemetry_semantic_conventions::attribute::RPC_GRPC_STATUS_CODE;
+use poem::{Body, Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use poem_grpc::Code;
+use prometheus::{
+    histogram_opts, opts, register_histogram_vec, register_int_counter_vec, HistogramVec,
+    IntCounter, IntCounterVec,
+};
+
+use crate::{
+    body::MessageFramer,
+    status::{code_name, grpc_status, is_grpc},
+};
+
+const MESSAGES_RECEIVED: &str = "rpc.messages_received";
+const MESSAGES_SENT: &str = "rpc.messages_sent";
+
+static METRICS: Lazy<Metrics> = Lazy::new(|| Metrics {
+    received: register_int_counter_vec!(
+        opts!(
+            "micro_stream_messages_received_total",
+            "number of rpc request messages received"
+        ),
+        &["method"]
+    )
+    .expect("failed to create micro_stream_messages_received_total counter"),
+    sent: register_int_counter_vec!(
+        opts!(
+            "micro_stream_messages_sent_total",
+            "number of rpc response messages sent"
+        ),
+        &["method"]
+    )
+    .expect("failed to create micro_stream_messages_sent_total counter"),
+    duration: register_histogram_vec!(
+        histogram_opts!(
+            "micro_stream_duration_seconds",
+            "rpc call time in seconds, until the response stream has ended"
+        ),
+        &["method", "code"]
+    )
+    .expect("failed to create micro_stream_duration_seconds histogram"),
+});
+
+/// Instruments calls over their whole lifetime, which for streaming calls
+/// extends well past the point where the handler returns its response.
+///
+/// Request and response messages are counted as they cross the stack, in
+/// `micro_stream_messages_received_total` and
+/// `micro_stream_messages_sent_total`, labeled by method. Once the response
+/// stream has ended, its duration is recorded in the
+/// `micro_stream_duration_seconds` histogram, labeled by method and gRPC
+/// status `code`; a stream dropped before its trailers, e.g. because the
+/// client cancelled it, counts as `CANCELLED`.
+///
+/// The server span is kept open until the stream ends, and its lifecycle is
+/// recorded as span events: `stream.request_closed` once the request stream
+/// has been read to its end, which unary handlers may never do, then
+/// `stream.completed` with the final status, or `stream.cancelled`.
+pub(crate) struct StreamTelemetry {
+    metrics: Metrics,
+}
+
+#[derive(Clone)]
+struct Metrics {
+    received: IntCounterVec,
+    sent: IntCounterVec,
+    duration: HistogramVec,
+}
+
+impl StreamTelemetry {
+    pub(crate) fn new() -> Self {
+        Self {
+            metrics: METRICS.clone(),
+        }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for StreamTelemetry {
+    type Output = StreamTelemetryEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        StreamTelemetryEndpoint {
+            inner: ep,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+pub(crate) struct StreamTelemetryEndpoint<E> {
+    inner: E,
+    metrics: Metrics,
+}
+
+impl<E: Endpoint> Endpoint for StreamTelemetryEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        if !is_grpc(req.headers()) {
+            return self.inner.call(req).await.map(IntoResponse::into_response);
+        }
+
+        let method = req.uri().path().to_string();
+        let stream = Arc::new(Stream {
+            start: Instant::now(),
+            // Holding the context keeps the server span open until the stream
+            // has ended.
+            cx: Context::current(),
+            received: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            ended: AtomicBool::new(false),
+            received_total: self.metrics.received.with_label_values(&[&method]),
+            sent_total: self.metrics.sent.with_label_values(&[&method]),
+            duration: self.metrics.duration.clone(),
+            method,
+        });
+        let body = req.take_body();
+        req.set_body(CountBody::wrap(body, stream.clone(), Direction::Received));
+
+        let resp = match self.inner.call(req).await {
+            Ok(resp) => resp.into_response(),
+            Err(err) => {
+                stream.end(Code::Unknown, false);
+                return Err(err);
+            }
+        };
+        if let Some(code) = grpc_status(resp.headers()) {
+            stream.end(code, false);
+            return Ok(resp);
+        }
+        let (parts, body) = resp.into_parts();
+        Ok(Response::from_parts(
+            parts,
+            CountBody::wrap(body, stream, Direction::Sent),
+        ))
+    }
+}
+
+/// The state of a call shared by its request and response bodies.
+struct Stream {
+    start: Instant,
+    cx: Context,
+    received: AtomicU64,
+    sent: AtomicU64,
+    ended: AtomicBool,
+    received_total: IntCounter,
+    sent_total: IntCounter,
+    duration: HistogramVec,
+    method: String,
+}
+
+impl Stream {
+    fn request_closed(&self) {
+        self.cx.span().add_event(
+            "stream.request_closed",
+            vec![KeyValue::new(
+                MESSAGES_RECEIVED,
+                self.received.load(Ordering::Relaxed) as i64,
+            )],
+        );
+    }
+
+    /// Records the end of the call with `code`, once.
+    fn end(&self, code: Code, cancelled: bool) {
+        if self.ended.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.duration
+            .with_label_values(&[&self.method, code_name(code)])
+            .observe(self.start.elapsed().as_secs_f64());
+
+        let span = self.cx.span();
+        let mut attributes = vec![
+            KeyValue::new(
+                MESSAGES_RECEIVED,
+                self.received.load(Ordering::Relaxed) as i64,
+            ),
+            KeyValue::new(MESSAGES_SENT, self.sent.load(Ordering::Relaxed) as i64),
+        ];
+        if cancelled {
+            span.add_event("stream.cancelled", attributes);
+        } else {
+            attributes.push(KeyValue::new(RPC_GRPC_STATUS_CODE, code.as_u16() as i64));
+            span.add_event("stream.completed", attributes);
+        }
+        span.set_attribute(KeyValue::new(RPC_GRPC_STATUS_CODE, code.as_u16() as i64));
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Received,
+    Sent,
+}
+
+/// Counts the messages of a request or response body.
+struct CountBody {
+    inner: BoxBody<Bytes, io::Error>,
+    stream: Arc<Stream>,
+    direction: Direction,
+    framer: MessageFramer,
+    done: bool,
+}
+
+impl CountBody {
+    fn wrap(body: Body, stream: Arc<Stream>, direction: Direction) -> Body {
+        Body::from(BoxBody::new(Self {
+            inner: body.into(),
+            stream,
+            direction,
+            framer: MessageFramer::default(),
+            done: false,
+        }))
+    }
+
+    fn count(&mut self, data: &[u8]) {
+        let mut pos = 0;
+        let mut messages = 0;
+        while self.framer.next_message(data, &mut pos).is_some() {
+            messages += 1;
+        }
+        if messages == 0 {
+            return;
+        }
+        let (count, total) = match self.direction {
+            Direction::Received => (&self.stream.received, &self.stream.received_total),
+            Direction::Sent => (&self.stream.sent, &self.stream.sent_total),
+        };
+        count.fetch_add(messages, Ordering::Relaxed);
+        total.inc_by(messages);
+    }
+
+    fn finish(&mut self, end: End) {
+        if std::mem::replace(&mut self.done, true) {
+            return;
+        }
+        match (self.direction, end) {
+            (Direction::Received, End::Eof) => self.stream.request_closed(),
+            (Direction::Received, _) => {}
+            (Direction::Sent, End::Status(code)) => self.stream.end(code, false),
+            (Direction::Sent, End::Eof | End::Error) => self.stream.end(Code::Unknown, false),
+            (Direction::Sent, End::Dropped) => self.stream.end(Code::Cancelled, true),
+        }
+    }
+}
+
+/// How a body ended.
+#[derive(Clone, Copy)]
+enum End {
+    /// With trailers carrying a status.
+    Status(Code),
+    /// Without trailers.
+    Eof,
+    Error,
+    /// Before it ended.
+    Dropped,
+}
+
+impl HttpBody for CountBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.count(data);
+                    // Unary handlers stop reading after the first message, so
+                    // the end of the stream may only be known from its flag.
+                    if this.inner.is_end_stream() {
+                        this.finish(End::Eof);
+                    }
+                } else if let Some(trailers) = frame.trailers_ref() {
+                    this.finish(End::Status(grpc_status(trailers).unwrap_or(Code::Unknown)));
+                }
+            }
+            Some(Err(_)) => this.finish(End::Error),
+            None => this.finish(End::Eof),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CountBody {
+    fn drop(&mut self) {
+        self.finish(End::Dropped);
+    }
+}
@@ -0,0 +1,23 @@
+/// Includes the file descriptor set written by `gear_codegen::build`.
+///
+/// The descriptor set describes every proto compiled by the build script of
+/// the calling crate. Pass it to
+/// [`GrpcServer::with_reflection`](crate::GrpcServer::with_reflection) to
+/// serve server reflection for all of them.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use gear_microkit::GrpcServer;
+///
+/// let server = GrpcServer::new().with_reflection(gear_microkit::file_descriptor_set!());
+/// ```
+#[macro_export]
+macro_rules! file_descriptor_set {
+    () => {
+        ::std::include_bytes!(::std::concat!(
+            ::std::env!("OUT_DIR"),
+            "/file_descriptor_set.bin"
+        ))
+    };
+}
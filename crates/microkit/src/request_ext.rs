@@ -3,6 +3,8 @@ use std::collections::HashMap;
 use num_enum::FromPrimitive;
 use poem_grpc::Request;
 
+use crate::{ClientIdentity, Deadline};
+
 /// The type of broker associated with a trading account.
 ///
 /// Parsed from the `broker-type` metadata field as an `i64` value.
@@ -147,6 +149,31 @@ pub trait RequestExt {
     /// The raw value is parsed as an `i64` and converted via [`BrokerType::from`].
     /// Returns `None` if the header is absent or not a valid integer.
     fn broker_type(&self) -> Option<BrokerType>;
+
+    /// Returns the identity from the client certificate verified during the
+    /// mutual TLS handshake.
+    ///
+    /// Returns `None` unless mutual TLS is enabled through
+    /// [`TlsConfig::client_ca_path`](crate::TlsConfig::client_ca_path) and the
+    /// client presented a certificate.
+    ///
+    /// The default implementation returns `None`, so implementors outside
+    /// this crate keep compiling; the implementation for
+    /// [`poem_grpc::Request`] reads the identity from the request extensions.
+    fn client_identity(&self) -> Option<&ClientIdentity> {
+        None
+    }
+
+    /// Returns the deadline the caller sent in the `grpc-timeout` header.
+    ///
+    /// The server cancels the handler with `DEADLINE_EXCEEDED` once the
+    /// deadline passes.
+    ///
+    /// The default implementation returns `None`; the implementation for
+    /// [`poem_grpc::Request`] reads the deadline from the request extensions.
+    fn deadline(&self) -> Option<Deadline> {
+        None
+    }
 }
 
 macro_rules! impl_string_values {
@@ -249,4 +276,12 @@ impl<T> RequestExt for Request<T> {
             .and_then(|value| value.parse::<i64>().ok())
             .map(Into::into)
     }
+
+    fn client_identity(&self) -> Option<&ClientIdentity> {
+        self.extensions().get()
+    }
+
+    fn deadline(&self) -> Option<Deadline> {
+        self.extensions().get().copied()
+    }
 }
@@ -0,0 +1,451 @@
+use std::{
+    collections::hash_map::RandomState,
+    convert::Infallible,
+    future::{pending, Future},
+    hash::{BuildHasher, Hasher},
+    io,
+    pin::{pin, Pin},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use http_body_util::BodyExt;
+use hyper::{body::Incoming, service::service_fn};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+};
+use poem::{
+    endpoint::BoxEndpoint,
+    http::{self, uri::Scheme},
+    listener::{Acceptor, BoxAcceptor},
+    web::{LocalAddr, RemoteAddr},
+    Endpoint, Request, Response,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::mpsc,
+    time::{sleep, sleep_until, Instant},
+};
+
+use crate::{lifecycle::Shutdown, GrpcServerConfig, ShutdownToken};
+
+/// How long to stop accepting after an error that is not specific to one
+/// connection, e.g. running out of file descriptors, which would otherwise
+/// fail every accept in a busy loop.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The HTTP settings applied to every accepted connection.
+#[derive(Clone, Copy)]
+struct Settings {
+    http2_max_concurrent_streams: Option<u32>,
+    http2_max_header_list_size: u32,
+    http2_max_pending_accept_reset_streams: Option<u32>,
+    http2_keepalive_interval: Option<Duration>,
+    http2_keepalive_timeout: Duration,
+    http2_initial_stream_window_size: Option<u32>,
+    http2_initial_connection_window_size: Option<u32>,
+    http2_max_frame_size: Option<u32>,
+    idle_timeout: Option<Duration>,
+    max_connection_idle: Option<Duration>,
+    max_connection_age: Option<Duration>,
+    max_connection_age_grace: Option<Duration>,
+}
+
+impl Settings {
+    fn new(config: &GrpcServerConfig) -> Self {
+        Self {
+            http2_max_concurrent_streams: config.http2_max_concurrent_streams,
+            http2_max_header_list_size: config.http2_max_header_list_size,
+            http2_max_pending_accept_reset_streams: config.http2_max_pending_accept_reset_streams,
+            http2_keepalive_interval: config.http2_keepalive_interval,
+            http2_keepalive_timeout: config.http2_keepalive_timeout,
+            http2_initial_stream_window_size: config.http2_initial_stream_window_size,
+            http2_initial_connection_window_size: config.http2_initial_connection_window_size,
+            http2_max_frame_size: config.http2_max_frame_size,
+            idle_timeout: config.idle_timeout,
+            max_connection_idle: config.max_connection_idle,
+            max_connection_age: config.max_connection_age,
+            max_connection_age_grace: config.max_connection_age_grace,
+        }
+    }
+}
+
+/// Serves `app` on every connection accepted by `acceptor` until `signal`
+/// resolves.
+///
+/// Shutdown is graceful: the acceptor is closed, every open connection is
+/// sent a `GOAWAY` (or closed after its current request, for HTTP/1), and
+/// the in-flight calls are given `timeout` to complete before their
+/// connections are dropped.
+///
+/// This replaces [`poem::Server`], which does not expose HTTP/2 keepalive,
+/// flow control or connection lifetime settings.
+pub(crate) async fn serve(
+    mut acceptor: BoxAcceptor,
+    app: BoxEndpoint<'static, Response>,
+    config: &GrpcServerConfig,
+    signal: impl Future<Output = ()>,
+    timeout: Duration,
+) -> io::Result<()> {
+    let app = Arc::new(app);
+    let settings = Settings::new(config);
+    // Stops the open connections gracefully, then forcibly after `timeout`.
+    let (draining, stopped) = (Shutdown::new(), Shutdown::new());
+    // Every connection holds a sender; the channel closes once all are gone.
+    let (open, mut closed) = mpsc::channel::<Infallible>(1);
+
+    for addr in acceptor.local_addr() {
+        tracing::info!(addr = %addr, "listening");
+    }
+
+    let mut signal = pin!(signal);
+    loop {
+        tokio::select! {
+            _ = &mut signal => break,
+            res = acceptor.accept() => {
+                let (socket, local_addr, remote_addr, scheme) = match res {
+                    Ok(accepted) => accepted,
+                    Err(err) if is_connection_error(&err) => {
+                        tracing::warn!(error = %err, "failed to accept connection");
+                        continue;
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            error = %err,
+                            backoff = ?ACCEPT_ERROR_BACKOFF,
+                            "failed to accept connection, pausing accepts"
+                        );
+                        tokio::select! {
+                            _ = &mut signal => break,
+                            _ = sleep(ACCEPT_ERROR_BACKOFF) => continue,
+                        }
+                    }
+                };
+                let conn = Connection {
+                    app: app.clone(),
+                    settings,
+                    local_addr,
+                    remote_addr,
+                    scheme,
+                };
+                let (draining, stopped) = (draining.token(), stopped.token());
+                let open = open.clone();
+                tokio::spawn(async move {
+                    conn.serve(socket, draining, stopped).await;
+                    drop(open);
+                });
+            }
+        }
+    }
+
+    drop(acceptor);
+    drop(open);
+    draining.trigger();
+    tokio::select! {
+        _ = closed.recv() => {}
+        _ = sleep(timeout) => {
+            stopped.trigger();
+            closed.recv().await;
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether an accept error only concerns the connection being
+/// accepted, e.g. because the client reset it or failed the TLS handshake,
+/// rather than the listener or the process, such as `EMFILE` or `ENFILE`.
+fn is_connection_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::InvalidData
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+    )
+}
+
+/// An accepted connection.
+struct Connection {
+    app: Arc<BoxEndpoint<'static, Response>>,
+    settings: Settings,
+    local_addr: LocalAddr,
+    remote_addr: RemoteAddr,
+    scheme: Scheme,
+}
+
+impl Connection {
+    /// Serves the connection until the client closes it, it is closed as
+    /// described by [`Close`], or the server shuts down.
+    async fn serve<Io>(self, socket: Io, draining: ShutdownToken, stopped: ShutdownToken)
+    where
+        Io: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let Self {
+            app,
+            settings,
+            local_addr,
+            remote_addr,
+            scheme,
+        } = self;
+        let activity = Arc::new(Activity::new());
+        let service = service_fn({
+            let activity = activity.clone();
+            let remote_addr = remote_addr.clone();
+            move |req: http::Request<Incoming>| {
+                let app = app.clone();
+                let call = activity.start_call();
+                let req =
+                    Request::from((req, local_addr.clone(), remote_addr.clone(), scheme.clone()));
+                async move {
+                    let resp: http::Response<_> = app.get_response(req).await.into();
+                    // The call lasts until its response body, which may be a
+                    // stream, has been sent or dropped.
+                    Ok::<_, Infallible>(resp.map(|body| {
+                        body.map_frame(move |frame| {
+                            let _ = &call;
+                            frame
+                        })
+                    }))
+                }
+            }
+        });
+
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .max_concurrent_streams(settings.http2_max_concurrent_streams)
+            .max_header_list_size(settings.http2_max_header_list_size)
+            .max_pending_accept_reset_streams(
+                settings
+                    .http2_max_pending_accept_reset_streams
+                    .map(|max| max as usize),
+            )
+            .keep_alive_interval(settings.http2_keepalive_interval)
+            .keep_alive_timeout(settings.http2_keepalive_timeout)
+            .initial_stream_window_size(settings.http2_initial_stream_window_size)
+            .initial_connection_window_size(settings.http2_initial_connection_window_size)
+            .max_frame_size(settings.http2_max_frame_size);
+        let socket = TrackIo {
+            inner: socket,
+            activity: activity.clone(),
+        };
+        let mut conn = pin!(builder.serve_connection_with_upgrades(TokioIo::new(socket), service));
+
+        let grace = tokio::select! {
+            _ = conn.as_mut() => return,
+            close = Close::when(&activity, &settings) => {
+                tracing::debug!(remote_addr = %remote_addr, reason = close.reason(), "closing connection");
+                match close {
+                    Close::Age => settings.max_connection_age_grace,
+                    Close::Inactive | Close::Idle => None,
+                }
+            }
+            _ = draining.cancelled() => None,
+        };
+        // Sends `GOAWAY` and lets the calls in flight complete.
+        conn.as_mut().graceful_shutdown();
+        let grace_expired = async {
+            match grace {
+                Some(grace) => sleep(grace).await,
+                None => pending().await,
+            }
+        };
+        tokio::select! {
+            _ = conn => {}
+            _ = stopped.cancelled() => {}
+            _ = grace_expired => {
+                tracing::debug!(
+                    remote_addr = %remote_addr,
+                    "max connection age grace period expired, closing connection",
+                );
+            }
+        }
+    }
+}
+
+/// Why a connection is closed before the client closes it.
+#[derive(Clone, Copy)]
+enum Close {
+    /// Nothing was read or written for the idle timeout.
+    Inactive,
+    /// No call was in flight for the max connection idle time.
+    Idle,
+    /// The connection reached the max connection age.
+    Age,
+}
+
+impl Close {
+    fn reason(self) -> &'static str {
+        match self {
+            Self::Inactive => "inactivity",
+            Self::Idle => "max connection idle",
+            Self::Age => "max connection age",
+        }
+    }
+
+    /// Resolves once the connection should be closed.
+    async fn when(activity: &Activity, settings: &Settings) -> Self {
+        let age_deadline = settings
+            .max_connection_age
+            .map(|age| activity.opened + jitter(age));
+        loop {
+            let now = Instant::now();
+            let mut next = age_deadline;
+            if age_deadline.is_some_and(|deadline| now >= deadline) {
+                return Self::Age;
+            }
+            if let Some(timeout) = settings.idle_timeout {
+                let deadline = activity.last_io() + timeout;
+                if now >= deadline {
+                    return Self::Inactive;
+                }
+                next = earliest(next, deadline);
+            }
+            if let Some(idle) = settings.max_connection_idle {
+                let deadline = match activity.calls.load(Ordering::Acquire) {
+                    0 => activity.last_call() + idle,
+                    _ => now + idle,
+                };
+                if now >= deadline {
+                    return Self::Idle;
+                }
+                next = earliest(next, deadline);
+            }
+            match next {
+                Some(deadline) => sleep_until(deadline).await,
+                None => pending().await,
+            }
+        }
+    }
+}
+
+fn earliest(deadline: Option<Instant>, other: Instant) -> Option<Instant> {
+    Some(deadline.map_or(other, |deadline| deadline.min(other)))
+}
+
+/// Spreads `age` by up to 10% either way, so connections opened together,
+/// e.g. right after a deployment, are not all closed together.
+fn jitter(age: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    let factor = 0.9 + 0.2 * (random as f64 / u64::MAX as f64);
+    age.mul_f64(factor)
+}
+
+/// The activity on a connection, in milliseconds since it was opened.
+struct Activity {
+    opened: Instant,
+    last_io: AtomicU64,
+    last_call: AtomicU64,
+    calls: AtomicUsize,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            opened: Instant::now(),
+            last_io: AtomicU64::new(0),
+            last_call: AtomicU64::new(0),
+            calls: AtomicUsize::new(0),
+        }
+    }
+
+    fn elapsed(&self) -> u64 {
+        self.opened.elapsed().as_millis() as u64
+    }
+
+    fn last_io(&self) -> Instant {
+        self.opened + Duration::from_millis(self.last_io.load(Ordering::Relaxed))
+    }
+
+    fn last_call(&self) -> Instant {
+        self.opened + Duration::from_millis(self.last_call.load(Ordering::Acquire))
+    }
+
+    fn touch(&self) {
+        self.last_io.store(self.elapsed(), Ordering::Relaxed);
+    }
+
+    fn start_call(self: &Arc<Self>) -> CallGuard {
+        self.calls.fetch_add(1, Ordering::AcqRel);
+        CallGuard(self.clone())
+    }
+}
+
+/// Counts a call as in flight until dropped.
+struct CallGuard(Arc<Activity>);
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        self.0.last_call.store(self.0.elapsed(), Ordering::Release);
+        self.0.calls.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Records the time of the last read or write on a connection.
+struct TrackIo<T> {
+    inner: T,
+    activity: Arc<Activity>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for TrackIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if res.is_ready() {
+            self.activity.touch();
+        }
+        res
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for TrackIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if res.is_ready() {
+            self.activity.touch();
+        }
+        res
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if res.is_ready() {
+            self.activity.touch();
+        }
+        res
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -1,34 +1,234 @@
-use std::io;
+use std::{any::type_name, future::Future, io, pin::Pin, sync::Arc, time::Duration};
 
 use opentelemetry::{global, trace::TracerProvider as _};
-use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::Tracer};
 use poem::{
     endpoint::BoxEndpoint,
-    listener::TcpListener,
     middleware::{AddData, OpenTelemetryMetrics, OpenTelemetryTracing, TokioMetrics},
-    EndpointExt, IntoEndpoint, Middleware, Response, Server,
+    EndpointExt, IntoEndpoint, Middleware, Response,
 };
-use poem_grpc::{RouteGrpc, Service};
+use poem_grpc::{Reflection, RouteGrpc, Service};
 
-use crate::middlewares::{RequestDurationMiddleware, SetCurrentService};
+use crate::{
+    admin::AdminServer,
+    debug::{DebugInfo, ServiceCatalog},
+    health::{status_setter, BypassHealth, Health, ReadinessCheck, StatusSetter},
+    lifecycle::{run_shutdown_hooks, run_start_hooks, supervise, BackgroundTask, Hook, Shutdown},
+    listener::bind,
+    methods::{KnownMethods, RecordMethods},
+    middlewares::{
+        cors, AccessLog, Compression, ConcurrencyLimit, GrpcWeb, MessageSizeLimit, RateLimit,
+        RecoverPanic, RequestDurationMiddleware, ServerDeadline, SetClientIdentity,
+        SetCurrentService, StreamTelemetry,
+    },
+    serve::serve,
+    shutdown::{os_signal, shutdown_tracer_provider},
+    stack::{BuiltinMiddleware, Placement, Stack, StackOptions},
+    telemetry,
+    testing::Harness,
+    tls::ClientRegistry,
+    transcoding::{HttpRoutes, Transcode},
+    BuildInfo, GrpcServerConfig, ListenAddress, ShutdownToken,
+};
+
+type ShutdownSignal = Pin<Box<dyn Future<Output = ()> + Send>>;
 
 /// A gRPC server with production-ready defaults.
 ///
 /// `GrpcServer` wraps a [`poem_grpc::RouteGrpc`] router and applies a standard
-/// middleware stack when started:
+/// middleware stack when started, listed from the outermost layer to the
+/// innermost:
 ///
 /// | Middleware | Purpose |
 /// |---|---|
-/// | [`AddData`] | Injects the OpenTelemetry [`Tracer`](opentelemetry_sdk::trace::Tracer) into request data |
-/// | [`Compression`] | Transparent response compression |
+/// | [`Cors`](poem::middleware::Cors) | Answers CORS preflight requests and checks the origin of browser calls (only with gRPC-Web) |
+/// | `GrpcWeb` | Translates gRPC-Web calls to native gRPC (opt-in via [`GrpcServerConfig::grpc_web`]) |
+/// | `Transcode` | Serves `google.api.http` annotated RPCs as REST endpoints (only with [`with_http_transcoding`](Self::with_http_transcoding)) |
 /// | [`OpenTelemetryTracing`] | Distributed tracing for incoming requests |
 /// | [`OpenTelemetryMetrics`] | Request-level OpenTelemetry metrics |
-/// | `SetCurrentService` | Extracts the target service name from the URI and stores it as request data |
-/// | [`TokioMetrics`] | Tokio runtime metrics (opt-in via `GEAR_ENABLE_TOKIO_METRICS=1`) |
+/// | `StreamTelemetry` | Counts messages sent and received and records the duration and final status of every call until its response stream ends (`micro_stream_*`), as well as stream lifecycle span events |
+/// | `AccessLog` | Writes one JSON line per call to stdout (opt-in via [`GrpcServerConfig::access_log`]) |
+/// | `MessageSizeLimit` | Rejects request and response messages larger than the [`GrpcServerConfig::message_size`] limits with `RESOURCE_EXHAUSTED` (4 MiB for requests by default) |
+/// | `Compression` | Decompresses requests and compresses responses as negotiated through `grpc-encoding` (see [`GrpcServerConfig::compression`]) |
+/// | [`TokioMetrics`] | Tokio runtime metrics (opt-in via [`GrpcServerConfig::enable_tokio_metrics`]) |
 /// | `RequestDurationMiddleware` | Per-method Prometheus histogram (`micro_request_duration_seconds`) |
+/// | `SetCurrentService` | Extracts the target service name from the URI and stores it as request data |
+/// | `RateLimit` | Rejects calls exceeding the [`GrpcServerConfig::rate_limits`] with `RESOURCE_EXHAUSTED` (only when rules are configured) |
+/// | `ServerDeadline` | Cancels calls with `DEADLINE_EXCEEDED` once the caller's `grpc-timeout` passes |
+/// | `ConcurrencyLimit` | Sheds calls beyond the configured concurrency limits with `RESOURCE_EXHAUSTED` (only when [`GrpcServerConfig::concurrency`] sets a limit) |
+/// | `RecoverPanic` | Turns handler panics into an `INTERNAL` status, recorded on the span and in `micro_panics_total` |
+/// | `SetClientIdentity` | Stores the verified [`ClientIdentity`](crate::ClientIdentity) as request data (only with mutual TLS) |
+/// | [`AddData`] | Injects the OpenTelemetry [`Tracer`](opentelemetry_sdk::trace::Tracer) into request data |
+///
+/// As tracing and the request metrics wrap the limiters, calls rejected by a
+/// rate or concurrency limit get a server span and are measured like any
+/// other, and the recorded durations include the time spent waiting for a
+/// concurrency slot and the handler's deadline budget.
+///
+/// Individual layers can be turned off with
+/// [`disable_middleware`](Self::disable_middleware), and custom middleware
+/// can be placed next to any of them with
+/// [`middleware_outside`](Self::middleware_outside) and
+/// [`middleware_inside`](Self::middleware_inside); see [`BuiltinMiddleware`].
+///
+/// The listen address, HTTP/2 limits and feature toggles come from a
+/// [`GrpcServerConfig`]. Unless one is supplied with
+/// [`with_config`](Self::with_config), it is loaded with
+/// [`GrpcServerConfig::from_env`] when the server starts, so the address is
+/// still taken from the `MICRO_SERVER_ADDRESS` environment variable and falls
+/// back to `0.0.0.0:8080` if unset.
+///
+/// # Tracing
+///
+/// Spans are exported according to [`GrpcServerConfig::tracing`]: over OTLP
+/// (gRPC or HTTP/protobuf), as JSON lines on stdout, or not at all. The
+/// sampler and resource attributes are configurable as well, and the standard
+/// `OTEL_*` environment variables are honoured. Unless configured otherwise,
+/// `service.name` and `service.version` are taken from
+/// [`with_build_info`](Self::with_build_info). A failure to construct the
+/// exporter is returned from `start` instead of panicking.
+///
+/// The server span of a streaming call lasts until its response stream has
+/// ended rather than until the handler returns, and carries
+/// `stream.request_closed`, `stream.completed` and `stream.cancelled` events
+/// along with the final `rpc.grpc.status_code`. Message counts and the
+/// duration of whole streams are exported as `micro_stream_*` metrics on the
+/// admin listener.
+///
+/// # Access log
+///
+/// With [`GrpcServerConfig::access_log`] enabled, every call is logged to
+/// stdout as a JSON line once its response has been sent, carrying the method,
+/// calling service, gRPC status, duration, peer address, the trace and span
+/// IDs of the server span, and common request metadata such as `app_id`,
+/// `platform` and `member_id`. Successful calls can be sampled and individual
+/// fields redacted; see [`AccessLogConfig`](crate::AccessLogConfig).
+///
+/// # gRPC-Web
+///
+/// With [`GrpcServerConfig::grpc_web`] enabled, browser clients can call the
+/// services with the gRPC-Web protocol, in both its binary and its base64
+/// text framing, over HTTP/1.1 as well as HTTP/2. Web calls are translated
+/// before they enter the rest of the stack, so they are traced, measured and
+/// limited like native calls. Cross-origin requests are checked against the
+/// configured [`CorsConfig`](crate::CorsConfig).
+///
+/// # HTTP/JSON transcoding
+///
+/// RPCs annotated with `google.api.http` options are additionally served as
+/// REST endpoints on the same port once their descriptor set is registered
+/// with [`with_http_transcoding`](Self::with_http_transcoding). Path variables,
+/// query parameters and the JSON body are mapped to the request message, which
+/// is then handled as a gRPC-JSON call by the full middleware stack, so the
+/// JSON conventions of the generated `JsonI64ToStringCodec` apply: fields keep
+/// their proto names and 64-bit integers are written as strings. Failed calls
+/// are answered with the HTTP status matching their gRPC status and a JSON
+/// body such as `{"code":5,"status":"NOT_FOUND","message":"no such order"}`.
+///
+/// # Deadlines
+///
+/// The `grpc-timeout` header of incoming calls is honoured: the handler is
+/// cancelled and the call fails with `DEADLINE_EXCEEDED` once the deadline
+/// passes. The [`Deadline`](crate::Deadline) is available to handlers through
+/// [`RequestExt::deadline`](crate::RequestExt::deadline), and generated
+/// clients forward the remaining budget to downstream services through the
+/// [`PropagateDeadline`](crate::middlewares::PropagateDeadline) middleware.
+///
+/// # Load shedding
+///
+/// HTTP/2 stream limits only bound the calls of a single connection. To bound
+/// the work of the whole server, [`GrpcServerConfig::concurrency`] limits the
+/// number of calls processed at the same time, globally and per method. Calls
+/// beyond a limit wait in an optional bounded queue and are otherwise rejected
+/// immediately with `RESOURCE_EXHAUSTED`, before reaching the handler. Queue
+/// depth and rejections are exported on the admin listener; see
+/// [`ConcurrencyConfig`](crate::ConcurrencyConfig).
+///
+/// Independently, [`GrpcServerConfig::rate_limits`] bounds the rate of calls
+/// per method, calling service, member or application with token buckets.
+/// Calls over a rate limit fail with `RESOURCE_EXHAUSTED` and a
+/// `grpc-retry-pushback-ms` hint; see [`RateLimitRule`](crate::RateLimitRule).
+///
+/// # Listeners
+///
+/// Besides the TCP [`address`](GrpcServerConfig::address), the router can be
+/// served on any number of additional TCP sockets, Unix domain sockets and
+/// inherited file descriptors (for socket activation), configured through
+/// [`GrpcServerConfig::listeners`] or [`add_listener`](Self::add_listener).
+/// Every listener shares the same middleware stack.
+///
+/// # TLS
+///
+/// When [`GrpcServerConfig::tls`] is set, the gRPC listener only accepts TLS
+/// connections, negotiating HTTP/2 through ALPN. The certificate files are
+/// watched and reloaded when they change, and with mutual TLS the identity of
+/// the client certificate is exposed through
+/// [`RequestExt::client_identity`](crate::RequestExt::client_identity). See
+/// [`TlsConfig`](crate::TlsConfig) for details.
+///
+/// # Admin listener
+///
+/// When [`GrpcServerConfig::admin_address`] is set, a separate HTTP listener
+/// serves the Prometheus text exposition of the default registry at
+/// `/metrics`. Besides the request metrics recorded by the middleware stack,
+/// it includes a `micro_build_info` gauge (see
+/// [`with_build_info`](Self::with_build_info)) and, on Linux, the standard
+/// `process_*` collectors.
+///
+/// For troubleshooting a running instance, the admin listener also serves
+/// read-only JSON reports under `/debug`: the registered services and their
+/// methods (`/debug/services`), the effective configuration with credentials
+/// redacted (`/debug/config`), the build metadata (`/debug/build`) and the
+/// middleware stack, outermost first (`/debug/middleware`). Methods are only
+/// listed for services whose descriptor set was registered with
+/// [`with_reflection`](Self::with_reflection) or
+/// [`with_http_transcoding`](Self::with_http_transcoding).
+///
+/// # Health checking
+///
+/// The standard `grpc.health.v1.Health` service is registered automatically.
+/// It reports `SERVING` for the server as a whole (the empty service name) and
+/// for every service added with [`add_service`](Self::add_service) once the
+/// server has started and all [startup hooks](Self::on_start) have succeeded.
+/// Health checks are answered ahead of the built-in middleware stack, so they
+/// are never rejected by the concurrency or rate limits of a busy server, and
+/// are not traced or measured either.
+///
+/// The admin listener additionally serves Kubernetes-style HTTP probes:
+/// `/livez` always succeeds while the process is running, and `/readyz`
+/// succeeds only when startup has completed and every check registered with
+/// [`readiness_check`](Self::readiness_check) passes within the
+/// [`readiness_timeout`](GrpcServerConfig::readiness_timeout).
+///
+/// # Reflection
+///
+/// Services become discoverable by tools such as `grpcurl` once their
+/// descriptor sets are registered with
+/// [`with_reflection`](Self::with_reflection). The reflection service can be
+/// turned off without code changes through [`GrpcServerConfig::reflection`].
+///
+/// # Lifecycle
+///
+/// Work tied to the lifetime of the server is registered on the builder:
+///
+/// - [`on_start`](Self::on_start) hooks run once the listeners are bound and
+///   gate readiness, e.g. to warm caches before traffic arrives.
+/// - [`spawn_background`](Self::spawn_background) and
+///   [`spawn_critical`](Self::spawn_critical) run tasks such as refreshers and
+///   consumers once startup has completed, and cancel them at shutdown. A
+///   failing critical task shuts the server down.
+/// - [`on_shutdown`](Self::on_shutdown) hooks run once the server has
+///   stopped serving, e.g. to flush producers.
 ///
-/// The server listens on the address specified by the `MICRO_SERVER_ADDRESS` environment
-/// variable, falling back to `0.0.0.0:8080` if unset.
+/// # Graceful shutdown
+///
+/// When the process receives `SIGTERM` or `SIGINT` (or the future passed to
+/// [`shutdown_signal`](Self::shutdown_signal) completes), every service is
+/// flipped to `NOT_SERVING` and `/readyz` starts failing. The server then stops
+/// accepting new connections and waits for in-flight calls and background
+/// tasks to finish, up to the [`shutdown_timeout`](Self::shutdown_timeout).
+/// Shutdown hooks then run, and buffered spans are flushed and the tracer
+/// provider is shut down before `start` returns.
 ///
 /// # Examples
 ///
@@ -56,6 +256,21 @@ use crate::middlewares::{RequestDurationMiddleware, SetCurrentService};
 #[derive(Default)]
 pub struct GrpcServer {
     router: RouteGrpc,
+    config: Option<GrpcServerConfig>,
+    build_info: BuildInfo,
+    listeners: Vec<ListenAddress>,
+    health_services: Vec<StatusSetter>,
+    readiness_checks: Vec<ReadinessCheck>,
+    reflection: Option<Reflection>,
+    http_routes: HttpRoutes,
+    services: ServiceCatalog,
+    start_hooks: Vec<Hook>,
+    shutdown_hooks: Vec<Hook>,
+    background_tasks: Vec<BackgroundTask>,
+    shutdown_signal: Option<ShutdownSignal>,
+    shutdown_timeout: Option<Duration>,
+    harness: Option<Harness>,
+    stack: StackOptions,
 }
 
 impl GrpcServer {
@@ -107,6 +322,549 @@ impl GrpcServer {
         S: IntoEndpoint<Endpoint = BoxEndpoint<'static, Response>> + Service,
     {
         self.router = self.router.add_service(service);
+        self.health_services.push(status_setter::<S>());
+        self.services.add_service(S::NAME, None);
+        self
+    }
+
+    /// Registers a gRPC service wrapped in a middleware that applies to this
+    /// service only.
+    ///
+    /// The middleware sits inside the built-in stack, so the calls it rejects
+    /// are still traced, measured and logged like those of every other
+    /// service. It sees requests after routing: the URI path is relative to
+    /// the service, e.g. `/GetUser`, while
+    /// [`original_uri`](poem::Request::original_uri) keeps the full method
+    /// path.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use gear_microkit::GrpcServer;
+    /// # struct AdminService;
+    /// # struct UserService;
+    /// # impl poem::IntoEndpoint for AdminService {
+    /// #     type Endpoint = poem::endpoint::BoxEndpoint<'static, poem::Response>;
+    /// #     fn into_endpoint(self) -> Self::Endpoint { todo!() }
+    /// # }
+    /// # impl poem_grpc::Service for AdminService {
+    /// #     const NAME: &'static str = "admin.AdminService";
+    /// # }
+    /// # impl poem::IntoEndpoint for UserService {
+    /// #     type Endpoint = poem::endpoint::BoxEndpoint<'static, poem::Response>;
+    /// #     fn into_endpoint(self) -> Self::Endpoint { todo!() }
+    /// # }
+    /// # impl poem_grpc::Service for UserService {
+    /// #     const NAME: &'static str = "user.UserService";
+    /// # }
+    /// use poem::middleware::SetHeader;
+    ///
+    /// let server = GrpcServer::new()
+    ///     .add_service_with(AdminService, SetHeader::new().appending("x-admin", "1"))
+    ///     .add_service(UserService);
+    /// ```
+    pub fn add_service_with<S, M>(mut self, service: S, middleware: M) -> Self
+    where
+        S: IntoEndpoint<Endpoint = BoxEndpoint<'static, Response>> + Service,
+        M: Middleware<BoxEndpoint<'static, Response>> + 'static,
+    {
+        self.router = self.router.add_service(WithMiddleware {
+            service,
+            middleware,
+        });
+        self.health_services.push(status_setter::<S>());
+        self.services.add_service(S::NAME, Some(type_name::<M>()));
+        self
+    }
+
+    /// Serves gRPC server reflection for the services described by
+    /// `file_descriptor_set`.
+    ///
+    /// Use [`file_descriptor_set!`](crate::file_descriptor_set!) to include
+    /// the descriptor set written by `gear_codegen::build`, which covers every
+    /// proto compiled by the build script. Calling this more than once merges
+    /// the descriptor sets into a single reflection service. Nothing is served
+    /// when [`GrpcServerConfig::reflection`] is disabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `file_descriptor_set` is not an encoded
+    /// `google.protobuf.FileDescriptorSet`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// use gear_microkit::GrpcServer;
+    ///
+    /// let server = GrpcServer::new().with_reflection(gear_microkit::file_descriptor_set!());
+    /// ```
+    pub fn with_reflection(mut self, file_descriptor_set: &[u8]) -> Self {
+        self.services.add_file_descriptor_set(file_descriptor_set);
+        self.reflection = Some(
+            self.reflection
+                .take()
+                .unwrap_or_default()
+                .add_file_descriptor_set(file_descriptor_set),
+        );
+        self
+    }
+
+    /// Serves the RPCs of `file_descriptor_set` annotated with
+    /// `google.api.http` options as REST endpoints.
+    ///
+    /// Use [`file_descriptor_set!`](crate::file_descriptor_set!) to include
+    /// the descriptor set written by `gear_codegen::build`; the protos then
+    /// import `google/api/annotations.proto`, which has to be placed in the
+    /// `proto` directory. Unary RPCs are supported, including
+    /// `additional_bindings`, `body` and `response_body`; streaming RPCs are
+    /// ignored. See [HTTP/JSON transcoding](Self#httpjson-transcoding).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `file_descriptor_set` is not an encoded
+    /// `google.protobuf.FileDescriptorSet` or declares an invalid path
+    /// template.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// use gear_microkit::GrpcServer;
+    ///
+    /// let server = GrpcServer::new().with_http_transcoding(gear_microkit::file_descriptor_set!());
+    /// ```
+    pub fn with_http_transcoding(mut self, file_descriptor_set: &[u8]) -> Self {
+        self.services.add_file_descriptor_set(file_descriptor_set);
+        self.http_routes
+            .add_file_descriptor_set(file_descriptor_set);
+        self
+    }
+
+    /// Registers an asynchronous readiness check under the given name.
+    ///
+    /// All checks are run concurrently each time the `/readyz` probe on the
+    /// admin listener is requested. The probe fails with
+    /// `503 Service Unavailable` if any check returns an error or does not
+    /// complete within [`GrpcServerConfig::readiness_timeout`], and the error
+    /// messages are reported in the JSON response body.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gear_microkit::GrpcServer;
+    /// # struct Pool;
+    /// # impl Pool {
+    /// #     async fn ping(&self) -> Result<(), std::io::Error> { Ok(()) }
+    /// # }
+    /// # let pool = std::sync::Arc::new(Pool);
+    ///
+    /// let server = GrpcServer::new().readiness_check("database", move || {
+    ///     let pool = pool.clone();
+    ///     async move { pool.ping().await }
+    /// });
+    /// ```
+    pub fn readiness_check<F, Fut, E>(mut self, name: impl Into<String>, check: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: ToString,
+    {
+        self.readiness_checks
+            .push(ReadinessCheck::new(name.into(), check));
+        self
+    }
+
+    /// Registers a hook that runs when the server starts, before it reports
+    /// itself ready.
+    ///
+    /// Hooks run one after another in registration order, once the listeners
+    /// are bound. Until all of them have succeeded, the gRPC health service
+    /// reports `NOT_SERVING` and `/readyz` fails, so hooks can warm caches or
+    /// establish connections before traffic is routed to the instance. If a
+    /// hook fails, the server shuts down and `start` returns its error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gear_microkit::GrpcServer;
+    /// # async fn load_symbols() -> Result<(), std::io::Error> { Ok(()) }
+    ///
+    /// let server = GrpcServer::new().on_start("warm symbol cache", load_symbols);
+    /// ```
+    pub fn on_start<F, Fut, E>(mut self, name: impl Into<String>, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: ToString,
+    {
+        self.start_hooks.push(Hook::new(name.into(), hook));
+        self
+    }
+
+    /// Registers a hook that runs after the server has stopped serving.
+    ///
+    /// Hooks run one after another in registration order, once in-flight
+    /// calls have drained and background tasks have finished, but before
+    /// buffered spans are flushed. Failures are logged and do not prevent the
+    /// remaining hooks from running.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gear_microkit::GrpcServer;
+    /// # async fn flush_producer() -> Result<(), std::io::Error> { Ok(()) }
+    ///
+    /// let server = GrpcServer::new().on_shutdown("flush kafka producer", flush_producer);
+    /// ```
+    pub fn on_shutdown<F, Fut, E>(mut self, name: impl Into<String>, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: ToString,
+    {
+        self.shutdown_hooks.push(Hook::new(name.into(), hook));
+        self
+    }
+
+    /// Runs a background task, such as a cache refresher or a message
+    /// consumer, for the lifetime of the server.
+    ///
+    /// The task is spawned once all [startup hooks](Self::on_start) have
+    /// succeeded. It receives a [`ShutdownToken`] that is cancelled when
+    /// shutdown begins, and is aborted if it is still running after the
+    /// [`shutdown_timeout`](Self::shutdown_timeout). Errors and panics are
+    /// logged; use [`spawn_critical`](Self::spawn_critical) for tasks the
+    /// server cannot run without.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use gear_microkit::GrpcServer;
+    ///
+    /// let server = GrpcServer::new().spawn_background("refresh rates", |shutdown| async move {
+    ///     while !shutdown.is_cancelled() {
+    ///         // refresh the rates
+    ///         tokio::select! {
+    ///             _ = shutdown.cancelled() => {}
+    ///             _ = tokio::time::sleep(Duration::from_secs(60)) => {}
+    ///         }
+    ///     }
+    ///     Ok::<_, std::io::Error>(())
+    /// });
+    /// ```
+    pub fn spawn_background<F, Fut, E>(mut self, name: impl Into<String>, task: F) -> Self
+    where
+        F: FnOnce(ShutdownToken) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: ToString,
+    {
+        self.background_tasks
+            .push(BackgroundTask::new(name.into(), false, task));
+        self
+    }
+
+    /// Runs a background task the server cannot run without.
+    ///
+    /// Behaves like [`spawn_background`](Self::spawn_background), except that
+    /// the task returning an error or panicking shuts the server down
+    /// gracefully, and `start` then returns the task's error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gear_microkit::{GrpcServer, ShutdownToken};
+    /// # async fn consume_orders(shutdown: ShutdownToken) -> Result<(), std::io::Error> { Ok(()) }
+    ///
+    /// let server = GrpcServer::new().spawn_critical("order consumer", consume_orders);
+    /// ```
+    pub fn spawn_critical<F, Fut, E>(mut self, name: impl Into<String>, task: F) -> Self
+    where
+        F: FnOnce(ShutdownToken) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: ToString,
+    {
+        self.background_tasks
+            .push(BackgroundTask::new(name.into(), true, task));
+        self
+    }
+
+    /// Uses the given configuration instead of loading one with
+    /// [`GrpcServerConfig::from_env`] at startup.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gear_microkit::{GrpcServer, GrpcServerConfig};
+    ///
+    /// let server = GrpcServer::new().with_config(GrpcServerConfig {
+    ///     address: "127.0.0.1:9090".to_string(),
+    ///     ..Default::default()
+    /// });
+    /// ```
+    pub fn with_config(mut self, config: GrpcServerConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Serves the router on an additional listener.
+    ///
+    /// The listener is added to the ones from
+    /// [`GrpcServerConfig::listeners`]; see [`ListenAddress`] for the
+    /// supported kinds.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::path::PathBuf;
+    ///
+    /// use gear_microkit::{GrpcServer, ListenAddress};
+    ///
+    /// let server = GrpcServer::new()
+    ///     .add_listener(ListenAddress::Unix(PathBuf::from("/run/gear/grpc.sock")));
+    /// ```
+    pub fn add_listener(mut self, address: ListenAddress) -> Self {
+        self.listeners.push(address);
+        self
+    }
+
+    /// Sets the application identity exported as the `micro_build_info`
+    /// metric on the admin listener.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gear_microkit::GrpcServer;
+    ///
+    /// let server = GrpcServer::new().with_build_info(gear_microkit::build_info!());
+    /// ```
+    pub fn with_build_info(mut self, build_info: BuildInfo) -> Self {
+        self.build_info = build_info;
+        self
+    }
+
+    /// Sets a custom future that triggers graceful shutdown when it completes.
+    ///
+    /// By default the server shuts down on `SIGTERM` or `SIGINT`. Supplying a
+    /// signal replaces the OS signal handling, which is useful for tests and
+    /// for embedding the server in a larger application.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use gear_microkit::GrpcServer;
+    /// use tokio::sync::oneshot;
+    ///
+    /// #[tokio::main(flavor = "current_thread")]
+    /// async fn main() -> std::io::Result<()> {
+    ///     let (tx, rx) = oneshot::channel::<()>();
+    ///     # drop(tx);
+    ///     GrpcServer::new()
+    ///         .shutdown_signal(async move {
+    ///             let _ = rx.await;
+    ///         })
+    ///         .start()
+    ///         .await
+    /// }
+    /// ```
+    pub fn shutdown_signal<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown_signal = Some(Box::pin(signal));
+        self
+    }
+
+    /// Sets the maximum time to wait for in-flight calls to complete once
+    /// shutdown has been triggered.
+    ///
+    /// Connections still open after the timeout are closed forcibly. This
+    /// overrides [`GrpcServerConfig::shutdown_timeout`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use gear_microkit::GrpcServer;
+    ///
+    /// let server = GrpcServer::new().shutdown_timeout(Duration::from_secs(10));
+    /// ```
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Builds the router wrapped in the built-in middleware stack, without
+    /// starting a server, so the services can be mounted into a larger poem
+    /// application.
+    ///
+    /// The endpoint can be nested under a path prefix or combined with other
+    /// routes on a listener of the application. gRPC clients still have to
+    /// speak HTTP/2 to it, which poem's [`Server`] accepts alongside HTTP/1.1.
+    ///
+    /// The configuration is loaded and validated like in
+    /// [`start`](Self::start), and spans are exported according to
+    /// [`GrpcServerConfig::tracing`] until the endpoint is dropped.
+    ///
+    /// # Lifecycle
+    ///
+    /// Everything tied to the server process is left to the application:
+    ///
+    /// - No listener or admin listener is bound, so readiness checks are
+    ///   never evaluated.
+    /// - [`on_start`](Self::on_start) hooks are not run. Work they would do,
+    ///   e.g. warming caches, has to be done before the application starts
+    ///   serving the endpoint.
+    /// - [`on_shutdown`](Self::on_shutdown) hooks are not run and background
+    ///   tasks are not spawned.
+    /// - The health service reports `SERVING` as soon as the endpoint is
+    ///   built, and keeps doing so while the application shuts down.
+    ///
+    /// Registered hooks and tasks are dropped with a warning.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the configuration is invalid or the trace
+    /// exporter cannot be initialized.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use gear_microkit::GrpcServer;
+    /// use poem::{get, handler, listener::TcpListener, Route, Server};
+    ///
+    /// #[handler]
+    /// fn index() -> &'static str {
+    ///     "hello"
+    /// }
+    ///
+    /// #[tokio::main(flavor = "current_thread")]
+    /// async fn main() -> std::io::Result<()> {
+    ///     let grpc = GrpcServer::new()
+    ///         // .add_service(my_grpc_service)
+    ///         .into_endpoint()?;
+    ///     let app = Route::new().at("/", get(index)).nest("/grpc", grpc);
+    ///     Server::new(TcpListener::bind("0.0.0.0:8080"))
+    ///         .run(app)
+    ///         .await
+    /// }
+    /// ```
+    pub fn into_endpoint(self) -> io::Result<BoxEndpoint<'static, Response>> {
+        let config = match self.config {
+            Some(config) => config,
+            None => GrpcServerConfig::from_env()?,
+        };
+        config.validate()?;
+
+        for (kind, count) in [
+            ("start hooks", self.start_hooks.len()),
+            ("shutdown hooks", self.shutdown_hooks.len()),
+            ("background tasks", self.background_tasks.len()),
+            ("readiness checks", self.readiness_checks.len()),
+        ] {
+            if count > 0 {
+                tracing::warn!(count, "ignoring {kind}, which `into_endpoint` does not run");
+            }
+        }
+
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer_provider = telemetry::init_tracer_provider(&config.tracing, self.build_info)?;
+        let tracer = tracer_provider.tracer_with_scope(telemetry::instrumentation_scope());
+        let (health_service, health_reporter) = poem_grpc::health_service();
+        Health::new(
+            health_reporter,
+            self.health_services,
+            Vec::new(),
+            config.readiness_timeout,
+        )
+        .set_serving();
+        let (app, _) = wrap_router(
+            self.router,
+            self.reflection,
+            self.http_routes,
+            self.stack,
+            &config,
+            tracer,
+            ClientRegistry::default(),
+        );
+        Ok(BypassHealth::new(health_service, app).boxed())
+    }
+
+    /// Removes a layer from the built-in middleware stack.
+    ///
+    /// Custom middleware inserted next to the layer with
+    /// [`middleware_outside`](Self::middleware_outside) or
+    /// [`middleware_inside`](Self::middleware_inside) keeps its position.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gear_microkit::{BuiltinMiddleware, GrpcServer};
+    ///
+    /// let server = GrpcServer::new().disable_middleware(BuiltinMiddleware::OpenTelemetryMetrics);
+    /// ```
+    pub fn disable_middleware(mut self, middleware: BuiltinMiddleware) -> Self {
+        self.stack.disable(middleware);
+        self
+    }
+
+    /// Inserts `middleware` into the built-in stack directly outside of
+    /// `anchor`, so it sees requests before `anchor` does and responses after.
+    ///
+    /// Middleware inserted at the same position is applied in registration
+    /// order, each wrapping the previous one.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gear_microkit::{BuiltinMiddleware, GrpcServer};
+    /// use poem::middleware::SetHeader;
+    ///
+    /// let server = GrpcServer::new().middleware_outside(
+    ///     BuiltinMiddleware::RateLimit,
+    ///     SetHeader::new().appending("x-powered-by", "gear"),
+    /// );
+    /// ```
+    pub fn middleware_outside<M>(mut self, anchor: BuiltinMiddleware, middleware: M) -> Self
+    where
+        M: Middleware<BoxEndpoint<'static, Response>> + Send + 'static,
+    {
+        self.stack.insert(anchor, Placement::Outside, middleware);
+        self
+    }
+
+    /// Inserts `middleware` into the built-in stack directly inside of
+    /// `anchor`, so it sees requests after `anchor` does and responses before.
+    ///
+    /// Middleware inserted at the same position is applied in registration
+    /// order, each wrapping the previous one.
+    ///
+    /// # Examples
+    ///
+    /// Run a middleware within the server span, but outside of the request
+    /// metrics:
+    ///
+    /// ```rust
+    /// use gear_microkit::{BuiltinMiddleware, GrpcServer};
+    /// use poem::middleware::SetHeader;
+    ///
+    /// let server = GrpcServer::new().middleware_inside(
+    ///     BuiltinMiddleware::OpenTelemetryTracing,
+    ///     SetHeader::new().appending("x-powered-by", "gear"),
+    /// );
+    /// ```
+    pub fn middleware_inside<M>(mut self, anchor: BuiltinMiddleware, middleware: M) -> Self
+    where
+        M: Middleware<BoxEndpoint<'static, Response>> + Send + 'static,
+    {
+        self.stack.insert(anchor, Placement::Inside, middleware);
+        self
+    }
+
+    /// Starts the server from a [`TestServer`](crate::testing::TestServer).
+    pub(crate) fn with_harness(mut self, harness: Harness) -> Self {
+        self.harness = Some(harness);
         self
     }
 
@@ -115,13 +873,20 @@ impl GrpcServer {
     ///
     /// This is useful when you need to add custom authentication, rate-limiting,
     /// or other cross-cutting concerns on top of the default middleware stack.
+    /// To wrap individual services instead, register them with
+    /// [`add_service_with`](Self::add_service_with).
     ///
     /// Pass `()` as the middleware to use only the built-in stack (equivalent to
     /// calling [`start`](Self::start)).
     ///
+    /// The returned future resolves once the server has shut down gracefully;
+    /// see [Graceful shutdown](Self#graceful-shutdown).
+    ///
     /// # Errors
     ///
-    /// Returns an [`io::Error`] if the TCP listener fails to bind or the server
+    /// Returns an [`io::Error`] if the configuration is invalid, the TCP
+    /// listener fails to bind, the TLS certificates cannot be loaded, a
+    /// startup hook or critical background task fails, or the server
     /// encounters a fatal runtime error.
     ///
     /// # Examples
@@ -141,39 +906,121 @@ impl GrpcServer {
     where
         T: Middleware<BoxEndpoint<'static, Response>> + 'static,
     {
+        let mut config = match self.config {
+            Some(config) => config,
+            None if self.harness.is_some() => GrpcServerConfig::default(),
+            None => GrpcServerConfig::from_env()?,
+        };
+        if self.harness.is_some() {
+            Harness::configure(&mut config);
+        }
+        config.listeners.extend(self.listeners);
+        config.validate()?;
+        let client_registry = ClientRegistry::default();
+        let acceptor = bind(&config, &client_registry).await?;
+        let local_addr = acceptor
+            .local_addr()
+            .first()
+            .and_then(|addr| addr.as_socket_addr().copied());
+        let admin = match &config.admin_address {
+            Some(address) => Some(AdminServer::bind(address).await?),
+            None => None,
+        };
+
         global::set_text_map_propagator(TraceContextPropagator::new());
-        let tracer_provider = SdkTracerProvider::builder()
-            .with_batch_exporter(
-                opentelemetry_otlp::SpanExporter::builder()
-                    .with_tonic()
-                    .build()
-                    .expect("Trace exporter should initialize."),
-            )
-            .build();
-        let tracer = tracer_provider.tracer("gear-rs");
-        let app = self
-            .router
-            .with(
-                AddData::new(tracer.clone())
-                    .combine(OpenTelemetryTracing::new(tracer))
-                    .combine(OpenTelemetryMetrics::new())
-                    .combine(SetCurrentService)
-                    .combine_if(
-                        std::env::var("GEAR_ENABLE_TOKIO_METRICS").as_deref() == Ok("1"),
-                        TokioMetrics::new(),
-                    )
-                    .combine(RequestDurationMiddleware::new()),
-            )
+        let (tracer_provider, ready) = match self.harness {
+            Some(harness) => (
+                telemetry::init_tracer_provider_with_exporter(
+                    &config.tracing,
+                    self.build_info,
+                    harness.spans,
+                ),
+                Some(harness.ready),
+            ),
+            None => (
+                telemetry::init_tracer_provider(&config.tracing, self.build_info)?,
+                None,
+            ),
+        };
+        let tracer = tracer_provider.tracer_with_scope(telemetry::instrumentation_scope());
+        let (health_service, health_reporter) = poem_grpc::health_service();
+        let health = Arc::new(Health::new(
+            health_reporter,
+            self.health_services,
+            self.readiness_checks,
+            config.readiness_timeout,
+        ));
+        let shutdown = Shutdown::new();
+        let shutdown_signal = self
+            .shutdown_signal
+            .unwrap_or_else(|| Box::pin(os_signal()));
+        let shutdown_signal = {
+            let health = health.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                let token = shutdown.token();
+                tokio::select! {
+                    _ = shutdown_signal => {}
+                    _ = token.cancelled() => {}
+                }
+                health.set_shutting_down();
+                shutdown.trigger();
+            }
+        };
+        let shutdown_timeout = self.shutdown_timeout.unwrap_or(config.shutdown_timeout);
+        let (app, mut layers) = wrap_router(
+            self.router,
+            self.reflection,
+            self.http_routes,
+            self.stack,
+            &config,
+            tracer,
+            client_registry,
+        );
+        let app = BypassHealth::new(health_service, app)
+            .boxed()
+            .with(middleware)
+            .map_to_response()
             .boxed();
-        let app = app.with(middleware);
+        if type_name::<T>() != "()" {
+            layers.insert(0, type_name::<T>());
+        }
+        let debug = Arc::new(DebugInfo::new(
+            &self.services,
+            &config,
+            self.build_info,
+            &layers,
+        ));
 
-        let grpc_server = Server::new(TcpListener::bind(
-            std::env::var("MICRO_SERVER_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8080".to_string()),
-        ))
-        .http2_max_concurrent_streams(None)
-        .http2_max_header_list_size(16384 * 64)
-        .run(app);
-        tokio::try_join!(grpc_server).map(|_| ())
+        let admin = admin.map(|admin| admin.spawn(self.build_info, health.clone(), debug));
+        let (start_hooks, background_tasks) = (self.start_hooks, self.background_tasks);
+        let lifecycle = async move {
+            let token = shutdown.token();
+            let started = tokio::select! {
+                res = run_start_hooks(start_hooks) => res,
+                _ = token.cancelled() => return Ok(()),
+            };
+            if let Err(err) = started {
+                shutdown.trigger();
+                return Err(err);
+            }
+            health.set_serving();
+            if let (Some(ready), Some(local_addr)) = (ready, local_addr) {
+                let _ = ready.send(local_addr);
+            }
+            supervise(background_tasks, shutdown, shutdown_timeout).await
+        };
+        let (res, lifecycle_res) = tokio::join!(
+            serve(acceptor, app, &config, shutdown_signal, shutdown_timeout),
+            lifecycle,
+        );
+
+        run_shutdown_hooks(self.shutdown_hooks).await;
+        shutdown_tracer_provider(tracer_provider).await;
+        if let Some(admin) = admin {
+            admin.shutdown().await?;
+        }
+        lifecycle_res.and(res)
     }
 
     /// Starts the server with only the built-in middleware stack.
@@ -183,7 +1030,9 @@ impl GrpcServer {
     ///
     /// # Errors
     ///
-    /// Returns an [`io::Error`] if the TCP listener fails to bind or the server
+    /// Returns an [`io::Error`] if the configuration is invalid, the TCP
+    /// listener fails to bind, the TLS certificates cannot be loaded, a
+    /// startup hook or critical background task fails, or the server
     /// encounters a fatal runtime error.
     ///
     /// # Examples
@@ -202,3 +1051,119 @@ impl GrpcServer {
         self.start_with_middleware(()).await
     }
 }
+
+/// A service wrapped in the middleware passed to
+/// [`add_service_with`](GrpcServer::add_service_with).
+struct WithMiddleware<S, M> {
+    service: S,
+    middleware: M,
+}
+
+impl<S: Service, M> Service for WithMiddleware<S, M> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, M> IntoEndpoint for WithMiddleware<S, M>
+where
+    S: IntoEndpoint<Endpoint = BoxEndpoint<'static, Response>>,
+    M: Middleware<BoxEndpoint<'static, Response>> + 'static,
+{
+    type Endpoint = BoxEndpoint<'static, Response>;
+
+    fn into_endpoint(self) -> Self::Endpoint {
+        self.service
+            .into_endpoint()
+            .with(self.middleware)
+            .map_to_response()
+            .boxed()
+    }
+}
+
+/// Adds the reflection service to `router`, unless disabled, and wraps it in
+/// the built-in middleware stack.
+///
+/// Returns the names of the applied layers, outermost first, as reported on
+/// `/debug/middleware`.
+fn wrap_router(
+    mut router: RouteGrpc,
+    reflection: Option<Reflection>,
+    http_routes: HttpRoutes,
+    stack: StackOptions,
+    config: &GrpcServerConfig,
+    tracer: Tracer,
+    client_registry: ClientRegistry,
+) -> (BoxEndpoint<'static, Response>, Vec<&'static str>) {
+    if let Some(reflection) = reflection.filter(|_| config.reflection) {
+        router = router.add_service(reflection.build());
+    }
+    let known_methods = KnownMethods::new(config);
+    let router = RecordMethods::new(router.boxed(), known_methods.clone()).boxed();
+    Stack::new(router, stack)
+        .fixed("AddData", true, || AddData::new(tracer.clone()))
+        .fixed("SetClientIdentity", config.tls.is_some(), || {
+            SetClientIdentity::new(client_registry)
+        })
+        .builtin(BuiltinMiddleware::RecoverPanic, true, || {
+            RecoverPanic::new(known_methods.clone())
+        })
+        .builtin(
+            BuiltinMiddleware::ConcurrencyLimit,
+            config.concurrency.is_enabled(),
+            || ConcurrencyLimit::new(&config.concurrency, known_methods.clone()),
+        )
+        .builtin(BuiltinMiddleware::ServerDeadline, true, || ServerDeadline)
+        .builtin(
+            BuiltinMiddleware::RateLimit,
+            !config.rate_limits.is_empty(),
+            || RateLimit::new(&config.rate_limits, known_methods.clone()),
+        )
+        .builtin(BuiltinMiddleware::SetCurrentService, true, || {
+            SetCurrentService
+        })
+        .builtin(
+            BuiltinMiddleware::RequestDuration,
+            true,
+            RequestDurationMiddleware::new,
+        )
+        .builtin(
+            BuiltinMiddleware::TokioMetrics,
+            config.enable_tokio_metrics,
+            TokioMetrics::new,
+        )
+        .builtin(
+            BuiltinMiddleware::Compression,
+            config.compression.enabled,
+            || Compression::new(&config.compression),
+        )
+        .builtin(
+            BuiltinMiddleware::MessageSizeLimit,
+            config.message_size.is_enabled(),
+            || MessageSizeLimit::new(&config.message_size),
+        )
+        .builtin(
+            BuiltinMiddleware::AccessLog,
+            config.access_log.enabled,
+            || AccessLog::new(&config.access_log),
+        )
+        .builtin(
+            BuiltinMiddleware::StreamTelemetry,
+            true,
+            StreamTelemetry::new,
+        )
+        .builtin(
+            BuiltinMiddleware::OpenTelemetryMetrics,
+            true,
+            OpenTelemetryMetrics::new,
+        )
+        .builtin(BuiltinMiddleware::OpenTelemetryTracing, true, || {
+            OpenTelemetryTracing::new(tracer)
+        })
+        .fixed("Transcode", !http_routes.is_empty(), || {
+            Transcode::new(http_routes, &config.message_size)
+        })
+        .fixed("GrpcWeb", config.grpc_web.enabled, || GrpcWeb)
+        .fixed("Cors", config.grpc_web.enabled, || {
+            cors(&config.grpc_web.cors)
+        })
+        .finish()
+}
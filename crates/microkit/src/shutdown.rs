@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use opentelemetry_sdk::trace::SdkTracerProvider;
+
+/// The default time allowed for in-flight requests to complete after a
+/// shutdown signal is received.
+///
+/// Kubernetes sends `SIGKILL` 30 seconds after `SIGTERM` by default, so this
+/// leaves some headroom for flushing buffered spans once draining completes.
+pub(crate) const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Resolves when the process receives `SIGINT` (Ctrl-C) or, on Unix, `SIGTERM`.
+///
+/// If a signal handler cannot be installed, the corresponding branch never
+/// resolves instead of aborting the server.
+pub(crate) async fn os_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("received SIGINT, shutting down"),
+        _ = terminate => tracing::info!("received SIGTERM, shutting down"),
+    }
+}
+
+/// Flushes all pending spans and shuts down the tracer provider.
+///
+/// The SDK calls block until the exporter finishes, so they are run on the
+/// blocking thread pool. Failures are logged rather than returned because the
+/// server itself has already stopped cleanly at this point.
+pub(crate) async fn shutdown_tracer_provider(tracer_provider: SdkTracerProvider) {
+    let res = tokio::task::spawn_blocking(move || {
+        if let Err(err) = tracer_provider.force_flush() {
+            tracing::warn!(error = %err, "failed to flush spans");
+        }
+        tracer_provider.shutdown()
+    })
+    .await;
+
+    match res {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => tracing::warn!(error = %err, "failed to shut down tracer provider"),
+        Err(err) => tracing::warn!(error = %err, "tracer provider shutdown task failed"),
+    }
+}
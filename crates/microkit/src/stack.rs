@@ -0,0 +1,195 @@
+use std::{any::type_name, collections::HashSet, mem};
+
+use poem::{endpoint::BoxEndpoint, EndpointExt, Middleware, Response};
+
+/// A layer of the built-in middleware stack of [`GrpcServer`] that can be
+/// disabled or have custom middleware inserted next to it.
+///
+/// The variants are listed from the outermost layer to the innermost. The
+/// layers that are not listed either carry data other middleware depends on
+/// (`AddData`, `SetClientIdentity`) or are enabled explicitly (`Cors`,
+/// `GrpcWeb`, `Transcode`), so they are always applied when configured.
+///
+/// [`GrpcServer`]: crate::GrpcServer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BuiltinMiddleware {
+    /// The server span of every call.
+    OpenTelemetryTracing,
+    /// The OpenTelemetry request metrics.
+    OpenTelemetryMetrics,
+    /// The message counts, stream durations and span events covering the
+    /// whole lifetime of streaming calls.
+    StreamTelemetry,
+    /// The JSON access log, when enabled in the configuration.
+    AccessLog,
+    /// The configured message size limits.
+    MessageSizeLimit,
+    /// The negotiation of gRPC message compression, when enabled in the
+    /// configuration.
+    Compression,
+    /// The Tokio runtime metrics, when enabled in the configuration.
+    TokioMetrics,
+    /// The `micro_request_duration_seconds` Prometheus histogram.
+    RequestDuration,
+    /// The name of the called service, read by the `AddClientHeaders` client
+    /// middleware.
+    SetCurrentService,
+    /// The configured rate limits.
+    RateLimit,
+    /// The `grpc-timeout` deadline of incoming calls.
+    ServerDeadline,
+    /// The configured concurrency limits.
+    ConcurrencyLimit,
+    /// The conversion of handler panics into `INTERNAL` statuses.
+    RecoverPanic,
+}
+
+impl BuiltinMiddleware {
+    fn name(self) -> &'static str {
+        match self {
+            Self::OpenTelemetryTracing => "OpenTelemetryTracing",
+            Self::OpenTelemetryMetrics => "OpenTelemetryMetrics",
+            Self::StreamTelemetry => "StreamTelemetry",
+            Self::AccessLog => "AccessLog",
+            Self::MessageSizeLimit => "MessageSizeLimit",
+            Self::Compression => "Compression",
+            Self::TokioMetrics => "TokioMetrics",
+            Self::RequestDuration => "RequestDurationMiddleware",
+            Self::SetCurrentService => "SetCurrentService",
+            Self::RateLimit => "RateLimit",
+            Self::ServerDeadline => "ServerDeadline",
+            Self::ConcurrencyLimit => "ConcurrencyLimit",
+            Self::RecoverPanic => "RecoverPanic",
+        }
+    }
+}
+
+type Layer =
+    Box<dyn FnOnce(BoxEndpoint<'static, Response>) -> BoxEndpoint<'static, Response> + Send>;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Placement {
+    /// Wrapping the built-in layer, seeing requests before it.
+    Outside,
+    /// Wrapped by the built-in layer, seeing requests after it.
+    Inside,
+}
+
+/// The changes to the built-in middleware stack made on the
+/// [`GrpcServer`](crate::GrpcServer) builder.
+#[derive(Default)]
+pub(crate) struct StackOptions {
+    disabled: HashSet<BuiltinMiddleware>,
+    inserted: Vec<Inserted>,
+}
+
+struct Inserted {
+    anchor: BuiltinMiddleware,
+    placement: Placement,
+    name: &'static str,
+    layer: Layer,
+}
+
+impl StackOptions {
+    pub(crate) fn disable(&mut self, middleware: BuiltinMiddleware) {
+        self.disabled.insert(middleware);
+    }
+
+    pub(crate) fn insert<M>(
+        &mut self,
+        anchor: BuiltinMiddleware,
+        placement: Placement,
+        middleware: M,
+    ) where
+        M: Middleware<BoxEndpoint<'static, Response>> + Send + 'static,
+    {
+        self.inserted.push(Inserted {
+            anchor,
+            placement,
+            name: type_name::<M>(),
+            layer: Box::new(move |ep| boxed(ep, middleware)),
+        });
+    }
+}
+
+/// The middleware stack under construction, from the innermost layer
+/// outwards.
+///
+/// Every layer is boxed, so the endpoint type does not grow with the number
+/// of optional layers.
+pub(crate) struct Stack {
+    app: BoxEndpoint<'static, Response>,
+    options: StackOptions,
+    /// The names of the applied layers, innermost first.
+    names: Vec<&'static str>,
+}
+
+impl Stack {
+    pub(crate) fn new(app: BoxEndpoint<'static, Response>, options: StackOptions) -> Self {
+        Self {
+            app,
+            options,
+            names: Vec::new(),
+        }
+    }
+
+    /// Applies a layer that cannot be disabled, if `enabled`.
+    pub(crate) fn fixed<M>(
+        mut self,
+        name: &'static str,
+        enabled: bool,
+        middleware: impl FnOnce() -> M,
+    ) -> Self
+    where
+        M: Middleware<BoxEndpoint<'static, Response>> + 'static,
+    {
+        if enabled {
+            self.app = boxed(self.app, middleware());
+            self.names.push(name);
+        }
+        self
+    }
+
+    /// Applies a built-in layer, if `enabled` and not disabled on the builder,
+    /// with the custom middleware inserted around it.
+    pub(crate) fn builtin<M>(
+        self,
+        builtin: BuiltinMiddleware,
+        enabled: bool,
+        middleware: impl FnOnce() -> M,
+    ) -> Self
+    where
+        M: Middleware<BoxEndpoint<'static, Response>> + 'static,
+    {
+        let enabled = enabled && !self.options.disabled.contains(&builtin);
+        self.inserted(builtin, Placement::Inside)
+            .fixed(builtin.name(), enabled, middleware)
+            .inserted(builtin, Placement::Outside)
+    }
+
+    fn inserted(mut self, anchor: BuiltinMiddleware, placement: Placement) -> Self {
+        let (matching, rest): (Vec<_>, _) = mem::take(&mut self.options.inserted)
+            .into_iter()
+            .partition(|inserted| inserted.anchor == anchor && inserted.placement == placement);
+        self.options.inserted = rest;
+        for inserted in matching {
+            self.app = (inserted.layer)(self.app);
+            self.names.push(inserted.name);
+        }
+        self
+    }
+
+    /// Returns the endpoint and the names of its layers, outermost first.
+    pub(crate) fn finish(mut self) -> (BoxEndpoint<'static, Response>, Vec<&'static str>) {
+        self.names.reverse();
+        (self.app, self.names)
+    }
+}
+
+fn boxed<M>(ep: BoxEndpoint<'static, Response>, middleware: M) -> BoxEndpoint<'static, Response>
+where
+    M: Middleware<BoxEndpoint<'static, Response>> + 'static,
+{
+    ep.with(middleware).map_to_response().boxed()
+}
@@ -0,0 +1,110 @@
+use percent_encoding::{percent_encode, AsciiSet, CONTROLS};
+use poem::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    Response,
+};
+use poem_grpc::{Code, Status};
+
+/// The characters percent-encoded in `grpc-message`, matching poem-grpc.
+const MESSAGE_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'<')
+    .add(b'>')
+    .add(b'`')
+    .add(b'?')
+    .add(b'{')
+    .add(b'}');
+
+/// Builds a trailers-only gRPC response carrying `status`.
+///
+/// Used by middleware that rejects a call before it reaches the service, where
+/// poem-grpc's own status encoding is not available.
+pub(crate) fn status_response(status: &Status) -> Response {
+    let mut resp = Response::builder()
+        .content_type("application/grpc")
+        .finish();
+    resp.headers_mut().extend(status_headers(status));
+    resp
+}
+
+/// Encodes `status` as the `grpc-status` and `grpc-message` headers, for a
+/// trailers-only response or the trailers of a body.
+pub(crate) fn status_headers(status: &Status) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("grpc-status", HeaderValue::from(status.code().as_u16()));
+    if let Some(message) = status.message().and_then(|message| {
+        HeaderValue::from_str(&percent_encode(message.as_bytes(), MESSAGE_ENCODE_SET).to_string())
+            .ok()
+    }) {
+        headers.insert("grpc-message", message);
+    }
+    headers
+}
+
+/// Returns the canonical name of `code`, e.g. `RESOURCE_EXHAUSTED`.
+pub(crate) fn code_name(code: Code) -> &'static str {
+    match code {
+        Code::Ok => "OK",
+        Code::Cancelled => "CANCELLED",
+        Code::Unknown => "UNKNOWN",
+        Code::InvalidArgument => "INVALID_ARGUMENT",
+        Code::DeadlineExceeded => "DEADLINE_EXCEEDED",
+        Code::NotFound => "NOT_FOUND",
+        Code::AlreadyExists => "ALREADY_EXISTS",
+        Code::PermissionDenied => "PERMISSION_DENIED",
+        Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+        Code::FailedPrecondition => "FAILED_PRECONDITION",
+        Code::Aborted => "ABORTED",
+        Code::OutOfRange => "OUT_OF_RANGE",
+        Code::Unimplemented => "UNIMPLEMENTED",
+        Code::Internal => "INTERNAL",
+        Code::Unavailable => "UNAVAILABLE",
+        Code::DataLoss => "DATA_LOSS",
+        Code::Unauthenticated => "UNAUTHENTICATED",
+        Code::Other(_) => "OTHER",
+    }
+}
+
+/// Maps `code` to the HTTP status used when a call is exposed as a REST
+/// endpoint, following `google.rpc.Code`.
+pub(crate) fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::Cancelled => StatusCode::from_u16(499).expect("valid status code"),
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unknown | Code::Internal | Code::DataLoss | Code::Other(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Reads the `grpc-status` of a header or trailer map.
+pub(crate) fn grpc_status(headers: &HeaderMap) -> Option<Code> {
+    headers
+        .get("grpc-status")?
+        .to_str()
+        .ok()?
+        .parse::<u16>()
+        .ok()
+        .map(Code::from)
+}
+
+/// Returns `true` if `headers` carry a gRPC content type, of any codec.
+pub(crate) fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/grpc"))
+}
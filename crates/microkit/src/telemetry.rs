@@ -0,0 +1,238 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+};
+
+use opentelemetry::{
+    trace::{SpanKind, Status},
+    InstrumentationScope, KeyValue,
+};
+use opentelemetry_otlp::{Protocol, WithExportConfig};
+use opentelemetry_sdk::{
+    error::{OTelSdkError, OTelSdkResult},
+    resource::TelemetryResourceDetector,
+    trace::{Sampler, SdkTracerProvider, SpanData, SpanExporter, TracerProviderBuilder},
+    Resource,
+};
+use opentelemetry_semantic_conventions::resource::{
+    DEPLOYMENT_ENVIRONMENT_NAME, HOST_NAME, K8S_NAMESPACE_NAME, K8S_NODE_NAME, K8S_POD_NAME,
+    SERVICE_NAME, SERVICE_VERSION,
+};
+use serde_json::json;
+
+use crate::{BuildInfo, OtlpProtocol, TraceExporter, TraceSampler, TracingConfig};
+
+const K8S_NAMESPACE_FILE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+/// Builds the tracer provider described by `config`.
+///
+/// Exporter construction errors are returned instead of panicking, so a
+/// misconfigured collector endpoint fails startup with a readable message.
+pub(crate) fn init_tracer_provider(
+    config: &TracingConfig,
+    build_info: BuildInfo,
+) -> io::Result<SdkTracerProvider> {
+    let builder = tracer_provider_builder(config, build_info);
+    let builder = match config.exporter {
+        TraceExporter::Otlp => {
+            let exporter = match config.otlp_protocol {
+                OtlpProtocol::Grpc => {
+                    let mut builder = opentelemetry_otlp::SpanExporter::builder().with_tonic();
+                    if let Some(endpoint) = otlp_endpoint(config) {
+                        builder = builder.with_endpoint(endpoint);
+                    }
+                    builder.build()
+                }
+                OtlpProtocol::HttpProtobuf => {
+                    let mut builder = opentelemetry_otlp::SpanExporter::builder()
+                        .with_http()
+                        .with_protocol(Protocol::HttpBinary);
+                    if let Some(endpoint) = otlp_endpoint(config) {
+                        builder = builder.with_endpoint(endpoint);
+                    }
+                    builder.build()
+                }
+            }
+            .map_err(|err| {
+                io::Error::other(format!("failed to initialize trace exporter: {err}"))
+            })?;
+            builder.with_batch_exporter(exporter)
+        }
+        TraceExporter::Stdout => builder.with_simple_exporter(StdoutSpanExporter),
+        TraceExporter::None => builder,
+    };
+    Ok(builder.build())
+}
+
+/// Builds a tracer provider that hands every finished span to `exporter`
+/// synchronously, ignoring the configured exporter.
+pub(crate) fn init_tracer_provider_with_exporter(
+    config: &TracingConfig,
+    build_info: BuildInfo,
+    exporter: impl SpanExporter + 'static,
+) -> SdkTracerProvider {
+    tracer_provider_builder(config, build_info)
+        .with_simple_exporter(exporter)
+        .build()
+}
+
+fn tracer_provider_builder(config: &TracingConfig, build_info: BuildInfo) -> TracerProviderBuilder {
+    SdkTracerProvider::builder()
+        .with_sampler(sampler(config))
+        .with_resource(resource(config, build_info))
+}
+
+/// The instrumentation scope of spans created by the server middleware.
+pub(crate) fn instrumentation_scope() -> InstrumentationScope {
+    InstrumentationScope::builder("gear-microkit")
+        .with_version(env!("CARGO_PKG_VERSION"))
+        .build()
+}
+
+fn sampler(config: &TracingConfig) -> Sampler {
+    let ratio = config.sampler_arg.unwrap_or(1.0);
+    match config.sampler {
+        TraceSampler::AlwaysOn => Sampler::AlwaysOn,
+        TraceSampler::AlwaysOff => Sampler::AlwaysOff,
+        TraceSampler::TraceIdRatio => Sampler::TraceIdRatioBased(ratio),
+        TraceSampler::ParentBasedAlwaysOn => Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+        TraceSampler::ParentBasedAlwaysOff => Sampler::ParentBased(Box::new(Sampler::AlwaysOff)),
+        TraceSampler::ParentBasedTraceIdRatio => {
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio)))
+        }
+    }
+}
+
+/// The configured endpoint, unless the standard environment variables name
+/// one. The exporter gives programmatic configuration precedence, which would
+/// otherwise prevent operators from redirecting spans per deployment.
+fn otlp_endpoint(config: &TracingConfig) -> Option<&str> {
+    let from_env = [
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+    ]
+    .iter()
+    .any(|name| env::var_os(name).is_some());
+    config.otlp_endpoint.as_deref().filter(|_| !from_env)
+}
+
+/// Builds the resource, with later sources overriding earlier ones: SDK
+/// defaults, detected host attributes, the build info, then configuration.
+fn resource(config: &TracingConfig, build_info: BuildInfo) -> Resource {
+    let mut builder = Resource::builder_empty().with_detector(Box::new(TelemetryResourceDetector));
+    if config.detect_resource {
+        builder = builder.with_attributes(detect_resource_attributes());
+    }
+    if build_info.version != BuildInfo::default().version {
+        builder = builder.with_attribute(KeyValue::new(SERVICE_VERSION, build_info.version));
+    }
+    if let Some(environment) = &config.environment {
+        builder = builder.with_attribute(KeyValue::new(
+            DEPLOYMENT_ENVIRONMENT_NAME,
+            environment.clone(),
+        ));
+    }
+    builder = builder.with_attributes(
+        config
+            .resource_attributes
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone())),
+    );
+    let service_name = config
+        .service_name
+        .as_deref()
+        .or_else(|| {
+            config
+                .resource_attributes
+                .get(SERVICE_NAME)
+                .map(String::as_str)
+        })
+        .or_else(|| (build_info.name != BuildInfo::default().name).then_some(build_info.name))
+        .unwrap_or("unknown_service");
+    builder.with_service_name(service_name.to_string()).build()
+}
+
+/// Host and Kubernetes attributes available from the process environment.
+fn detect_resource_attributes() -> Vec<KeyValue> {
+    let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+    let mut attributes = Vec::new();
+    let hostname = var("HOSTNAME");
+    if let Some(hostname) = &hostname {
+        attributes.push(KeyValue::new(HOST_NAME, hostname.clone()));
+    }
+    if var("KUBERNETES_SERVICE_HOST").is_some() {
+        if let Some(pod) = var("POD_NAME").or(hostname) {
+            attributes.push(KeyValue::new(K8S_POD_NAME, pod));
+        }
+        let namespace = var("POD_NAMESPACE").or_else(|| {
+            fs::read_to_string(K8S_NAMESPACE_FILE)
+                .ok()
+                .map(|namespace| namespace.trim().to_string())
+        });
+        if let Some(namespace) = namespace {
+            attributes.push(KeyValue::new(K8S_NAMESPACE_NAME, namespace));
+        }
+        if let Some(node) = var("NODE_NAME") {
+            attributes.push(KeyValue::new(K8S_NODE_NAME, node));
+        }
+    }
+    attributes
+}
+
+/// Writes each finished span to stdout as a single JSON line.
+#[derive(Debug)]
+struct StdoutSpanExporter;
+
+impl SpanExporter for StdoutSpanExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let mut stdout = io::stdout().lock();
+        for span in batch {
+            let line = span_to_json(&span).to_string();
+            writeln!(stdout, "{line}")
+                .map_err(|err| OTelSdkError::InternalFailure(err.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+fn span_to_json(span: &SpanData) -> serde_json::Value {
+    let attributes = |attributes: &[KeyValue]| -> serde_json::Map<String, serde_json::Value> {
+        attributes
+            .iter()
+            .map(|kv| (kv.key.to_string(), kv.value.to_string().into()))
+            .collect()
+    };
+    let (status, status_message) = match &span.status {
+        Status::Unset => ("unset", None),
+        Status::Ok => ("ok", None),
+        Status::Error { description } => ("error", Some(description.to_string())),
+    };
+    let kind = match span.span_kind {
+        SpanKind::Client => "client",
+        SpanKind::Server => "server",
+        SpanKind::Producer => "producer",
+        SpanKind::Consumer => "consumer",
+        SpanKind::Internal => "internal",
+    };
+    json!({
+        "name": span.name,
+        "kind": kind,
+        "trace_id": span.span_context.trace_id().to_string(),
+        "span_id": span.span_context.span_id().to_string(),
+        "parent_span_id": span.parent_span_id.to_string(),
+        "start_time": humantime::format_rfc3339_nanos(span.start_time).to_string(),
+        "end_time": humantime::format_rfc3339_nanos(span.end_time).to_string(),
+        "status": status,
+        "status_message": status_message,
+        "attributes": attributes(&span.attributes),
+        "events": span
+            .events
+            .iter()
+            .map(|event| json!({
+                "name": event.name,
+                "time": humantime::format_rfc3339_nanos(event.timestamp).to_string(),
+                "attributes": attributes(&event.attributes),
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
@@ -0,0 +1,190 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use opentelemetry_sdk::{
+    error::OTelSdkResult,
+    trace::{SpanData, SpanExporter},
+};
+use poem::{endpoint::BoxEndpoint, Middleware, Response};
+use poem_grpc::ClientConfig;
+use prometheus::{Encoder, TextEncoder};
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{GrpcServer, GrpcServerConfig, TraceExporter};
+
+/// A [`GrpcServer`] running in the background of a test.
+///
+/// The server is built with the same middleware stack as
+/// [`GrpcServer::start`], but instead of reading its configuration from the
+/// environment it listens on an ephemeral port on the loopback interface,
+/// serves no admin listener and captures finished spans in memory rather than
+/// exporting them. A configuration passed to
+/// [`GrpcServer::with_config`] is used as the base, with these settings
+/// overridden.
+///
+/// [`start`](Self::start) resolves once the server has bound its listener and
+/// every [startup hook](GrpcServer::on_start) has succeeded. Dropping the
+/// handle triggers a graceful shutdown in the background; call
+/// [`shutdown`](Self::shutdown) to wait for it to complete instead.
+///
+/// # Examples
+///
+/// ```rust
+/// use gear_microkit::{testing::TestServer, GrpcServer};
+///
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() -> std::io::Result<()> {
+///     let server = TestServer::start(GrpcServer::new()).await?;
+///     assert!(server.addr().ip().is_loopback());
+///     // let client = server.client(HelloworldClient::new);
+///     server.shutdown().await
+/// }
+/// ```
+pub struct TestServer {
+    addr: SocketAddr,
+    spans: SpanCapture,
+    stop: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl TestServer {
+    /// Starts `server` with only the built-in middleware stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the server fails to start, for the reasons
+    /// listed on [`GrpcServer::start`].
+    pub async fn start(server: GrpcServer) -> io::Result<Self> {
+        Self::start_with_middleware(server, ()).await
+    }
+
+    /// Starts `server` with an additional middleware applied outermost, like
+    /// [`GrpcServer::start_with_middleware`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the server fails to start, for the reasons
+    /// listed on [`GrpcServer::start_with_middleware`].
+    pub async fn start_with_middleware<T>(server: GrpcServer, middleware: T) -> io::Result<Self>
+    where
+        T: Middleware<BoxEndpoint<'static, Response>> + Send + 'static,
+    {
+        let spans = SpanCapture::default();
+        let (ready, ready_rx) = oneshot::channel();
+        let (stop, stop_rx) = oneshot::channel::<()>();
+        let server = server
+            .with_harness(Harness {
+                spans: spans.clone(),
+                ready,
+            })
+            .shutdown_signal(async move {
+                let _ = stop_rx.await;
+            });
+        let task = tokio::spawn(server.start_with_middleware(middleware));
+
+        match ready_rx.await {
+            Ok(addr) => Ok(Self {
+                addr,
+                spans,
+                stop: Some(stop),
+                task: Some(task),
+            }),
+            Err(_) => match task.await {
+                Ok(Err(err)) => Err(err),
+                Ok(Ok(())) => Err(io::Error::other("server stopped before it was ready")),
+                Err(err) => Err(io::Error::other(err)),
+            },
+        }
+    }
+
+    /// The address the server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The `http://` URI of the server, as expected by
+    /// [`ClientConfigBuilder::uri`](poem_grpc::ClientConfigBuilder::uri).
+    pub fn uri(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Creates a client connected to the server with a constructor of a
+    /// generated client, e.g. `server.client(HelloworldClient::new)`.
+    pub fn client<C>(&self, new: impl FnOnce(ClientConfig) -> C) -> C {
+        let config = ClientConfig::builder()
+            .uri(self.uri())
+            .build()
+            .expect("valid test server uri");
+        new(config)
+    }
+
+    /// The spans finished so far, in the order they ended.
+    ///
+    /// The server span of a call ends when its handler returns, which may be
+    /// slightly after the client has received the response.
+    pub fn spans(&self) -> Vec<SpanData> {
+        self.spans.0.lock().unwrap().clone()
+    }
+
+    /// The Prometheus text exposition of the default registry, as served on
+    /// the admin listener's `/metrics`.
+    ///
+    /// The registry is shared by every server in the process, so assertions
+    /// should be scoped to labels unique to the test, such as the method.
+    pub fn metrics(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&prometheus::gather(), &mut buf)
+            .expect("failed to encode metrics");
+        String::from_utf8(buf).expect("metrics are valid UTF-8")
+    }
+
+    /// Shuts the server down gracefully and waits until it has stopped.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`io::Error`] the server stopped with, if any.
+    pub async fn shutdown(mut self) -> io::Result<()> {
+        self.stop.take();
+        match self.task.take() {
+            Some(task) => task.await.map_err(io::Error::other)?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // Dropping the sender completes the shutdown signal.
+        self.stop.take();
+    }
+}
+
+/// What [`GrpcServer`] does differently when started by a [`TestServer`].
+pub(crate) struct Harness {
+    pub(crate) spans: SpanCapture,
+    /// Receives the listening address once startup has completed.
+    pub(crate) ready: oneshot::Sender<SocketAddr>,
+}
+
+impl Harness {
+    pub(crate) fn configure(config: &mut GrpcServerConfig) {
+        config.address = "127.0.0.1:0".to_string();
+        config.admin_address = None;
+        config.tracing.exporter = TraceExporter::None;
+    }
+}
+
+/// Collects finished spans in memory.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SpanCapture(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for SpanCapture {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        self.0.lock().unwrap().extend(batch);
+        Ok(())
+    }
+}
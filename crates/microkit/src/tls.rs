@@ -0,0 +1,669 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::SocketAddr,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::{future::BoxFuture, FutureExt};
+use poem::{
+    http::uri::Scheme,
+    listener::Acceptor,
+    web::{LocalAddr, RemoteAddr},
+    Addr,
+};
+use rustls::{
+    crypto::{aws_lc_rs, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    task::JoinHandle,
+};
+use tokio_rustls::server::TlsStream;
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+use crate::{ClientAuth, TlsConfig};
+
+/// The identity of a client that authenticated with a certificate during the
+/// mutual TLS handshake.
+///
+/// When [`TlsConfig::client_ca_path`] is set, every request on a connection
+/// with a verified client certificate carries a `ClientIdentity` as request
+/// data. Handlers can read it with
+/// [`RequestExt::client_identity`](crate::RequestExt::client_identity).
+///
+/// Cloning is cheap; the parsed certificate is shared between all requests on
+/// a connection.
+#[derive(Debug, Clone)]
+pub struct ClientIdentity(Arc<ClientIdentityInner>);
+
+#[derive(Debug)]
+struct ClientIdentityInner {
+    subject: String,
+    common_name: Option<String>,
+    dns_names: Vec<String>,
+    uris: Vec<String>,
+    certificate: Vec<u8>,
+}
+
+impl ClientIdentity {
+    fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(ToString::to_string);
+        let mut dns_names = Vec::new();
+        let mut uris = Vec::new();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(name) => dns_names.push(name.to_string()),
+                    GeneralName::URI(uri) => uris.push(uri.to_string()),
+                    _ => {}
+                }
+            }
+        }
+        Some(Self(Arc::new(ClientIdentityInner {
+            subject: cert.subject().to_string(),
+            common_name,
+            dns_names,
+            uris,
+            certificate: der.to_vec(),
+        })))
+    }
+
+    /// Returns the subject distinguished name, e.g. `CN=order-service, O=gear`.
+    pub fn subject(&self) -> &str {
+        &self.0.subject
+    }
+
+    /// Returns the common name (`CN`) of the subject, if any.
+    pub fn common_name(&self) -> Option<&str> {
+        self.0.common_name.as_deref()
+    }
+
+    /// Returns the DNS names from the subject alternative name extension.
+    pub fn dns_names(&self) -> &[String] {
+        &self.0.dns_names
+    }
+
+    /// Returns the URIs from the subject alternative name extension, such as
+    /// a SPIFFE ID (`spiffe://cluster.local/ns/default/sa/order-service`).
+    pub fn uris(&self) -> &[String] {
+        &self.0.uris
+    }
+
+    /// Returns the DER encoding of the client's end-entity certificate.
+    pub fn certificate_der(&self) -> &[u8] {
+        &self.0.certificate
+    }
+}
+
+/// The client identities of the live TLS connections, keyed by the local and
+/// peer address.
+///
+/// poem only passes the local and remote address of a connection on to its
+/// requests, so the identity established during the handshake is looked up by
+/// these addresses when a request arrives. Each entry is removed when its
+/// connection is dropped.
+#[derive(Clone, Default)]
+pub(crate) struct ClientRegistry {
+    connections: Arc<Mutex<HashMap<ConnectionKey, (u64, ClientIdentity)>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl ClientRegistry {
+    /// Returns the identity of the client connected from `remote_addr` to
+    /// `local_addr`.
+    pub(crate) fn get(
+        &self,
+        local_addr: &LocalAddr,
+        remote_addr: &RemoteAddr,
+    ) -> Option<ClientIdentity> {
+        let key = connection_key(local_addr, remote_addr)?;
+        let connections = self.connections.lock().unwrap();
+        connections.get(&key).map(|(_, identity)| identity.clone())
+    }
+
+    fn register(&self, local_addr: &LocalAddr, remote_addr: &RemoteAddr) -> Option<Registration> {
+        Some(Registration {
+            registry: self.clone(),
+            key: connection_key(local_addr, remote_addr)?,
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+}
+
+type ConnectionKey = (SocketAddr, SocketAddr);
+
+fn connection_key(local_addr: &LocalAddr, remote_addr: &RemoteAddr) -> Option<ConnectionKey> {
+    match (&local_addr.0, &remote_addr.0) {
+        (Addr::SocketAddr(local), Addr::SocketAddr(remote)) => Some((*local, *remote)),
+        _ => None,
+    }
+}
+
+/// Removes a connection's identity from the [`ClientRegistry`] on drop.
+///
+/// Entries are tagged with a connection ID so that a closing connection never
+/// removes the entry of a newer connection from the same address.
+struct Registration {
+    registry: ClientRegistry,
+    key: ConnectionKey,
+    id: u64,
+}
+
+impl Registration {
+    fn set(&self, identity: ClientIdentity) {
+        let mut connections = self.registry.connections.lock().unwrap();
+        connections.insert(self.key, (self.id, identity));
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut connections = self.registry.connections.lock().unwrap();
+        if matches!(connections.get(&self.key), Some((id, _)) if *id == self.id) {
+            connections.remove(&self.key);
+        }
+    }
+}
+
+/// The PEM files a [`ServerConfig`] was built from, used to detect rotation.
+#[derive(PartialEq, Eq)]
+struct TlsFiles {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+    client_ca: Option<Vec<u8>>,
+}
+
+impl TlsFiles {
+    fn read(config: &TlsConfig) -> io::Result<Self> {
+        Ok(Self {
+            cert: read_file(&config.cert_path, "certificate")?,
+            key: read_file(&config.key_path, "private key")?,
+            client_ca: config
+                .client_ca_path
+                .as_deref()
+                .map(|path| read_file(path, "client CA"))
+                .transpose()?,
+        })
+    }
+
+    fn server_config(&self, client_auth: ClientAuth) -> io::Result<ServerConfig> {
+        let provider = Arc::new(aws_lc_rs::default_provider());
+        let certs = CertificateDer::pem_slice_iter(&self.cert)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| invalid_data(format!("invalid TLS certificate: {err}")))?;
+        if certs.is_empty() {
+            return Err(invalid_data("no certificate found in TLS certificate file"));
+        }
+        let key = PrivateKeyDer::from_pem_slice(&self.key)
+            .map_err(|err| invalid_data(format!("invalid TLS private key: {err}")))?;
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?;
+        let builder = match &self.client_ca {
+            Some(client_ca) => builder.with_client_cert_verifier(client_verifier(
+                client_ca,
+                client_auth,
+                provider,
+            )?),
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder
+            .with_single_cert(certs, key)
+            .map_err(|err| invalid_data(format!("invalid TLS certificate or key: {err}")))?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(config)
+    }
+}
+
+fn client_verifier(
+    client_ca: &[u8],
+    client_auth: ClientAuth,
+    provider: Arc<CryptoProvider>,
+) -> io::Result<Arc<dyn rustls::server::danger::ClientCertVerifier>> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_slice_iter(client_ca) {
+        let cert = cert.map_err(|err| invalid_data(format!("invalid client CA: {err}")))?;
+        roots
+            .add(cert)
+            .map_err(|err| invalid_data(format!("invalid client CA: {err}")))?;
+    }
+    let mut builder = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+    if client_auth == ClientAuth::Optional {
+        builder = builder.allow_unauthenticated();
+    }
+    builder
+        .build()
+        .map_err(|err| invalid_data(format!("invalid client CA: {err}")))
+}
+
+fn read_file(path: &Path, what: &str) -> io::Result<Vec<u8>> {
+    fs::read(path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to read TLS {what} `{}`: {err}", path.display()),
+        )
+    })
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Wraps an acceptor with TLS, reloading the certificates when the files
+/// change on disk.
+///
+/// Handshakes run lazily on the connection task rather than in
+/// [`accept`](Acceptor::accept), so a slow client cannot stall the accept
+/// loop, and fail with [`TimedOut`](io::ErrorKind::TimedOut) once
+/// [`TlsConfig::handshake_timeout`] passes.
+pub(crate) struct TlsAcceptor<A> {
+    inner: A,
+    server_config: Arc<RwLock<Arc<ServerConfig>>>,
+    handshake_timeout: Duration,
+    registry: ClientRegistry,
+    reloader: Option<JoinHandle<()>>,
+}
+
+impl<A> TlsAcceptor<A> {
+    /// Loads the certificates and starts watching them for changes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the files cannot be read or do not form a valid
+    /// TLS configuration. Errors during later reloads are logged and the
+    /// previous certificates stay in use.
+    pub(crate) fn new(inner: A, config: &TlsConfig, registry: ClientRegistry) -> io::Result<Self> {
+        let files = TlsFiles::read(config)?;
+        let server_config = Arc::new(RwLock::new(Arc::new(
+            files.server_config(config.client_auth)?,
+        )));
+        let reloader = config.reload_interval.map(|interval| {
+            tokio::spawn(reload(
+                config.clone(),
+                interval,
+                files,
+                server_config.clone(),
+            ))
+        });
+        Ok(Self {
+            inner,
+            server_config,
+            handshake_timeout: config.handshake_timeout,
+            registry,
+            reloader,
+        })
+    }
+}
+
+impl<A> Drop for TlsAcceptor<A> {
+    fn drop(&mut self) {
+        if let Some(reloader) = &self.reloader {
+            reloader.abort();
+        }
+    }
+}
+
+async fn reload(
+    config: TlsConfig,
+    interval: Duration,
+    mut files: TlsFiles,
+    server_config: Arc<RwLock<Arc<ServerConfig>>>,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let res = TlsFiles::read(&config).and_then(|new_files| {
+            if new_files == files {
+                return Ok(None);
+            }
+            let new_config = new_files.server_config(config.client_auth)?;
+            Ok(Some((new_files, new_config)))
+        });
+        match res {
+            Ok(Some((new_files, new_config))) => {
+                *server_config.write().unwrap() = Arc::new(new_config);
+                files = new_files;
+                tracing::info!("reloaded TLS certificates");
+            }
+            Ok(None) => {}
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to reload TLS certificates, keeping the previous ones")
+            }
+        }
+    }
+}
+
+impl<A: Acceptor> Acceptor for TlsAcceptor<A> {
+    type Io = TlsConnection<A::Io>;
+
+    fn local_addr(&self) -> Vec<LocalAddr> {
+        self.inner.local_addr()
+    }
+
+    async fn accept(&mut self) -> io::Result<(Self::Io, LocalAddr, RemoteAddr, Scheme)> {
+        let (stream, local_addr, remote_addr, _) = self.inner.accept().await?;
+        let acceptor = tokio_rustls::TlsAcceptor::from(self.server_config.read().unwrap().clone());
+        let registration = self.registry.register(&local_addr, &remote_addr);
+        let timeout = self.handshake_timeout;
+        let handshake = Box::pin(async move {
+            let stream = tokio::time::timeout(timeout, acceptor.accept(stream))
+                .await
+                .map_err(|_| {
+                    io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out")
+                })??;
+            let identity = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| ClientIdentity::from_der(cert));
+            Ok((stream, identity))
+        });
+        let connection = TlsConnection {
+            state: State::Handshaking(handshake),
+            registration,
+        };
+        Ok((connection, local_addr, remote_addr, Scheme::HTTPS))
+    }
+}
+
+type Handshake<IO> = BoxFuture<'static, io::Result<(TlsStream<IO>, Option<ClientIdentity>)>>;
+
+/// A server-side TLS stream that completes its handshake on first use.
+pub(crate) struct TlsConnection<IO> {
+    state: State<IO>,
+    registration: Option<Registration>,
+}
+
+enum State<IO> {
+    Handshaking(Handshake<IO>),
+    Ready(Box<TlsStream<IO>>),
+    Failed,
+}
+
+impl<IO> TlsConnection<IO> {
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&mut TlsStream<IO>>> {
+        if let State::Handshaking(handshake) = &mut self.state {
+            match handshake.poll_unpin(cx) {
+                Poll::Ready(Ok((stream, identity))) => {
+                    if let (Some(registration), Some(identity)) = (&self.registration, identity) {
+                        registration.set(identity);
+                    }
+                    self.state = State::Ready(Box::new(stream));
+                }
+                Poll::Ready(Err(err)) => {
+                    self.state = State::Failed;
+                    return Poll::Ready(Err(err));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        match &mut self.state {
+            State::Ready(stream) => Poll::Ready(Ok(stream)),
+            _ => Poll::Ready(Err(invalid_data("TLS handshake failed"))),
+        }
+    }
+}
+impl<IO> AsyncRead for TlsConnection<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut().poll_handshake(cx) {
+            Poll::Ready(Ok(stream)) => Pin::new(stream).poll_read(cx, buf),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<IO> AsyncWrite for TlsConnection<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut().poll_handshake(cx) {
+            Poll::Ready(Ok(stream)) => Pin::new(stream).poll_write(cx, buf),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut().poll_handshake(cx) {
+            Poll::Ready(Ok(stream)) => Pin::new(stream).poll_flush(cx),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut().poll_handshake(cx) {
+            Poll::Ready(Ok(stream)) => Pin::new(stream).poll_shutdown(cx),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use poem::listener::{Listener, TcpListener};
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertifiedIssuer, DnType, IsCa, KeyPair, SanType,
+    };
+    use rustls::ClientConfig;
+    use tokio::{io::AsyncReadExt, net::TcpStream};
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+
+    struct Ca(CertifiedIssuer<'static, KeyPair>);
+
+    impl Ca {
+        fn new() -> Self {
+            let mut params = CertificateParams::default();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            Self(CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap())
+        }
+
+        /// Issues a certificate for `server.test`, returned with its key in
+        /// PEM.
+        fn issue(&self) -> (String, String) {
+            let params = CertificateParams::new(vec!["server.test".to_string()]).unwrap();
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.0).unwrap();
+            (cert.pem(), key.serialize_pem())
+        }
+    }
+
+    /// Returns a configuration reading the certificate and key from files
+    /// named after `name` in the temporary directory.
+    fn tls_config(name: &str) -> TlsConfig {
+        let path = |ext: &str| -> PathBuf {
+            std::env::temp_dir().join(format!("gear-{}-{name}.{ext}", std::process::id()))
+        };
+        TlsConfig::new(path("crt"), path("key"))
+    }
+
+    fn write_files(config: &TlsConfig, (cert, key): &(String, String)) {
+        fs::write(&config.cert_path, cert).unwrap();
+        fs::write(&config.key_path, key).unwrap();
+    }
+
+    /// Serves TLS connections, reading from them until they close, and
+    /// returns the address to connect to.
+    async fn serve(config: &TlsConfig) -> SocketAddr {
+        let inner = TcpListener::bind("127.0.0.1:0")
+            .into_acceptor()
+            .await
+            .unwrap();
+        let addr = *inner.local_addr()[0].as_socket_addr().unwrap();
+        let mut acceptor = TlsAcceptor::new(inner, config, ClientRegistry::default()).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut conn, ..)) = acceptor.accept().await {
+                tokio::spawn(async move { conn.read(&mut [0; 1]).await });
+            }
+        });
+        addr
+    }
+
+    /// Connects to `addr` and returns the certificate the server presented.
+    async fn server_certificate(addr: SocketAddr, ca: &Ca) -> CertificateDer<'static> {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.0.der().clone()).unwrap();
+        let config = ClientConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect("server.test".try_into().unwrap(), stream)
+            .await
+            .unwrap();
+        stream.get_ref().1.peer_certificates().unwrap()[0].clone()
+    }
+
+    fn der(pem: &str) -> CertificateDer<'static> {
+        CertificateDer::from_pem_slice(pem.as_bytes()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reloads_changed_certificates() {
+        let ca = Ca::new();
+        let (first, second) = (ca.issue(), ca.issue());
+        let config = TlsConfig {
+            reload_interval: Some(Duration::from_millis(20)),
+            ..tls_config("reload")
+        };
+        write_files(&config, &first);
+        let addr = serve(&config).await;
+        assert_eq!(server_certificate(addr, &ca).await, der(&first.0));
+
+        write_files(&config, &second);
+        let mut reloaded = false;
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            if server_certificate(addr, &ca).await == der(&second.0) {
+                reloaded = true;
+                break;
+            }
+        }
+        assert!(reloaded, "the new certificate was not loaded");
+
+        // Invalid files are ignored and the last valid certificate is kept.
+        fs::write(&config.cert_path, "not a certificate").unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server_certificate(addr, &ca).await, der(&second.0));
+    }
+
+    #[tokio::test]
+    async fn times_out_stalled_handshakes() {
+        let config = TlsConfig {
+            handshake_timeout: Duration::from_millis(50),
+            ..tls_config("handshake")
+        };
+        write_files(&config, &Ca::new().issue());
+        let inner = TcpListener::bind("127.0.0.1:0")
+            .into_acceptor()
+            .await
+            .unwrap();
+        let addr = *inner.local_addr()[0].as_socket_addr().unwrap();
+        let mut acceptor = TlsAcceptor::new(inner, &config, ClientRegistry::default()).unwrap();
+
+        // The client connects but never sends a ClientHello.
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (mut conn, ..) = acceptor.accept().await.unwrap();
+        let err = conn.read(&mut [0; 1]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn parses_client_identities() {
+        let mut params = CertificateParams::new(vec!["order.internal".to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "order-service");
+        params.subject_alt_names.push(SanType::URI(
+            "spiffe://cluster.local/ns/default/sa/order-service"
+                .try_into()
+                .unwrap(),
+        ));
+        let cert = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+
+        let identity = ClientIdentity::from_der(cert.der()).unwrap();
+        assert_eq!(identity.subject(), "CN=order-service");
+        assert_eq!(identity.common_name(), Some("order-service"));
+        assert_eq!(identity.dns_names(), ["order.internal"]);
+        assert_eq!(
+            identity.uris(),
+            ["spiffe://cluster.local/ns/default/sa/order-service"]
+        );
+        assert_eq!(identity.certificate_der(), cert.der().as_ref());
+
+        assert!(ClientIdentity::from_der(b"not a certificate").is_none());
+    }
+
+    #[test]
+    fn registers_identities_per_connection() {
+        let cert = CertificateParams::new(vec!["order.internal".to_string()])
+            .unwrap()
+            .self_signed(&KeyPair::generate().unwrap())
+            .unwrap();
+        let identity = ClientIdentity::from_der(cert.der()).unwrap();
+        let registry = ClientRegistry::default();
+        let local = LocalAddr(Addr::SocketAddr("127.0.0.1:8443".parse().unwrap()));
+        let remote = RemoteAddr(Addr::SocketAddr("10.0.0.7:51234".parse().unwrap()));
+        let other = RemoteAddr(Addr::SocketAddr("10.0.0.8:51234".parse().unwrap()));
+
+        let first = registry.register(&local, &remote).unwrap();
+        assert!(registry.get(&local, &remote).is_none());
+        first.set(identity.clone());
+        let found = registry.get(&local, &remote).unwrap();
+        assert_eq!(found.certificate_der(), identity.certificate_der());
+        assert!(registry.get(&local, &other).is_none());
+
+        // A closing connection leaves the entry of a newer one from the same
+        // address in place.
+        let second = registry.register(&local, &remote).unwrap();
+        second.set(identity);
+        drop(first);
+        assert!(registry.get(&local, &remote).is_some());
+        drop(second);
+        assert!(registry.get(&local, &remote).is_none());
+
+        let custom = RemoteAddr(Addr::Custom("test", "peer".into()));
+        assert!(registry.register(&local, &custom).is_none());
+    }
+}
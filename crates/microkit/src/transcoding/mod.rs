@@ -0,0 +1,585 @@
+//! HTTP/JSON transcoding of RPCs annotated with `google.api.http`.
+
+mod template;
+
+use std::{collections::HashMap, sync::Arc};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use http_body_util::{combinators::BoxBody, BodyExt, LengthLimitError, Limited};
+use percent_encoding::percent_decode_str;
+use poem::{
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri, Version},
+    Body, Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+use poem_grpc::Code;
+use prost::Message as _;
+use serde_json::{json, Map, Value};
+
+use self::template::PathTemplate;
+use crate::{
+    descriptor::{self, DescriptorProto, FileDescriptorSet, HttpRule, Pattern},
+    status::{code_name, grpc_status, http_status},
+    MessageSizeConfig,
+};
+
+/// The REST endpoints declared by the `google.api.http` annotations of a set
+/// of protos, and the message schemas needed to build their requests.
+#[derive(Default)]
+pub(crate) struct HttpRoutes {
+    routes: Vec<Route>,
+    messages: HashMap<String, MessageSchema>,
+    enums: HashMap<String, HashMap<String, i32>>,
+}
+
+struct Route {
+    method: Method,
+    template: PathTemplate,
+    grpc_path: String,
+    input_type: String,
+    body: Option<String>,
+    response_body: Option<String>,
+}
+
+struct MessageSchema {
+    fields: HashMap<String, FieldSchema>,
+}
+
+struct FieldSchema {
+    name: String,
+    kind: i32,
+    repeated: bool,
+    type_name: String,
+}
+
+impl HttpRoutes {
+    /// Adds the routes and messages of an encoded
+    /// `google.protobuf.FileDescriptorSet`.
+    ///
+    /// Panics if the descriptor set cannot be decoded or declares an invalid
+    /// path template, as both are mistakes in the build rather than at
+    /// runtime.
+    pub(crate) fn add_file_descriptor_set(&mut self, data: &[u8]) {
+        let set = FileDescriptorSet::decode(data).expect("valid file descriptor set");
+        for file in set.file {
+            let prefix = match file.package.as_str() {
+                "" => String::new(),
+                package => format!(".{package}"),
+            };
+            for message in &file.message_type {
+                self.add_message(&prefix, message);
+            }
+            for enum_type in &file.enum_type {
+                self.add_enum(&prefix, enum_type);
+            }
+            for service in &file.service {
+                let service_name = match file.package.as_str() {
+                    "" => service.name.clone(),
+                    package => format!("{package}.{}", service.name),
+                };
+                for method in &service.method {
+                    let Some(rule) = method
+                        .options
+                        .as_ref()
+                        .and_then(|options| options.http.as_ref())
+                    else {
+                        continue;
+                    };
+                    let grpc_path = format!("/{service_name}/{}", method.name);
+                    if method.client_streaming || method.server_streaming {
+                        tracing::warn!(
+                            method = grpc_path,
+                            "ignoring `google.api.http` annotation of streaming method"
+                        );
+                        continue;
+                    }
+                    self.add_rule(&grpc_path, &method.input_type, rule);
+                    for rule in &rule.additional_bindings {
+                        self.add_rule(&grpc_path, &method.input_type, rule);
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_message(&mut self, prefix: &str, message: &DescriptorProto) {
+        let name = format!("{prefix}.{}", message.name);
+        for nested in &message.nested_type {
+            self.add_message(&name, nested);
+        }
+        for enum_type in &message.enum_type {
+            self.add_enum(&name, enum_type);
+        }
+        let fields = message
+            .field
+            .iter()
+            .map(|field| {
+                let schema = FieldSchema {
+                    name: field.name.clone(),
+                    kind: field.r#type,
+                    repeated: field.label == descriptor::LABEL_REPEATED,
+                    type_name: field.type_name.clone(),
+                };
+                (field.name.clone(), schema)
+            })
+            .collect();
+        self.messages.insert(name, MessageSchema { fields });
+    }
+
+    fn add_enum(&mut self, prefix: &str, enum_type: &descriptor::EnumDescriptorProto) {
+        let values = enum_type
+            .value
+            .iter()
+            .map(|value| (value.name.clone(), value.number))
+            .collect();
+        self.enums
+            .insert(format!("{prefix}.{}", enum_type.name), values);
+    }
+
+    fn add_rule(&mut self, grpc_path: &str, input_type: &str, rule: &HttpRule) {
+        let (method, path) = match &rule.pattern {
+            Some(Pattern::Get(path)) => (Method::GET, path),
+            Some(Pattern::Put(path)) => (Method::PUT, path),
+            Some(Pattern::Post(path)) => (Method::POST, path),
+            Some(Pattern::Delete(path)) => (Method::DELETE, path),
+            Some(Pattern::Patch(path)) => (Method::PATCH, path),
+            Some(Pattern::Custom(custom)) => (
+                Method::from_bytes(custom.kind.as_bytes()).unwrap_or_else(|_| {
+                    panic!("invalid HTTP method `{}` of `{grpc_path}`", custom.kind)
+                }),
+                &custom.path,
+            ),
+            None => return,
+        };
+        let template = PathTemplate::parse(path)
+            .unwrap_or_else(|err| panic!("invalid path template `{path}` of `{grpc_path}`: {err}"));
+        self.routes.push(Route {
+            method,
+            template,
+            grpc_path: grpc_path.to_string(),
+            input_type: input_type.to_string(),
+            body: Some(rule.body.clone()).filter(|body| !body.is_empty()),
+            response_body: Some(rule.response_body.clone()).filter(|body| !body.is_empty()),
+        });
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    fn find(&self, method: &Method, path: &str) -> Option<(&Route, Vec<(&str, String)>)> {
+        self.routes
+            .iter()
+            .filter(|route| route.method == method)
+            .find_map(|route| Some((route, route.template.matches(path)?)))
+    }
+
+    /// Builds the JSON request message of `route` from the path variables,
+    /// the query parameters and the request body.
+    fn request_message(
+        &self,
+        route: &Route,
+        variables: Vec<(&str, String)>,
+        query: Vec<(String, String)>,
+        body: &[u8],
+    ) -> Result<Value, String> {
+        let mut message = Value::Object(Map::new());
+        if !body.is_empty() {
+            let value = serde_json::from_slice::<Value>(body)
+                .map_err(|err| format!("invalid JSON body: {err}"))?;
+            match route.body.as_deref() {
+                Some("*") if value.is_object() => message = value,
+                Some("*") => return Err("the body must be a JSON object".to_string()),
+                Some(field) => set_field(&mut message, field, value, false)?,
+                None => {}
+            }
+        }
+        for (path, value) in variables {
+            let field = self
+                .field(&route.input_type, path)
+                .ok_or_else(|| format!("unknown field `{path}`"))?;
+            let value = self.parse_value(field, &value)?;
+            set_field(&mut message, path, value, field.repeated)?;
+        }
+        // With `body: "*"` every field is taken from the body, so query
+        // parameters are ignored, as are those naming unknown fields.
+        if route.body.as_deref() != Some("*") {
+            for (path, value) in query {
+                if let Some(field) = self.field(&route.input_type, &path) {
+                    let value = self.parse_value(field, &value)?;
+                    set_field(&mut message, &path, value, field.repeated)?;
+                }
+            }
+        }
+        Ok(message)
+    }
+
+    /// Resolves a dotted field path, e.g. `shelf.theme`, from `message`.
+    fn field(&self, message: &str, path: &str) -> Option<&FieldSchema> {
+        let mut message = self.messages.get(message)?;
+        let mut names = path.split('.').peekable();
+        while let Some(name) = names.next() {
+            let field = message.fields.get(name)?;
+            if names.peek().is_none() {
+                return Some(field);
+            }
+            message = self.messages.get(&field.type_name)?;
+        }
+        None
+    }
+
+    /// Converts a path or query parameter into the JSON value expected by
+    /// `JsonI64ToStringCodec` for `field`.
+    fn parse_value(&self, field: &FieldSchema, value: &str) -> Result<Value, String> {
+        use crate::descriptor::*;
+
+        let invalid = || format!("invalid value `{value}` for field `{}`", field.name);
+        match field.kind {
+            TYPE_DOUBLE | TYPE_FLOAT => value
+                .parse::<f64>()
+                .ok()
+                .and_then(|value| serde_json::Number::from_f64(value).map(Value::Number))
+                .ok_or_else(invalid),
+            TYPE_INT32 | TYPE_SINT32 | TYPE_SFIXED32 => {
+                value.parse::<i32>().map(Value::from).map_err(|_| invalid())
+            }
+            TYPE_UINT32 | TYPE_FIXED32 => {
+                value.parse::<u32>().map(Value::from).map_err(|_| invalid())
+            }
+            // 64-bit integers are written as strings by `JsonI64ToStringCodec`.
+            TYPE_INT64 | TYPE_SINT64 | TYPE_SFIXED64 => value
+                .parse::<i64>()
+                .map(|_| Value::String(value.to_string()))
+                .map_err(|_| invalid()),
+            TYPE_UINT64 | TYPE_FIXED64 => value
+                .parse::<u64>()
+                .map(|_| Value::String(value.to_string()))
+                .map_err(|_| invalid()),
+            TYPE_BOOL => value
+                .parse::<bool>()
+                .map(Value::Bool)
+                .map_err(|_| invalid()),
+            TYPE_STRING => Ok(Value::String(value.to_string())),
+            TYPE_BYTES => Ok(Value::from(value.as_bytes())),
+            TYPE_ENUM => value
+                .parse::<i32>()
+                .ok()
+                .or_else(|| {
+                    self.enums
+                        .get(&field.type_name)
+                        .and_then(|values| values.get(value).copied())
+                })
+                .map(Value::from)
+                .ok_or_else(invalid),
+            _ => Err(format!(
+                "field `{}` cannot be set from a path or query parameter",
+                field.name
+            )),
+        }
+    }
+}
+
+/// Sets the field at the dotted `path` of `message`, appending to the list
+/// for repeated fields.
+fn set_field(message: &mut Value, path: &str, value: Value, repeated: bool) -> Result<(), String> {
+    let mut target = message;
+    for name in path.split('.') {
+        let Value::Object(object) = target else {
+            return Err(format!("field `{path}` conflicts with the body"));
+        };
+        target = object
+            .entry(name)
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+    match target {
+        Value::Array(values) if repeated => values.push(value),
+        _ if repeated => *target = Value::Array(vec![value]),
+        _ => *target = value,
+    }
+    Ok(())
+}
+
+/// Serves the [`HttpRoutes`] as REST endpoints by translating them to
+/// gRPC-JSON calls of the wrapped router.
+///
+/// Request bodies are read up to the `max_decoding_message_size` of the
+/// route's method; larger ones are rejected with `RESOURCE_EXHAUSTED` and
+/// `413 Payload Too Large`.
+pub(crate) struct Transcode {
+    routes: Arc<HttpRoutes>,
+    message_size: Arc<MessageSizeConfig>,
+}
+
+impl Transcode {
+    pub(crate) fn new(routes: HttpRoutes, message_size: &MessageSizeConfig) -> Self {
+        Self {
+            routes: Arc::new(routes),
+            message_size: Arc::new(message_size.clone()),
+        }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for Transcode {
+    type Output = TranscodeEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        TranscodeEndpoint {
+            inner: ep,
+            routes: self.routes.clone(),
+            message_size: self.message_size.clone(),
+        }
+    }
+}
+
+pub(crate) struct TranscodeEndpoint<E> {
+    inner: E,
+    routes: Arc<HttpRoutes>,
+    message_size: Arc<MessageSizeConfig>,
+}
+
+impl<E: Endpoint> Endpoint for TranscodeEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        let is_grpc = req
+            .content_type()
+            .is_some_and(|content_type| content_type.starts_with("application/grpc"));
+        let found = (!is_grpc)
+            .then(|| self.routes.find(req.method(), req.uri().path()))
+            .flatten();
+        let Some((route, variables)) = found else {
+            return self.inner.call(req).await.map(IntoResponse::into_response);
+        };
+
+        let query = req.params::<Vec<(String, String)>>().unwrap_or_default();
+        let max = self
+            .message_size
+            .limit(&route.grpc_path)
+            .max_decoding_message_size;
+        let body = match read_body(req.take_body(), max).await {
+            Ok(body) => body,
+            Err(resp) => return Ok(resp),
+        };
+        let message = match self.routes.request_message(route, variables, query, &body) {
+            Ok(message) => message,
+            Err(message) => return Ok(error_response(Code::InvalidArgument, &message)),
+        };
+        let message = serde_json::to_vec(&message).expect("serializable JSON value");
+        let mut frame = BytesMut::with_capacity(5 + message.len());
+        frame.put_u8(0);
+        frame.put_u32(message.len() as u32);
+        frame.extend_from_slice(&message);
+
+        let (mut parts, _) = req.into_parts();
+        parts.method = Method::POST;
+        parts.uri = Uri::try_from(route.grpc_path.as_str()).expect("valid gRPC path");
+        parts.version = Version::HTTP_2;
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc+json"),
+        );
+        parts
+            .headers
+            .insert(header::TE, HeaderValue::from_static("trailers"));
+        let resp = self
+            .inner
+            .call(Request::from_parts(parts, Body::from(frame.freeze())))
+            .await?
+            .into_response();
+        Ok(into_http_response(resp, route.response_body.as_deref()).await)
+    }
+}
+
+/// Reads a request body of at most `max` bytes.
+async fn read_body(body: Body, max: Option<usize>) -> Result<Bytes, Response> {
+    let body: BoxBody<Bytes, std::io::Error> = body.into();
+    let collected = match max {
+        Some(max) => Limited::new(body, max).collect().await,
+        None => body.collect().await.map_err(Into::into),
+    };
+    collected
+        .map(|collected| collected.to_bytes())
+        .map_err(|err| match (err.is::<LengthLimitError>(), max) {
+            (true, Some(max)) => {
+                let mut resp = error_response(
+                    Code::ResourceExhausted,
+                    &format!("request body exceeds the limit of {max} bytes"),
+                );
+                resp.set_status(StatusCode::PAYLOAD_TOO_LARGE);
+                resp
+            }
+            _ => error_response(
+                Code::InvalidArgument,
+                &format!("failed to read the body: {err}"),
+            ),
+        })
+}
+
+/// Converts the gRPC-JSON response of a unary call into a plain JSON
+/// response.
+async fn into_http_response(resp: Response, response_body: Option<&str>) -> Response {
+    let (parts, body) = resp.into_parts();
+    let body: BoxBody<Bytes, std::io::Error> = body.into();
+    let collected = match body.collect().await {
+        Ok(collected) => collected,
+        Err(err) => return error_response(Code::Internal, &err.to_string()),
+    };
+    let trailers = collected.trailers().cloned().unwrap_or_default();
+    let status = grpc_status(&parts.headers)
+        .map(|code| (code, &parts.headers))
+        .or_else(|| grpc_status(&trailers).map(|code| (code, &trailers)));
+    let Some((code, status_headers)) = status else {
+        return error_response(Code::Internal, "missing grpc-status");
+    };
+    if code != Code::Ok {
+        let message = status_headers
+            .get("grpc-message")
+            .and_then(|message| message.to_str().ok())
+            .map(|message| percent_decode_str(message).decode_utf8_lossy().into_owned())
+            .unwrap_or_default();
+        return error_response(code, &message);
+    }
+
+    let data = collected.to_bytes();
+    let message = (data.len() >= 5 && data[0] == 0)
+        .then(|| u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize)
+        .and_then(|len| data.get(5..5 + len));
+    let Some(mut value) = message.and_then(|message| serde_json::from_slice::<Value>(message).ok())
+    else {
+        return error_response(Code::Internal, "invalid response message");
+    };
+    if let Some(path) = response_body {
+        for name in path.split('.') {
+            value = value.get_mut(name).map(Value::take).unwrap_or(Value::Null);
+        }
+    }
+
+    let mut resp = Response::builder()
+        .content_type("application/json")
+        .body(serde_json::to_vec(&value).expect("serializable JSON value"));
+    copy_metadata(&parts.headers, resp.headers_mut());
+    resp
+}
+
+/// Forwards the response metadata set by the handler.
+fn copy_metadata(from: &HeaderMap, to: &mut HeaderMap) {
+    for (name, value) in from {
+        if !name.as_str().starts_with("grpc-")
+            && name != header::CONTENT_TYPE
+            && name != header::CONTENT_LENGTH
+        {
+            to.append(name.clone(), value.clone());
+        }
+    }
+}
+
+fn error_response(code: Code, message: &str) -> Response {
+    let body = json!({
+        "code": code.as_u16(),
+        "status": code_name(code),
+        "message": message,
+    });
+    Response::builder()
+        .status(http_status(code))
+        .content_type("application/json")
+        .body(body.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::*;
+
+    fn field(kind: i32) -> FieldSchema {
+        FieldSchema {
+            name: "field".to_string(),
+            kind,
+            repeated: false,
+            type_name: ".test.Color".to_string(),
+        }
+    }
+
+    fn parse(kind: i32, value: &str) -> Result<Value, String> {
+        let mut routes = HttpRoutes::default();
+        routes.enums.insert(
+            ".test.Color".to_string(),
+            HashMap::from([("RED".to_string(), 0), ("BLUE".to_string(), 2)]),
+        );
+        routes.parse_value(&field(kind), value)
+    }
+
+    #[test]
+    fn parses_64_bit_integers_as_strings() {
+        for kind in [TYPE_INT64, TYPE_SINT64, TYPE_SFIXED64] {
+            assert_eq!(parse(kind, "-42"), Ok(json!("-42")));
+            assert_eq!(
+                parse(kind, "9223372036854775807"),
+                Ok(json!("9223372036854775807"))
+            );
+            assert!(parse(kind, "9223372036854775808").is_err());
+            assert!(parse(kind, "1.5").is_err());
+        }
+        for kind in [TYPE_UINT64, TYPE_FIXED64] {
+            assert_eq!(
+                parse(kind, "18446744073709551615"),
+                Ok(json!("18446744073709551615"))
+            );
+            assert!(parse(kind, "-1").is_err());
+        }
+    }
+
+    #[test]
+    fn parses_scalars() {
+        assert_eq!(parse(TYPE_INT32, "-7"), Ok(json!(-7)));
+        assert!(parse(TYPE_INT32, "2147483648").is_err());
+        assert_eq!(parse(TYPE_UINT32, "7"), Ok(json!(7)));
+        assert!(parse(TYPE_UINT32, "-7").is_err());
+        assert_eq!(parse(TYPE_DOUBLE, "1.5"), Ok(json!(1.5)));
+        assert!(parse(TYPE_DOUBLE, "NaN").is_err());
+        assert_eq!(parse(TYPE_BOOL, "true"), Ok(json!(true)));
+        assert!(parse(TYPE_BOOL, "1").is_err());
+        assert_eq!(parse(TYPE_STRING, "a b"), Ok(json!("a b")));
+    }
+
+    #[test]
+    fn parses_enums_by_name_or_number() {
+        assert_eq!(parse(TYPE_ENUM, "BLUE"), Ok(json!(2)));
+        assert_eq!(parse(TYPE_ENUM, "5"), Ok(json!(5)));
+        assert!(parse(TYPE_ENUM, "GREEN").is_err());
+    }
+
+    #[test]
+    fn rejects_message_fields() {
+        // TYPE_MESSAGE
+        assert!(parse(11, "{}").is_err());
+    }
+
+    #[test]
+    fn sets_nested_fields() {
+        let mut message = json!({});
+        set_field(&mut message, "shelf.theme", json!("sci-fi"), false).unwrap();
+        set_field(&mut message, "shelf.id", json!("1"), false).unwrap();
+        set_field(&mut message, "name", json!("a"), false).unwrap();
+        set_field(&mut message, "name", json!("b"), false).unwrap();
+        assert_eq!(
+            message,
+            json!({"shelf": {"theme": "sci-fi", "id": "1"}, "name": "b"})
+        );
+    }
+
+    #[test]
+    fn appends_to_repeated_fields() {
+        let mut message = json!({"tags": "body"});
+        set_field(&mut message, "tags", json!("a"), true).unwrap();
+        set_field(&mut message, "tags", json!("b"), true).unwrap();
+        set_field(&mut message, "filter.ids", json!(1), true).unwrap();
+        assert_eq!(message, json!({"tags": ["a", "b"], "filter": {"ids": [1]}}));
+    }
+
+    #[test]
+    fn rejects_fields_conflicting_with_the_body() {
+        let mut message = json!({"shelf": "1"});
+        assert!(set_field(&mut message, "shelf.id", json!("2"), false).is_err());
+        assert_eq!(message, json!({"shelf": "1"}));
+    }
+}
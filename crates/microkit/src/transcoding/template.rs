@@ -0,0 +1,268 @@
+use percent_encoding::percent_decode_str;
+
+/// A `google.api.http` path template, e.g. `/v1/{name=shelves/*}/books:list`.
+pub(super) struct PathTemplate {
+    segments: Vec<Segment>,
+    variables: Vec<Variable>,
+    verb: Option<String>,
+}
+
+#[derive(PartialEq)]
+enum Segment {
+    Literal(String),
+    /// `*`, exactly one segment.
+    Any,
+    /// `**`, the remaining segments.
+    Rest,
+}
+
+/// A `{field}` or `{field=pattern}` variable covering `segments[start..end]`.
+struct Variable {
+    field: String,
+    start: usize,
+    end: usize,
+}
+
+impl PathTemplate {
+    pub(super) fn parse(template: &str) -> Result<Self, String> {
+        let Some(path) = template.strip_prefix('/') else {
+            return Err("must start with `/`".to_string());
+        };
+        let verb_start = path
+            .rfind(':')
+            .filter(|&i| path.rfind(['/', '}']).is_none_or(|j| i > j));
+        let (mut rest, verb) = match verb_start {
+            Some(i) => (&path[..i], Some(path[i + 1..].to_string())),
+            None => (path, None),
+        };
+
+        let mut segments = Vec::new();
+        let mut variables = Vec::new();
+        loop {
+            if let Some(variable) = rest.strip_prefix('{') {
+                let Some(close) = variable.find('}') else {
+                    return Err("unclosed `{`".to_string());
+                };
+                let (field, pattern) = variable[..close]
+                    .split_once('=')
+                    .unwrap_or((&variable[..close], "*"));
+                if field.is_empty() {
+                    return Err("empty variable name".to_string());
+                }
+                let start = segments.len();
+                for segment in pattern.split('/') {
+                    segments.push(Segment::parse(segment)?);
+                }
+                variables.push(Variable {
+                    field: field.to_string(),
+                    start,
+                    end: segments.len(),
+                });
+                rest = &variable[close + 1..];
+            } else {
+                let end = rest.find('/').unwrap_or(rest.len());
+                segments.push(Segment::parse(&rest[..end])?);
+                rest = &rest[end..];
+            }
+            match rest.strip_prefix('/') {
+                Some(next) => rest = next,
+                None if rest.is_empty() => break,
+                None => return Err(format!("unexpected `{rest}`")),
+            }
+        }
+        if let Some(i) = segments
+            .iter()
+            .position(|segment| *segment == Segment::Rest)
+        {
+            if i != segments.len() - 1 {
+                return Err("`**` must be the last segment".to_string());
+            }
+        }
+        Ok(Self {
+            segments,
+            variables,
+            verb,
+        })
+    }
+
+    /// Matches `path` against the template, returning the decoded value of
+    /// every variable.
+    pub(super) fn matches(&self, path: &str) -> Option<Vec<(&str, String)>> {
+        let path = match &self.verb {
+            Some(verb) => path.strip_suffix(verb.as_str())?.strip_suffix(':')?,
+            None => path,
+        };
+        let parts = path.strip_prefix('/')?.split('/').collect::<Vec<_>>();
+        let rest = self.segments.last() == Some(&Segment::Rest);
+        if rest {
+            if parts.len() < self.segments.len() - 1 {
+                return None;
+            }
+        } else if parts.len() != self.segments.len() {
+            return None;
+        }
+        for (segment, part) in self.segments.iter().zip(&parts) {
+            match segment {
+                Segment::Literal(literal) if literal != part => return None,
+                Segment::Any if part.is_empty() => return None,
+                _ => {}
+            }
+        }
+
+        self.variables
+            .iter()
+            .map(|variable| {
+                let end = if rest && variable.end == self.segments.len() {
+                    parts.len()
+                } else {
+                    variable.end
+                };
+                let value = parts[variable.start..end]
+                    .iter()
+                    .map(|part| percent_decode_str(part).decode_utf8().ok())
+                    .collect::<Option<Vec<_>>>()?
+                    .join("/");
+                Some((variable.field.as_str(), value))
+            })
+            .collect()
+    }
+}
+
+impl Segment {
+    fn parse(segment: &str) -> Result<Self, String> {
+        match segment {
+            "*" => Ok(Self::Any),
+            "**" => Ok(Self::Rest),
+            "" => Err("empty segment".to_string()),
+            _ if segment.contains(['{', '}', '*', '=']) => {
+                Err(format!("invalid segment `{segment}`"))
+            }
+            _ => Ok(Self::Literal(segment.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(template: &str, path: &str) -> Option<Vec<(String, String)>> {
+        let template = PathTemplate::parse(template).unwrap();
+        let variables = template.matches(path)?;
+        Some(
+            variables
+                .into_iter()
+                .map(|(field, value)| (field.to_string(), value))
+                .collect(),
+        )
+    }
+
+    fn vars(variables: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(
+            variables
+                .iter()
+                .map(|&(field, value)| (field.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn matches_literals() {
+        assert_eq!(matches("/v1/shelves", "/v1/shelves"), vars(&[]));
+        assert_eq!(matches("/v1/shelves", "/v1/books"), None);
+        assert_eq!(matches("/v1/shelves", "/v1/shelves/1"), None);
+        assert_eq!(matches("/v1/shelves", "/v1"), None);
+    }
+
+    #[test]
+    fn matches_variables() {
+        assert_eq!(
+            matches(
+                "/v1/shelves/{shelf}/books/{book.id}",
+                "/v1/shelves/1/books/2"
+            ),
+            vars(&[("shelf", "1"), ("book.id", "2")])
+        );
+        assert_eq!(
+            matches("/v1/{name=shelves/*/books/*}", "/v1/shelves/1/books/2"),
+            vars(&[("name", "shelves/1/books/2")])
+        );
+        assert_eq!(matches("/v1/shelves/{shelf}", "/v1/shelves/"), None);
+        assert_eq!(matches("/v1/shelves/{shelf}", "/v1/shelves/1/2"), None);
+        assert_eq!(
+            matches("/v1/{name=shelves/*/books/*}", "/v1/shelves/1/authors/2"),
+            None
+        );
+    }
+
+    #[test]
+    fn matches_the_remaining_segments() {
+        assert_eq!(
+            matches("/v1/files/{path=**}", "/v1/files/a/b/c"),
+            vars(&[("path", "a/b/c")])
+        );
+        assert_eq!(
+            matches("/v1/files/{path=**}", "/v1/files/a"),
+            vars(&[("path", "a")])
+        );
+        assert_eq!(
+            matches("/v1/{bucket}/{path=objects/**}", "/v1/b/objects/x/y"),
+            vars(&[("bucket", "b"), ("path", "objects/x/y")])
+        );
+        assert_eq!(matches("/v1/{bucket}/{path=objects/**}", "/v1/b"), None);
+    }
+
+    #[test]
+    fn matches_verbs() {
+        let template = "/v1/{name=shelves/*}:publish";
+        assert_eq!(
+            matches(template, "/v1/shelves/1:publish"),
+            vars(&[("name", "shelves/1")])
+        );
+        assert_eq!(matches(template, "/v1/shelves/1"), None);
+        assert_eq!(matches(template, "/v1/shelves/1:archive"), None);
+        assert_eq!(
+            matches("/v1/{path=**}:download", "/v1/a/b:download"),
+            vars(&[("path", "a/b")])
+        );
+        // Without a verb in the template, a colon is part of the value.
+        assert_eq!(
+            matches("/v1/shelves/{shelf}", "/v1/shelves/a:b"),
+            vars(&[("shelf", "a:b")])
+        );
+    }
+
+    #[test]
+    fn percent_decodes_values() {
+        assert_eq!(
+            matches("/v1/shelves/{shelf}", "/v1/shelves/a%20b%2Fc"),
+            vars(&[("shelf", "a b/c")])
+        );
+        assert_eq!(
+            matches("/v1/shelves/{shelf}", "/v1/shelves/%E4%B9%A6"),
+            vars(&[("shelf", "书")])
+        );
+        assert_eq!(matches("/v1/shelves/{shelf}", "/v1/shelves/%FF"), None);
+        // Literals are compared before decoding.
+        assert_eq!(matches("/v1/shelves", "/v1/shelve%73"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        for template in [
+            "",
+            "v1/shelves",
+            "/v1/{shelf",
+            "/v1/{}",
+            "/v1/{=*}",
+            "/v1//shelves",
+            "/v1/shelves/",
+            "/v1/**/shelves",
+            "/v1/{path=**}/shelves",
+            "/v1/a*b",
+            "/v1/{shelf}x",
+        ] {
+            assert!(PathTemplate::parse(template).is_err(), "{template}");
+        }
+    }
+}
@@ -1,15 +1,13 @@
 mod services;
+#[cfg(test)]
+mod tests;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let reflection = poem_grpc::Reflection::new()
-        .add_file_descriptor_set(poem_grpc::include_file_descriptor_set!(
-            "file_descriptor_set.bin"
-        ))
-        .build();
-
     let server = gear_microkit::GrpcServer::new()
-        .add_service(reflection)
+        .with_build_info(gear_microkit::build_info!())
+        .with_reflection(gear_microkit::file_descriptor_set!())
+        .with_http_transcoding(gear_microkit::file_descriptor_set!())
         .add_service(services::hello::new());
 
     server.start().await?;
@@ -0,0 +1,210 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use gear_microkit::{
+    testing::TestServer, BuiltinMiddleware, GrpcServer, GrpcServerConfig, MessageSizeLimit,
+    RateLimitRule,
+};
+use poem::{Endpoint, Middleware, Request, Result};
+use poem_grpc::{Code, Status};
+
+use crate::services::hello::{self, HelloRequest, HelloworldClient};
+
+const SAY_HELLO: &str = "/hello.Helloworld/SayHello";
+
+async fn say_hello(client: &HelloworldClient, name: &str) -> Result<String, Status> {
+    let req = HelloRequest {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    let resp = client.say_hello(poem_grpc::Request::new(req)).await?;
+    Ok(resp.into_inner().message)
+}
+
+fn server(config: GrpcServerConfig) -> GrpcServer {
+    GrpcServer::new()
+        .with_config(config)
+        .add_service(hello::new())
+}
+
+/// Counts the calls reaching its position in the stack.
+#[derive(Clone, Default)]
+struct CountCalls(Arc<AtomicUsize>);
+
+impl CountCalls {
+    fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for CountCalls {
+    type Output = CountCallsEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        CountCallsEndpoint {
+            inner: ep,
+            calls: self.0.clone(),
+        }
+    }
+}
+
+struct CountCallsEndpoint<E> {
+    inner: E,
+    calls: Arc<AtomicUsize>,
+}
+
+impl<E: Endpoint> Endpoint for CountCallsEndpoint<E> {
+    type Output = E::Output;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        self.inner.call(req).await
+    }
+}
+
+/// A configuration letting a single call through per second.
+fn rate_limited() -> GrpcServerConfig {
+    GrpcServerConfig {
+        rate_limits: vec![RateLimitRule::new("test", 1)],
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn serves_calls_until_shut_down() {
+    let server = TestServer::start(server(GrpcServerConfig::default()))
+        .await
+        .unwrap();
+    let client = server.client(HelloworldClient::new);
+    assert_eq!(say_hello(&client, "world").await.unwrap(), "Hello world!");
+
+    server.shutdown().await.unwrap();
+    assert!(say_hello(&client, "world").await.is_err());
+}
+
+#[tokio::test]
+async fn reconnects_after_the_max_connection_age() {
+    let config = GrpcServerConfig {
+        max_connection_age: Some(Duration::from_millis(100)),
+        max_connection_age_grace: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let server = TestServer::start(server(config)).await.unwrap();
+    let client = server.client(HelloworldClient::new);
+    assert_eq!(say_hello(&client, "first").await.unwrap(), "Hello first!");
+
+    // The first connection is sent a `GOAWAY` and closed by now.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(say_hello(&client, "second").await.unwrap(), "Hello second!");
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn places_custom_middleware_around_builtin_layers() {
+    let outside = CountCalls::default();
+    let inside = CountCalls::default();
+    let server = server(rate_limited())
+        .middleware_outside(BuiltinMiddleware::RateLimit, outside.clone())
+        .middleware_inside(BuiltinMiddleware::RateLimit, inside.clone());
+    let server = TestServer::start(server).await.unwrap();
+    let client = server.client(HelloworldClient::new);
+
+    assert!(say_hello(&client, "first").await.is_ok());
+    let err = say_hello(&client, "second").await.unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+    assert_eq!(outside.count(), 2);
+    assert_eq!(inside.count(), 1);
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn skips_disabled_middleware() {
+    let inside = CountCalls::default();
+    let server = server(rate_limited())
+        .disable_middleware(BuiltinMiddleware::RateLimit)
+        .disable_middleware(BuiltinMiddleware::OpenTelemetryTracing)
+        .middleware_inside(BuiltinMiddleware::RateLimit, inside.clone());
+    let server = TestServer::start(server).await.unwrap();
+    let client = server.client(HelloworldClient::new);
+
+    assert!(say_hello(&client, "first").await.is_ok());
+    assert!(say_hello(&client, "second").await.is_ok());
+    // Middleware anchored to a disabled layer keeps its position.
+    assert_eq!(inside.count(), 2);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(server.spans().is_empty());
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn rejects_oversized_messages() {
+    let mut config = GrpcServerConfig::default();
+    config.message_size.max_encoding_message_size = Some(16);
+    config.message_size.methods.insert(
+        SAY_HELLO.to_string(),
+        MessageSizeLimit {
+            max_decoding_message_size: Some(16),
+            max_encoding_message_size: None,
+        },
+    );
+    let server = TestServer::start(server(config)).await.unwrap();
+    let client = server.client(HelloworldClient::new);
+
+    assert_eq!(say_hello(&client, "a").await.unwrap(), "Hello a!");
+    // The request fits, but the reply is larger than the global limit.
+    let err = say_hello(&client, "aaaaaaaaaa").await.unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+    // The request is larger than the limit of the method.
+    let err = say_hello(&client, &"a".repeat(32)).await.unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn records_stream_metrics_and_events() {
+    let server = TestServer::start(server(GrpcServerConfig::default()))
+        .await
+        .unwrap();
+    let client = server.client(HelloworldClient::new);
+    assert!(say_hello(&client, "world").await.is_ok());
+
+    // The server span ends once the response stream has ended.
+    let mut spans = server.spans();
+    for _ in 0..100 {
+        if !spans.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        spans = server.spans();
+    }
+    let event = spans
+        .iter()
+        .flat_map(|span| span.events.iter())
+        .find(|event| event.name == "stream.completed")
+        .expect("stream.completed event");
+    let attribute = |key: &str| {
+        event
+            .attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.as_str().into_owned())
+    };
+    assert_eq!(attribute("rpc.messages_received").as_deref(), Some("1"));
+    assert_eq!(attribute("rpc.messages_sent").as_deref(), Some("1"));
+    assert_eq!(attribute("rpc.grpc.status_code").as_deref(), Some("0"));
+
+    let metrics = server.metrics();
+    for series in [
+        format!("micro_stream_messages_received_total{{method=\"{SAY_HELLO}\"}}"),
+        format!("micro_stream_messages_sent_total{{method=\"{SAY_HELLO}\"}}"),
+        format!("micro_stream_duration_seconds_count{{code=\"OK\",method=\"{SAY_HELLO}\"}}"),
+    ] {
+        assert!(metrics.contains(&series), "missing {series} in:\n{metrics}");
+    }
+    server.shutdown().await.unwrap();
+}